@@ -0,0 +1,2 @@
+-- Add up migration script here
+ALTER TABLE `sessions` ADD COLUMN pkce_verifier VARCHAR(255) AFTER csrf_token;
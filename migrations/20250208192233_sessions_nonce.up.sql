@@ -0,0 +1,2 @@
+-- Add up migration script here
+ALTER TABLE `sessions` ADD COLUMN nonce VARCHAR(255) AFTER pkce_verifier;
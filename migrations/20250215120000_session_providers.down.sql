@@ -0,0 +1,6 @@
+-- Add down migration script here
+ALTER TABLE `user_sessions`
+    DROP COLUMN provider,
+    MODIFY refresh_token TEXT NOT NULL;
+
+ALTER TABLE `sessions` DROP COLUMN provider;
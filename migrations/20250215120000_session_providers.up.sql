@@ -0,0 +1,6 @@
+-- Add up migration script here
+ALTER TABLE `sessions` ADD COLUMN provider VARCHAR(32) NOT NULL DEFAULT 'google' AFTER session_id;
+
+ALTER TABLE `user_sessions`
+    ADD COLUMN provider VARCHAR(32) NOT NULL DEFAULT 'google' AFTER user_id,
+    MODIFY refresh_token TEXT NULL;
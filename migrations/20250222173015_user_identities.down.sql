@@ -0,0 +1,10 @@
+-- Add down migration script here
+ALTER TABLE `sessions` DROP COLUMN link_user_id;
+
+ALTER TABLE `users` ADD COLUMN google_id VARCHAR(255) AFTER id;
+
+UPDATE `users` u
+JOIN `user_identities` i ON i.user_id = u.id AND i.provider = 'google'
+SET u.google_id = i.subject;
+
+DROP TABLE IF EXISTS `user_identities`;
@@ -0,0 +1,21 @@
+-- Add up migration script here
+DROP TABLE IF EXISTS `user_identities`;
+
+CREATE TABLE `user_identities` (
+    id INT AUTO_INCREMENT PRIMARY KEY,
+    user_id INT NOT NULL,
+    provider VARCHAR(32) NOT NULL,
+    subject VARCHAR(255) NOT NULL,
+    email VARCHAR(255) NOT NULL,
+    linked_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (provider, subject),
+    UNIQUE (user_id, provider),
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+INSERT INTO `user_identities` (user_id, provider, subject, email)
+SELECT id, 'google', google_id, email FROM `users` WHERE google_id IS NOT NULL;
+
+ALTER TABLE `users` DROP COLUMN google_id;
+
+ALTER TABLE `sessions` ADD COLUMN link_user_id INT AFTER nonce;
@@ -0,0 +1,4 @@
+-- Add down migration script here
+ALTER TABLE `sessions`
+    DROP INDEX sessions_expires_at,
+    DROP INDEX sessions_session_id_unique;
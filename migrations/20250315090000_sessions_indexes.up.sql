@@ -0,0 +1,10 @@
+-- Add up migration script here
+DELETE FROM `sessions` WHERE expires_at <= NOW();
+
+-- Keep the newest row of any session_id issued twice before it had to be unique.
+DELETE older FROM `sessions` older
+JOIN `sessions` newer ON newer.session_id = older.session_id AND newer.id > older.id;
+
+ALTER TABLE `sessions`
+    ADD UNIQUE INDEX sessions_session_id_unique (session_id),
+    ADD INDEX sessions_expires_at (expires_at);
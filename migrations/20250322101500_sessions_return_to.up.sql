@@ -0,0 +1,2 @@
+-- Add up migration script here
+ALTER TABLE `sessions` ADD COLUMN return_to VARCHAR(2048) AFTER link_user_id;
@@ -9,9 +9,9 @@ pub struct Database {
 }
 
 impl Database {
-    pub async fn new(database_url: &str) -> Result<Self, AppError> {
+    pub async fn new(database_url: &str, max_connections: u32) -> Result<Self, AppError> {
         let pool = MySqlPoolOptions::new()
-            .max_connections(10)
+            .max_connections(max_connections)
             .connect(database_url)
             .await
             .context("Failed to connect to the database")?;
@@ -32,7 +32,7 @@ mod tests {
 
     #[tokio::test]
     async fn test_database_new_failure() {
-        let result = Database::new("invalid_url").await;
+        let result = Database::new("invalid_url", 1).await;
         assert!(result.is_err());
     }
 
@@ -1,2 +1,6 @@
+pub mod app_config;
 pub mod database;
+pub mod oauth_provider_config;
 pub mod parameter;
+pub mod secret_file;
+pub mod telemetry;
@@ -0,0 +1,128 @@
+use std::fmt;
+
+use anyhow::Context;
+use oauth2::{AuthUrl, ClientId, ClientSecret, RedirectUrl, RevocationUrl, TokenUrl};
+
+use crate::{config::app_config::{GoogleConfig, REDACTED}, error::app_error::AppError, service::google_token_service::GoogleOAuthClient};
+
+/// Everything needed to talk to an OpenID Connect provider, built once at startup.
+#[derive(Clone)]
+pub struct OAuthProviderConfig {
+    pub client_id: String,
+    pub client_secret: String,
+    pub redirect_url: String,
+    pub auth_url: String,
+    pub token_url: String,
+    pub revocation_url: String,
+    pub token_info_url: String,
+    pub userinfo_url: String,
+    pub jwks_url: String,
+    pub issuer: String,
+    pub scopes: Vec<String>,
+    /// Sent with the authorisation request, e.g. `access_type=offline`.
+    pub extra_auth_params: Vec<(String, String)>,
+}
+
+/// The client secret is masked, see the config's `Debug` impls.
+impl fmt::Debug for OAuthProviderConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OAuthProviderConfig")
+            .field("client_id", &self.client_id)
+            .field("client_secret", &REDACTED)
+            .field("redirect_url", &self.redirect_url)
+            .field("auth_url", &self.auth_url)
+            .field("token_url", &self.token_url)
+            .field("revocation_url", &self.revocation_url)
+            .field("token_info_url", &self.token_info_url)
+            .field("userinfo_url", &self.userinfo_url)
+            .field("jwks_url", &self.jwks_url)
+            .field("issuer", &self.issuer)
+            .field("scopes", &self.scopes)
+            .field("extra_auth_params", &self.extra_auth_params)
+            .finish()
+    }
+}
+
+impl OAuthProviderConfig {
+    pub fn google(config: &GoogleConfig) -> Result<Self, AppError> {
+        let required = |value: &Option<String>, key: &str| value.clone()
+            .ok_or_else(|| AppError::ConfigurationError(format!("google.{} is required", key)));
+
+        Ok(Self {
+            client_id: required(&config.client_id, "client_id")?,
+            client_secret: required(&config.client_secret, "client_secret")?,
+            redirect_url: required(&config.redirect_uri, "redirect_uri")?,
+            auth_url: config.auth_uri.clone(),
+            token_url: config.token_uri.clone(),
+            revocation_url: config.revocation_uri.clone(),
+            token_info_url: config.token_info_uri.clone(),
+            userinfo_url: config.userinfo_uri.clone(),
+            jwks_url: config.jwks_uri.clone(),
+            issuer: config.issuer.clone(),
+            scopes: vec!["openid".to_string(), config.email_scope.clone(), config.profile_scope.clone()],
+            extra_auth_params: parse_auth_params(&config.auth_params)?,
+        })
+    }
+
+    pub fn oauth_client(&self) -> Result<GoogleOAuthClient, AppError> {
+        Ok(GoogleOAuthClient::new(
+                ClientId::new(self.client_id.clone()),
+                Some(ClientSecret::new(self.client_secret.clone())),
+                AuthUrl::new(self.auth_url.clone()).context("failed to create new authorization server URL")?,
+                Some(TokenUrl::new(self.token_url.clone()).context("failed to create new token endpoint URL")?),
+            )
+            .set_redirect_uri(
+                RedirectUrl::new(self.redirect_url.clone()).context("failed to create new redirection URL")?,
+            )
+            .set_revocation_uri(
+                RevocationUrl::new(self.revocation_url.clone()).context("failed to create new revocation URL")?,
+            )
+        )
+    }
+}
+
+fn parse_auth_params(auth_params: &str) -> Result<Vec<(String, String)>, AppError> {
+    auth_params
+        .split_whitespace()
+        .map(|param| {
+            param
+                .split_once('=')
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .ok_or_else(|| AppError::ConfigurationError(format!("Invalid authorisation parameter `{}`, expected key=value", param)))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{assert_error, error::app_error::AppError, test_utils::placeholder_oauth_config};
+
+    use super::parse_auth_params;
+
+    #[test]
+    fn test_debug_output_hides_client_secret() {
+        let printed = format!("{:?}", placeholder_oauth_config());
+
+        assert!(printed.contains("test-client-id"));
+        assert!(!printed.contains("test-client-secret"));
+    }
+
+    #[test]
+    fn test_parse_auth_params() {
+        let params = parse_auth_params("access_type=offline  prompt=consent hd=lift.com").unwrap();
+
+        assert_eq!(params, vec![
+            ("access_type".to_string(), "offline".to_string()),
+            ("prompt".to_string(), "consent".to_string()),
+            ("hd".to_string(), "lift.com".to_string()),
+        ]);
+        assert!(parse_auth_params("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_parse_auth_params_rejects_missing_value() {
+        let result = parse_auth_params("access_type=offline prompt");
+
+        assert_error!(result, &AppError::ConfigurationError(String::new()));
+    }
+}
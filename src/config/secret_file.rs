@@ -0,0 +1,95 @@
+use std::{path::{Path, PathBuf}, time::Duration};
+
+use tokio::{task::JoinHandle, time::MissedTickBehavior};
+
+use crate::service::shutdown_signal::ShutdownSignal;
+
+/// Reads a secret mounted as a file. The trailing newline most tools add is dropped.
+pub fn read_secret_file(path: &Path) -> std::io::Result<String> {
+    let contents = std::fs::read_to_string(path)?;
+    Ok(contents.trim_end_matches(['\r', '\n']).to_string())
+}
+
+/// Polls a secret file and calls `on_change` whenever its contents change. Kubernetes
+/// updates mounted secrets in place, so a rotated secret is picked up without a restart.
+/// The task ends when `shutdown` is triggered.
+pub fn watch_secret_file<F>(path: PathBuf, interval: Duration, shutdown: ShutdownSignal, on_change: F) -> JoinHandle<()>
+where
+    F: Fn(String) + Send + 'static,
+{
+    tokio::spawn(async move {
+        let mut current = read_secret_file(&path).ok();
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        ticker.tick().await;
+
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => {}
+                _ = shutdown.triggered() => return,
+            }
+            match read_secret_file(&path) {
+                Ok(secret) if !secret.is_empty() && current.as_ref() != Some(&secret) => {
+                    tracing::info!("Reloaded secret from {}", path.display());
+                    on_change(secret.clone());
+                    current = Some(secret);
+                }
+                Ok(_) => {}
+                Err(error) => tracing::warn!("Failed to read secret file {}: {}", path.display(), error),
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::sync::mpsc;
+
+    use crate::service::shutdown_signal::ShutdownSignal;
+
+    use super::{read_secret_file, watch_secret_file};
+
+    fn secret_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("oauth-app-{}-{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn test_read_secret_file_drops_trailing_newline() {
+        let path = secret_path("trailing-newline");
+        std::fs::write(&path, "client-secret\n").unwrap();
+
+        assert_eq!(read_secret_file(&path).unwrap(), "client-secret");
+    }
+
+    #[tokio::test]
+    async fn test_watch_secret_file_reports_changes() {
+        let path = secret_path("watched");
+        std::fs::write(&path, "old-secret\n").unwrap();
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let watcher = watch_secret_file(path.clone(), Duration::from_millis(10), ShutdownSignal::default(), move |secret| {
+            sender.send(secret).unwrap();
+        });
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        std::fs::write(&path, "new-secret\n").unwrap();
+        let secret = tokio::time::timeout(Duration::from_secs(5), receiver.recv()).await.unwrap();
+
+        assert_eq!(secret.as_deref(), Some("new-secret"));
+        assert!(receiver.try_recv().is_err());
+        watcher.abort();
+    }
+
+    #[tokio::test]
+    async fn test_watch_secret_file_stops_on_shutdown() {
+        let path = secret_path("shutdown");
+        std::fs::write(&path, "secret").unwrap();
+        let shutdown = ShutdownSignal::default();
+        let watcher = watch_secret_file(path, Duration::from_millis(10), shutdown.clone(), |_| {});
+
+        shutdown.trigger();
+
+        tokio::time::timeout(Duration::from_secs(5), watcher).await.unwrap().unwrap();
+    }
+}
@@ -0,0 +1,108 @@
+use tracing::Span;
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
+
+use crate::{config::app_config::TelemetryConfig, error::app_error::AppError, service::trace_context::TraceContext};
+
+/// Flushes spans that are still waiting to be exported when dropped at the end of `main`.
+pub struct TelemetryGuard {
+    #[cfg(feature = "otlp")]
+    tracer_provider: Option<opentelemetry_sdk::trace::TracerProvider>,
+}
+
+impl Drop for TelemetryGuard {
+    fn drop(&mut self) {
+        #[cfg(feature = "otlp")]
+        if let Some(tracer_provider) = self.tracer_provider.take() {
+            if let Err(error) = tracer_provider.shutdown() {
+                eprintln!("Failed to flush OpenTelemetry spans: {}", error);
+            }
+        }
+    }
+}
+
+/// Installs the global subscriber: `RUST_LOG` filtered log lines and, when an OTLP endpoint
+/// is configured, span export to an OpenTelemetry collector.
+#[cfg_attr(not(feature = "otlp"), allow(unused_variables))]
+pub fn init(config: &TelemetryConfig) -> Result<TelemetryGuard, AppError> {
+    let registry = tracing_subscriber::registry()
+        .with(
+            EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| format!("sqlx=debug,{}=debug", env!("CARGO_CRATE_NAME")).into()),
+        )
+        .with(tracing_subscriber::fmt::layer());
+
+    #[cfg(feature = "otlp")]
+    let tracer_provider = config.otlp_endpoint
+        .as_deref()
+        .map(|endpoint| otlp::tracer_provider(endpoint, &config.service_name))
+        .transpose()?;
+    #[cfg(feature = "otlp")]
+    let registry = registry.with(tracer_provider.as_ref().map(otlp::layer));
+
+    registry.init();
+    Ok(TelemetryGuard {
+        #[cfg(feature = "otlp")]
+        tracer_provider,
+    })
+}
+
+/// Places the request's span in the caller's trace, or a new one, and returns the position
+/// to hand back in the `traceparent` response header.
+#[cfg_attr(not(feature = "otlp"), allow(unused_variables))]
+pub fn attach_trace_context(span: &Span, parent: Option<TraceContext>) -> TraceContext {
+    #[cfg(feature = "otlp")]
+    if let Some(exported) = otlp::attach(span, parent) {
+        return exported;
+    }
+    parent.map(|parent| parent.child()).unwrap_or_else(TraceContext::root)
+}
+
+#[cfg(feature = "otlp")]
+mod otlp {
+    use anyhow::Context as _;
+    use opentelemetry::{trace::{SpanContext, SpanId, TraceContextExt, TraceFlags, TraceId, TraceState, TracerProvider as _}, Context, KeyValue};
+    use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+    use opentelemetry_sdk::{runtime, trace::{Tracer, TracerProvider}, Resource};
+    use tracing::Span;
+    use tracing_opentelemetry::{OpenTelemetryLayer, OpenTelemetrySpanExt};
+    use tracing_subscriber::registry::LookupSpan;
+
+    use crate::{error::app_error::AppError, service::trace_context::TraceContext};
+
+    pub fn tracer_provider(endpoint: &str, service_name: &str) -> Result<TracerProvider, AppError> {
+        let exporter = SpanExporter::builder()
+            .with_tonic()
+            .with_endpoint(endpoint)
+            .build()
+            .context("Failed to create the OTLP span exporter")?;
+        Ok(TracerProvider::builder()
+            .with_batch_exporter(exporter, runtime::Tokio)
+            .with_resource(Resource::new([KeyValue::new("service.name", service_name.to_string())]))
+            .build())
+    }
+
+    pub fn layer<S>(tracer_provider: &TracerProvider) -> OpenTelemetryLayer<S, Tracer>
+    where
+        S: tracing::Subscriber + for<'span> LookupSpan<'span>,
+    {
+        tracing_opentelemetry::layer().with_tracer(tracer_provider.tracer(env!("CARGO_CRATE_NAME")))
+    }
+
+    /// Links the span to the incoming parent and reads back the ids OpenTelemetry assigned,
+    /// so the ids we log and return match the exported trace. `None` when nothing is exported.
+    pub fn attach(span: &Span, parent: Option<TraceContext>) -> Option<TraceContext> {
+        if let Some(parent) = parent {
+            let flags = if parent.sampled { TraceFlags::SAMPLED } else { TraceFlags::default() };
+            let remote = SpanContext::new(TraceId::from_bytes(parent.trace_id), SpanId::from_bytes(parent.span_id), flags, true, TraceState::default());
+            span.set_parent(Context::new().with_remote_span_context(remote));
+        }
+
+        let context = span.context();
+        let span_context = context.span().span_context().clone();
+        span_context.is_valid().then(|| TraceContext {
+            trace_id: span_context.trace_id().to_bytes(),
+            span_id: span_context.span_id().to_bytes(),
+            sampled: span_context.is_sampled(),
+        })
+    }
+}
@@ -2,7 +2,7 @@ use axum::response::{IntoResponse, Response};
 use http::StatusCode;
 use thiserror::Error;
 
-use super::token_error::TokenError;
+use super::{problem_details::ProblemDetails, token_error::TokenError};
 
 #[derive(Debug, Error, Eq, PartialEq)]
 pub enum AppError {
@@ -12,12 +12,21 @@ pub enum AppError {
     #[error("Unauthorized")]
     Unauthorized,
 
+    #[error("Forbidden")]
+    Forbidden,
+
     #[error("Bad request: {0}")]
     BadRequest(String),
 
     #[error("Not found: {0}")]
     NotFound(String),
 
+    #[error("Conflict: {0}")]
+    Conflict(String),
+
+    #[error("Too many requests")]
+    TooManyRequests,
+
     #[error(transparent)]
     TokenError(#[from] TokenError),
 
@@ -28,17 +37,61 @@ pub enum AppError {
     DatabaseError(String),
 }
 
+impl AppError {
+    /// Stable machine readable code returned in the error body. Part of the API contract.
+    pub fn code(&self) -> &'static str {
+        match self {
+            AppError::InternalServerError(_) => "internal_error",
+            AppError::Unauthorized => "unauthorized",
+            AppError::Forbidden => "forbidden",
+            AppError::BadRequest(_) => "bad_request",
+            AppError::NotFound(_) => "not_found",
+            AppError::Conflict(_) => "conflict",
+            AppError::TooManyRequests => "too_many_requests",
+            AppError::TokenError(error) => error.code(),
+            AppError::ConfigurationError(_) => "configuration_error",
+            AppError::DatabaseError(_) => "database_error",
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            AppError::InternalServerError(_) | AppError::ConfigurationError(_) | AppError::DatabaseError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            AppError::Unauthorized => StatusCode::UNAUTHORIZED,
+            AppError::Forbidden => StatusCode::FORBIDDEN,
+            AppError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            AppError::NotFound(_) => StatusCode::NOT_FOUND,
+            AppError::Conflict(_) => StatusCode::CONFLICT,
+            AppError::TooManyRequests => StatusCode::TOO_MANY_REQUESTS,
+            AppError::TokenError(error) => error.status(),
+        }
+    }
+
+    /// What the client is told. Server errors carry internal details, e.g. `sqlx` debug
+    /// output, that are only logged.
+    fn public_detail(&self) -> String {
+        match self {
+            AppError::InternalServerError(_) | AppError::ConfigurationError(_) | AppError::DatabaseError(_) => {
+                "The server could not complete the request".to_string()
+            }
+            AppError::BadRequest(msg) | AppError::NotFound(msg) | AppError::Conflict(msg) => msg.clone(),
+            _ => self.to_string(),
+        }
+    }
+}
+
 impl IntoResponse for AppError {
+    /// Runs inside the request span, so the log line carries the request id that is also
+    /// returned in the body and the `X-Request-Id` header.
     fn into_response(self) -> Response {
-        match self {
-            AppError::InternalServerError(msg) => (StatusCode::INTERNAL_SERVER_ERROR, msg.to_string()).into_response(),
-            AppError::TokenError(error) => error.into_response(),
-            AppError::Unauthorized => (StatusCode::UNAUTHORIZED, self.to_string()).into_response(),
-            AppError::BadRequest(msg) => (StatusCode::BAD_REQUEST, msg.to_string()).into_response(),
-            AppError::NotFound(msg) => (StatusCode::NOT_FOUND, msg.to_string()).into_response(),
-            AppError::ConfigurationError(msg) => (StatusCode::INTERNAL_SERVER_ERROR, msg.clone()).into_response(),
-            AppError::DatabaseError(msg) => (StatusCode::INTERNAL_SERVER_ERROR, msg.clone()).into_response(),
+        if let AppError::TokenError(error) = self {
+            return error.into_response();
         }
+        let status = self.status();
+        if status.is_server_error() {
+            tracing::error!("Request failed: {}", self);
+        }
+        ProblemDetails::new(status, self.code(), self.public_detail()).into_response()
     }
 }
 
@@ -59,3 +112,58 @@ impl From<anyhow::Error> for AppError {
         Self::InternalServerError(err.to_string())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use axum::response::IntoResponse;
+    use http::{header::CONTENT_TYPE, StatusCode};
+
+    use crate::error::{problem_details::PROBLEM_JSON, token_error::TokenError};
+
+    use super::AppError;
+
+    async fn body(error: AppError) -> (StatusCode, String, serde_json::Value) {
+        let response = error.into_response();
+        let status = response.status();
+        let content_type = response.headers()[CONTENT_TYPE].to_str().unwrap().to_string();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, content_type, serde_json::from_slice(&bytes).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_errors_are_problem_json() {
+        let (status, content_type, body) = body(AppError::Conflict("Already linked".to_string())).await;
+
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(content_type, PROBLEM_JSON);
+        assert_eq!(body, serde_json::json!({
+            "type": "about:blank",
+            "title": "Conflict",
+            "status": 409,
+            "detail": "Already linked",
+            "code": "conflict",
+        }));
+    }
+
+    #[tokio::test]
+    async fn test_server_errors_hide_details() {
+        let (status, _, body) = body(AppError::DatabaseError("PoolTimedOut { host: \"db\" }".to_string())).await;
+
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(body["code"], "database_error");
+        assert!(!body["detail"].as_str().unwrap().contains("PoolTimedOut"));
+    }
+
+    #[test]
+    fn test_codes_and_statuses() {
+        for (error, code, status) in [
+            (AppError::Forbidden, "forbidden", StatusCode::FORBIDDEN),
+            (AppError::TooManyRequests, "too_many_requests", StatusCode::TOO_MANY_REQUESTS),
+            (AppError::NotFound(String::new()), "not_found", StatusCode::NOT_FOUND),
+            (AppError::TokenError(TokenError::TokenExpired), "token_expired", StatusCode::UNAUTHORIZED),
+        ] {
+            assert_eq!(error.code(), code);
+            assert_eq!(error.status(), status);
+        }
+    }
+}
@@ -1,2 +1,3 @@
 pub mod app_error;
+pub mod problem_details;
 pub mod token_error;
@@ -2,6 +2,8 @@ use axum::response::{IntoResponse, Response};
 use http::StatusCode;
 use thiserror::Error;
 
+use super::problem_details::ProblemDetails;
+
 
 #[derive(Debug, Error, Eq, PartialEq)]
 pub enum TokenError {
@@ -16,19 +18,54 @@ pub enum TokenError {
 
     #[error("Token error: {0}")]
     GenericTokenError(String),
+
+    #[error("The login session cookie is missing")]
+    LoginStateMissing,
+
+    #[error("The login expired or was already completed, please try again")]
+    LoginStateExpired,
+
+    /// The provider redirected back with an OAuth error, e.g. `access_denied` when the user
+    /// declined consent.
+    #[error("The OAuth provider returned {error}")]
+    ProviderError { error: String, description: Option<String> },
+}
+
+impl TokenError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            TokenError::InvalidToken => "invalid_token",
+            TokenError::MissingToken => "missing_token",
+            TokenError::TokenExpired => "token_expired",
+            TokenError::GenericTokenError(_) => "token_error",
+            TokenError::LoginStateMissing => "login_state_missing",
+            TokenError::LoginStateExpired => "login_expired",
+            TokenError::ProviderError { .. } => "provider_error",
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            TokenError::LoginStateMissing => StatusCode::BAD_REQUEST,
+            _ => StatusCode::UNAUTHORIZED,
+        }
+    }
 }
 
 impl IntoResponse for TokenError {
     fn into_response(self) -> Response {
-        let status: StatusCode = match &self {
-            TokenError::MissingToken => StatusCode::UNAUTHORIZED,
-            TokenError::InvalidToken => StatusCode::UNAUTHORIZED,
-            TokenError::TokenExpired => StatusCode::UNAUTHORIZED,
-            TokenError::GenericTokenError(_) => StatusCode::UNAUTHORIZED,
-        };
-
         tracing::error!("Token error: {:#}", self);
 
-        (status, self.to_string()).into_response()
+        let detail = match &self {
+            TokenError::GenericTokenError(msg) => msg.clone(),
+            // `error` and `error_description` come from the callback URL, so anyone can set
+            // them; they are only logged.
+            TokenError::ProviderError { error, description } => {
+                tracing::warn!("OAuth provider error {}: {}", error, description.as_deref().unwrap_or("no description"));
+                "The OAuth provider could not complete the login".to_string()
+            }
+            _ => self.to_string(),
+        };
+        ProblemDetails::new(self.status(), self.code(), detail).into_response()
     }
 }
@@ -0,0 +1,82 @@
+use std::convert::Infallible;
+
+use axum::extract::{FromRequestParts, OptionalFromRequestParts};
+use http::request::Parts;
+
+use crate::{error::app_error::AppError, state::app_state::UserContext};
+
+/// The user resolved by the auth middleware for the current request.
+///
+/// The middleware stores the `UserContext` in the request extensions, so every request
+/// only ever sees its own user.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AuthUser(pub UserContext);
+
+impl<S> FromRequestParts<S> for AuthUser
+where
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts
+            .extensions
+            .get::<UserContext>()
+            .cloned()
+            .map(AuthUser)
+            .ok_or(AppError::Unauthorized)
+    }
+}
+
+impl<S> OptionalFromRequestParts<S> for AuthUser
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Option<Self>, Self::Rejection> {
+        Ok(parts.extensions.get::<UserContext>().cloned().map(AuthUser))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::extract::{FromRequestParts, OptionalFromRequestParts};
+    use http::Request;
+
+    use crate::{assert_error, error::app_error::AppError, state::app_state::UserContext};
+
+    use super::AuthUser;
+
+    fn test_user() -> UserContext {
+        UserContext { user_id: 1, email: "test@lift.com".to_string(), name: "John".to_string(), roles: Vec::new() }
+    }
+
+    #[tokio::test]
+    async fn test_auth_user_from_extensions() {
+        let (mut parts, _) = Request::builder().body(()).unwrap().into_parts();
+        parts.extensions.insert(test_user());
+
+        let result = <AuthUser as FromRequestParts<()>>::from_request_parts(&mut parts, &()).await;
+
+        assert_eq!(result.unwrap(), AuthUser(test_user()));
+    }
+
+    #[tokio::test]
+    async fn test_auth_user_missing_is_unauthorized() {
+        let (mut parts, _) = Request::builder().body(()).unwrap().into_parts();
+
+        let result = <AuthUser as FromRequestParts<()>>::from_request_parts(&mut parts, &()).await;
+
+        assert_error!(result, &AppError::Unauthorized);
+    }
+
+    #[tokio::test]
+    async fn test_optional_auth_user_missing_is_none() {
+        let (mut parts, _) = Request::builder().body(()).unwrap().into_parts();
+
+        let result = <AuthUser as OptionalFromRequestParts<()>>::from_request_parts(&mut parts, &()).await;
+
+        assert_eq!(result.unwrap(), None);
+    }
+}
@@ -0,0 +1,93 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+
+use crate::{error::app_error::AppError, extractor::auth_user::AuthUser, handler::auth_handler::start_authorisation, AppState};
+
+pub async fn list_identities(
+    AuthUser(user): AuthUser,
+    State(app_state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let identities = app_state.user_service.list_identities(user.user_id).await?;
+    Ok(Json(identities))
+}
+
+/// Starts an OAuth flow with `provider` whose callback links the identity to the current user.
+pub async fn link_identity(
+    AuthUser(user): AuthUser,
+    Path(provider_name): Path<String>,
+    State(app_state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    tracing::debug!("Linking {} identity to user with ID: {}", provider_name, user.user_id);
+    start_authorisation(&app_state, &provider_name, Some(user.user_id), None).await
+}
+
+pub async fn unlink_identity(
+    AuthUser(user): AuthUser,
+    Path(provider_name): Path<String>,
+    State(app_state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    tracing::debug!("Unlinking {} identity from user with ID: {}", provider_name, user.user_id);
+    app_state.user_service.unlink_identity(user.user_id, &provider_name).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum_extra::extract::cookie::Cookie;
+    use axum_test::TestServer;
+    use http::StatusCode;
+    use serde_json::Value;
+    use sqlx::MySqlPool;
+
+    use crate::{repository::session_repository::SessionRepositoryTrait, route::create_router, service::{cookie_policy::USER_SESSION_COOKIE_NAME, oauth_provider::AccessTokenInfo}, state::app_state::AppState, test_utils::setup_app_state};
+
+    /// Signs Tom in with an already validated access token, so no provider is contacted.
+    async fn setup(db: MySqlPool) -> (AppState, TestServer, Cookie<'static>) {
+        let app_state = setup_app_state(db).await;
+        let session_id = app_state.session_service.create_session(1, "google", "tom-access-token", None).await.unwrap();
+        app_state.token_info_cache.insert("google", "tom-access-token", AccessTokenInfo {
+            subject: "110235950686105464135".to_string(),
+            expires_in: Some(3600),
+        }).await;
+        let server = TestServer::new(create_router(app_state.clone()).await).unwrap();
+        (app_state, server, Cookie::new(USER_SESSION_COOKIE_NAME, session_id))
+    }
+
+    #[sqlx::test(fixtures("./../../tests/fixtures/users.sql"))]
+    async fn test_list_identities(db: MySqlPool) {
+        let (_, server, cookie) = setup(db).await;
+
+        let response = server.get("/account/identities").add_cookie(cookie).await;
+
+        response.assert_status_ok();
+        let identities: Value = response.json();
+        assert_eq!(identities[0]["provider"], "google");
+        assert_eq!(identities[0]["subject"], "110235950686105464135");
+    }
+
+    #[sqlx::test(fixtures("./../../tests/fixtures/users.sql"))]
+    async fn test_cannot_unlink_last_identity(db: MySqlPool) {
+        let (_, server, cookie) = setup(db).await;
+
+        let response = server.delete("/account/identities/google").add_cookie(cookie).await;
+
+        response.assert_status(StatusCode::CONFLICT);
+        assert_eq!(response.json::<serde_json::Value>()["code"], "conflict");
+    }
+
+    #[sqlx::test(fixtures("./../../tests/fixtures/users.sql"))]
+    async fn test_link_identity_starts_flow_with_link_intent(db: MySqlPool) {
+        let (app_state, server, cookie) = setup(db).await;
+
+        let response = server.post("/account/identities/google").add_cookie(cookie).await;
+
+        response.assert_status(StatusCode::SEE_OTHER);
+        let session_id = response.cookie("SESSION").value().to_string();
+        let csrf_session = app_state.session_repository.get_csrf_session_by_session_id(&session_id).await.unwrap();
+        assert_eq!(csrf_session.link_user_id, Some(1));
+    }
+}
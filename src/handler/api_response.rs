@@ -0,0 +1,21 @@
+use axum::{response::{IntoResponse, Response}, Json};
+use serde::Serialize;
+
+/// Success body of the `/api/v1` routes, with the payload under `data`. Errors use the
+/// problem+json body every `AppError` produces.
+#[derive(Debug, Serialize)]
+pub struct ApiResponse<T> {
+    pub data: T,
+}
+
+impl<T> ApiResponse<T> {
+    pub fn new(data: T) -> Self {
+        Self { data }
+    }
+}
+
+impl<T: Serialize> IntoResponse for ApiResponse<T> {
+    fn into_response(self) -> Response {
+        Json(self).into_response()
+    }
+}
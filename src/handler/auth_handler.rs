@@ -1,17 +1,14 @@
-use anyhow::Context;
-use async_session::base64;
 use axum::{
-    extract::{Query, State},
-    http::{header::SET_COOKIE, HeaderMap},
-    response::{IntoResponse, Redirect},
+    extract::{Path, Query, State},
+    http::{header::{ACCEPT, AUTHORIZATION}, HeaderMap},
+    response::{Html, IntoResponse, Redirect, Response},
+    Json,
 };
-use axum_extra::{extract::cookie::Cookie, headers, TypedHeader};
-use rand::RngCore;
-use serde::Deserialize;
+use axum_extra::{headers, TypedHeader};
+use oauth2::PkceCodeVerifier;
+use serde::{Deserialize, Serialize};
 
-use crate::{error::{app_error::AppError, token_error::TokenError}, repository::session_repository::SessionRepositoryTrait, service::google_token_service::{GoogleTokenService, TokenServiceTrait}, AppState};
-
-static SESSION_COOKIE_NAME: &str = "SESSION";
+use crate::{error::{app_error::AppError, token_error::TokenError}, extractor::auth_user::AuthUser, repository::{session_repository::{CsrfSession, SessionRepositoryTrait}, user_repository::UserRepositoryTrait}, service::{cookie_policy::{LOGIN_STATE_COOKIE_NAME, USER_SESSION_COOKIE_NAME}, jwt_service::IssuedAccessToken, metrics::{Outcome, FIRST_PARTY}, oauth_provider::OAuthProvider, session_service::generate_session_id}, state::app_state::UserContext, AppState};
 
 #[derive(Debug, Deserialize)]
 #[allow(dead_code)]
@@ -20,152 +17,344 @@ pub struct AuthRequest {
     state: String,
 }
 
-pub async fn google_auth(
+/// The query a provider redirects back with: `code` and `state` on success, `error` (and
+/// optionally `error_description`) when the login failed or the user declined consent.
+#[derive(Debug, Deserialize)]
+pub struct CallbackQuery {
+    code: Option<String>,
+    state: Option<String>,
+    error: Option<String>,
+    error_description: Option<String>,
+}
+
+impl CallbackQuery {
+    fn into_auth_request(self) -> Result<AuthRequest, AppError> {
+        if let Some(error) = self.error {
+            return Err(TokenError::ProviderError { error, description: self.error_description }.into());
+        }
+        match (self.code, self.state) {
+            (Some(code), Some(state)) => Ok(AuthRequest { code, state }),
+            _ => Err(AppError::BadRequest("The callback is missing the code or state parameter".to_string())),
+        }
+    }
+}
+
+/// Where to send the browser afterwards. Only honoured if the `ReturnToPolicy` allows it.
+#[derive(Debug, Default, Deserialize)]
+pub struct ReturnToQuery {
+    pub return_to: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RefreshRequest {
+    refresh_token: String,
+}
+
+/// Credentials handed to API clients: a short-lived access token and a refresh token that
+/// can be exchanged once at `/auth/refresh`.
+#[derive(Debug, Serialize)]
+pub struct ApiTokens {
+    #[serde(flatten)]
+    access_token: IssuedAccessToken,
+    refresh_token: String,
+}
+
+pub async fn provider_auth(
+    Path(provider_name): Path<String>,
+    Query(query): Query<ReturnToQuery>,
     State(app_state): State<AppState>,
-    State(google_token_service): State<GoogleTokenService>,
 ) -> Result<impl IntoResponse, AppError> {
-    let (auth_url, csrf_token) = google_token_service.generate_authorisation_url().await?;
+    let return_to = allowed_return_to(&app_state, query);
+    start_authorisation(&app_state, &provider_name, None, return_to).await
+}
+
+/// Sends the browser to the provider's consent screen. When `link_user_id` is set, the
+/// callback links the provider identity to that user instead of signing in. `return_to`
+/// must already have been checked against the `ReturnToPolicy`.
+pub(crate) async fn start_authorisation(
+    app_state: &AppState,
+    provider_name: &str,
+    link_user_id: Option<u64>,
+    return_to: Option<String>,
+) -> Result<impl IntoResponse, AppError> {
+    let provider = app_state.providers.get(provider_name)?;
+    let authorisation_request = provider.authorisation_request().await?;
 
     let session_id = generate_session_id();
-    app_state.session_repository.add_csrf_token(&session_id, csrf_token.secret()).await?;
+    app_state.session_repository.add_csrf_token(&session_id, &CsrfSession {
+        provider: provider.name().to_string(),
+        csrf_token: authorisation_request.csrf_token.secret().clone(),
+        pkce_verifier: Some(authorisation_request.pkce_verifier.secret().clone()),
+        nonce: authorisation_request.nonce.clone(),
+        link_user_id,
+        return_to,
+    }).await?;
+    app_state.metrics.login_started(provider.name());
 
-    let cookies = [format!(
-        "{SESSION_COOKIE_NAME}={session_id}; SameSite=Lax; HttpOnly; Secure; Path=/"
-    )];
     let mut headers = HeaderMap::new();
-    for cookie in cookies {
-        headers.append(
-            SET_COOKIE,
-            cookie.parse().context("Failed to parse header value")?,
-        );
-    }
+    app_state.cookie_policy.append(&mut headers, app_state.cookie_policy.login_state(session_id))?;
+
+    Ok((headers, Redirect::to(authorisation_request.url.as_ref())))
+}
 
-    Ok((headers, Redirect::to(auth_url.as_ref())))
+/// The requested `return_to`, or `None` when it is absent or not allowed.
+fn allowed_return_to(app_state: &AppState, query: ReturnToQuery) -> Option<String> {
+    let return_to = query.return_to?;
+    let allowed = app_state.return_to_policy.validate(&return_to);
+    if allowed.is_none() {
+        tracing::warn!(target: "security", return_to = %return_to, "Ignoring a return_to that is not allowed");
+    }
+    allowed
 }
 
+/// Completes the login. Browsers are redirected home with a session cookie; clients that
+/// ask for JSON also receive first-party API tokens. A login that expired or was declined
+/// at the provider shows browsers a page offering to start again.
 pub async fn auth_callback(
-    Query(query): Query<AuthRequest>,
-    TypedHeader(cookies): TypedHeader<headers::Cookie>,
+    Path(provider_name): Path<String>,
+    Query(query): Query<CallbackQuery>,
+    cookies: Option<TypedHeader<headers::Cookie>>,
+    request_headers: HeaderMap,
     State(app_state): State<AppState>,
-    State(google_token_service): State<GoogleTokenService>,
-) -> Result<impl IntoResponse, AppError> {
-    tracing::debug!("Handling google auth callback");
-    validate_csrf_token(&app_state, &query, &cookies).await?;
+) -> Result<Response, AppError> {
+    tracing::debug!("Handling {} auth callback", provider_name);
+    let provider = app_state.providers.get(&provider_name)?;
+    let cookies = cookies.map(|TypedHeader(cookies)| cookies);
+    let response = complete_callback(&app_state, provider.as_ref(), query, cookies.as_ref(), &request_headers).await;
+    app_state.metrics.login_finished(provider.name(), Outcome::of(&response));
+
+    match response {
+        Err(AppError::TokenError(error)) if is_failed_login(&error) && !accepts_json(&request_headers) => {
+            tracing::info!("{} login failed: {}", provider.name(), error);
+            Ok(login_failed_page(provider.name(), &error))
+        }
+        response => response,
+    }
+}
 
-    let (access_token, refresh_token) = google_token_service.exchange_authorisation_code(query.code.clone()).await?;
+async fn complete_callback(
+    app_state: &AppState,
+    provider: &dyn OAuthProvider,
+    query: CallbackQuery,
+    cookies: Option<&headers::Cookie>,
+    request_headers: &HeaderMap,
+) -> Result<Response, AppError> {
+    let query = query.into_auth_request()?;
+    let cookies = cookies.ok_or(TokenError::LoginStateMissing)?;
+    let authorisation_state = validate_csrf_token(app_state, provider.name(), &query, cookies).await?;
+
+    let login = provider.complete_login(query.code.clone(), authorisation_state.pkce_verifier, authorisation_state.nonce.as_deref()).await?;
+
+    if let Some(link_user_id) = authorisation_state.link_user_id {
+        app_state.user_service.link_identity(link_user_id, &login.profile).await?;
+        return Ok(Redirect::to("/account/identities").into_response());
+    }
 
-    let access_token = access_token.secret().to_string();
+    let user_context = app_state.user_service.find_or_insert_user(&login.profile).await?;
+    let user_session_id = app_state.session_service.create_session(
+        user_context.user_id,
+        provider.name(),
+        &login.tokens.access_token,
+        login.tokens.refresh_token.as_deref(),
+    ).await?;
 
-    let refresh_token = refresh_token.secret().to_string();
+    // The login state has been consumed, so its cookie goes with it.
+    let cookie_policy = &app_state.cookie_policy;
+    let mut headers = HeaderMap::new();
+    cookie_policy.append(&mut headers, cookie_policy.user_session(user_session_id))?;
+    cookie_policy.append(&mut headers, cookie_policy.removal(LOGIN_STATE_COOKIE_NAME))?;
 
-    let user_data = google_token_service.get_user_info(&access_token).await?;
+    if accepts_json(request_headers) {
+        return Ok((headers, Json(issue_api_tokens(app_state, &user_context).await?)).into_response());
+    }
 
-    let user_context = app_state.user_service.find_or_insert_user(&user_data).await?;
-    app_state.set_user_context(user_context).await;
+    let return_to = authorisation_state.return_to.as_deref().unwrap_or("/");
+    Ok((headers, Redirect::to(return_to)).into_response())
+}
 
-    let cookies = [
-        format!("access_token={access_token}; SameSite=Lax; HttpOnly; Secure; Path=/"),
-        format!("refresh_token={refresh_token}; SameSite=Lax; HttpOnly; Secure; Path=/"),
-    ];
-    let mut headers = HeaderMap::new();
-    for cookie in cookies {
-        headers.append(
-            SET_COOKIE,
-            cookie.parse().context("Failed to parse header value")?,
-        );
-    }
+/// Failures the user can recover from by simply logging in again.
+fn is_failed_login(error: &TokenError) -> bool {
+    matches!(error, TokenError::LoginStateMissing | TokenError::LoginStateExpired | TokenError::ProviderError { .. })
+}
+
+/// The provider's `error_description` is not shown, as it is attacker controllable text.
+fn login_failed_page(provider: &str, error: &TokenError) -> Response {
+    let message = match error {
+        TokenError::ProviderError { error, .. } if error == "access_denied" => "The login was cancelled.",
+        TokenError::ProviderError { .. } => "The login provider could not sign you in.",
+        _ => "Your login expired. Please try again.",
+    };
+    let page = format!(
+        r#"<!DOCTYPE html>
+<html>
+<head><meta charset="utf-8"><meta http-equiv="refresh" content="10; url=/"><title>Login failed</title></head>
+<body>
+<p>{message}</p>
+<p><a href="/auth/{provider}">Try again</a> or <a href="/">go back home</a>.</p>
+</body>
+</html>
+"#
+    );
+    (error.status(), Html(page)).into_response()
+}
 
-    Ok((headers, Redirect::to("/")))
+fn accepts_json(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .any(|value| value.contains("application/json"))
 }
 
+/// The values generated alongside the CSRF token when the login was started.
+#[derive(Debug)]
+struct AuthorisationState {
+    pkce_verifier: PkceCodeVerifier,
+    nonce: Option<String>,
+    link_user_id: Option<u64>,
+    return_to: Option<String>,
+}
+
+/// Checks the returned state against the stored CSRF token and hands back the PKCE verifier
+/// and, for providers that use one, the id token nonce generated alongside it. The login must have been started
+/// with the same provider that is completing it. The stored token is consumed even when the
+/// check fails, so every state is single-use.
 async fn validate_csrf_token(
     app_state: &AppState,
+    provider: &str,
     auth_request: &AuthRequest,
     cookies: &headers::Cookie,
-) -> Result<(), AppError> {
-    tracing::debug!("Validating CSRF token for google auth callback");
+) -> Result<AuthorisationState, AppError> {
+    tracing::debug!("Validating CSRF token for {} auth callback", provider);
     let session_id = cookies
-        .get(SESSION_COOKIE_NAME)
-        .context("Unexpected error getting cookie name")?
-        .to_string();
+        .get(&app_state.cookie_policy.name(LOGIN_STATE_COOKIE_NAME))
+        .ok_or(TokenError::LoginStateMissing)?;
 
-    let stored_csrf_token = app_state.session_repository.get_csrf_token_by_session_id(&session_id).await?;
-    app_state.session_repository.expire_session(&session_id).await?;
+    let csrf_session = app_state.session_repository.consume_csrf_token(session_id).await?
+        .ok_or(TokenError::LoginStateExpired)?;
 
-    if stored_csrf_token != auth_request.state {
+    if csrf_session.csrf_token != auth_request.state {
+        app_state.metrics.csrf_mismatch();
         return Err(TokenError::GenericTokenError("CSRF token mismatch".to_string()).into());
     }
+    if csrf_session.provider != provider {
+        return Err(TokenError::GenericTokenError("OAuth provider mismatch".to_string()).into());
+    }
 
-    Ok(())
+    let pkce_verifier = csrf_session.pkce_verifier
+        .ok_or_else(|| TokenError::GenericTokenError("Missing PKCE verifier".to_string()))?;
+    Ok(AuthorisationState {
+        pkce_verifier: PkceCodeVerifier::new(pkce_verifier),
+        nonce: csrf_session.nonce,
+        link_user_id: csrf_session.link_user_id,
+        return_to: csrf_session.return_to,
+    })
 }
 
-fn generate_session_id() -> String {
-    let mut key = vec![0u8; 64];
-    rand::thread_rng().fill_bytes(&mut key);
-    base64::encode(key)
+/// Rotates a first-party refresh token and issues a new access token alongside it.
+pub async fn refresh(
+    State(app_state): State<AppState>,
+    Json(request): Json<RefreshRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    let rotated = app_state.refresh_token_service.rotate(&request.refresh_token).await;
+    app_state.metrics.token_refreshed(FIRST_PARTY, Outcome::of(&rotated));
+    let rotated = rotated?;
+    let user_context = app_state.user_repository
+        .find_user_by_id(rotated.user_id)
+        .await?
+        .ok_or(TokenError::InvalidToken)?;
+
+    Ok(Json(ApiTokens {
+        access_token: app_state.jwt_service.issue_access_token(&user_context)?,
+        refresh_token: rotated.refresh_token,
+    }))
 }
 
+/// Exchanges the browser's session for first-party API tokens, for clients that logged in
+/// through the usual redirect flow. Only the session cookie is accepted, so an access token
+/// cannot be traded for a refresh token that outlives it.
+pub async fn session_token(
+    AuthUser(user): AuthUser,
+    request_headers: HeaderMap,
+    State(app_state): State<AppState>,
+) -> Result<Json<ApiTokens>, AppError> {
+    if request_headers.contains_key(AUTHORIZATION) {
+        return Err(AppError::Forbidden);
+    }
+    Ok(Json(issue_api_tokens(&app_state, &user).await?))
+}
+
+async fn issue_api_tokens(app_state: &AppState, user_context: &UserContext) -> Result<ApiTokens, AppError> {
+    Ok(ApiTokens {
+        access_token: app_state.jwt_service.issue_access_token(user_context)?,
+        refresh_token: app_state.refresh_token_service.issue(user_context.user_id).await?,
+    })
+}
+
+/// Publishes the public key that first-party access tokens are signed with.
+pub async fn jwks(State(app_state): State<AppState>) -> impl IntoResponse {
+    Json(app_state.jwt_service.jwks())
+}
+
+/// Bearer clients may send no cookies at all. The session cookie is removed even when ending
+/// the session behind it fails, so the browser is never left holding a dead cookie.
 pub async fn logout(
+    AuthUser(user): AuthUser,
+    cookies: Option<TypedHeader<headers::Cookie>>,
+    Query(query): Query<ReturnToQuery>,
     State(app_state): State<AppState>,
-    TypedHeader(cookies): TypedHeader<headers::Cookie>,
-    State(google_token_service): State<GoogleTokenService>,
 ) -> Result<impl IntoResponse, AppError> {
-    if let Some(user_id) = app_state.get_user_id().await {
-        tracing::debug!("Logging out user with ID: {}", user_id);
-    }
-    // TODO: Revocation of access and refresh token not necessary as revoking a refresh 
-    // token in Google OAUTH 2.0 also revokes the associated access token and vice versa.
-    // See: https://cloud.google.com/apigee/docs/api-platform/security/oauth/validating-and-invalidating-access-tokens
-    if let Some(refresh_token) = cookies.get("refresh_token") {
-        google_token_service.revoke_token(refresh_token.to_string()).await?;
+    tracing::debug!("Logging out user with ID: {}", user.user_id);
+
+    let user_session_name = app_state.cookie_policy.name(USER_SESSION_COOKIE_NAME);
+    let user_session_id = cookies.as_ref().and_then(|TypedHeader(cookies)| cookies.get(&user_session_name));
+    if let Some(user_session_id) = user_session_id {
+        if let Err(error) = end_user_session(&app_state, user_session_id).await {
+            tracing::warn!("Failed to end the user session on logout: {}", error);
+        }
     }
 
-    app_state.clear_user_context().await;
+    let mut headers = HeaderMap::new();
+    app_state.cookie_policy.append(&mut headers, app_state.cookie_policy.removal(USER_SESSION_COOKIE_NAME))?;
 
-    // TODO - refactor
-    let empty_access_token = Cookie::build(("access_token", ""))
-        .path("/")
-        .http_only(true)
-        .build();
-    let empty_refresh_token = Cookie::build(("refresh_token", ""))
-        .path("/")
-        .http_only(true)
-        .build();
+    let return_to = allowed_return_to(&app_state, query).unwrap_or_else(|| "/".to_string());
+    Ok((headers, Redirect::to(&return_to)))
+}
 
-    let mut headers = HeaderMap::new();
-    headers.append(
-        SET_COOKIE,
-        empty_access_token.to_string().parse().unwrap(),
-    );
-    headers.append(
-        SET_COOKIE,
-        empty_refresh_token.to_string().parse().unwrap(),
-    );
+/// Deletes the user session and revokes its provider token. Revocations are counted by
+/// outcome in the metrics.
+async fn end_user_session(app_state: &AppState, user_session_id: &str) -> Result<(), AppError> {
+    let user_session = app_state.session_service.get_session(user_session_id).await?;
+    app_state.session_service.destroy_session(user_session_id).await?;
 
-    Ok((headers, Redirect::to("/")))
+    // TODO: Revocation of access and refresh token not necessary as revoking a refresh 
+    // token in Google OAUTH 2.0 also revokes the associated access token and vice versa.
+    // See: https://cloud.google.com/apigee/docs/api-platform/security/oauth/validating-and-invalidating-access-tokens
+    if let Some(user_session) = user_session {
+        let provider = app_state.providers.get(&user_session.provider)?;
+        app_state.token_info_cache.invalidate(provider.name(), &user_session.access_token).await;
+        let token = user_session.refresh_token.unwrap_or(user_session.access_token);
+        let revoked = provider.revoke(&token).await;
+        app_state.metrics.token_revoked(provider.name(), Outcome::of(&revoked));
+        revoked?;
+    }
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
 
     use axum_extra::headers::{Cookie, HeaderMapExt};
-    use chrono::Utc;
+    use axum_test::{TestResponse, TestServer};
     use http::HeaderMap;
-    use oauth2::{basic::BasicClient, AuthUrl, ClientId, ClientSecret, TokenUrl};
+    use serde_json::{json, Value};
     use sqlx::MySqlPool;
 
-    use crate::{assert_error, config::database::Database, error::{app_error::AppError, token_error::TokenError}, handler::auth_handler::validate_csrf_token, repository::session_repository::{SessionRepository, SessionRepositoryTrait}, state::app_state::AppState};
+    use crate::{assert_error, config::app_config::CookieConfig, error::{app_error::AppError, token_error::TokenError}, handler::auth_handler::validate_csrf_token, repository::session_repository::{SessionRepository, SessionRepositoryTrait}, route::create_router, service::cookie_policy::{CookiePolicy, LOGIN_STATE_COOKIE_NAME, USER_SESSION_COOKIE_NAME}, state::app_state::AppState, test_utils::{mock_oauth_provider::{MockEndpoint, MockOAuthProvider, MockUser}, setup_app_state, setup_app_state_with_oauth_config, span_collector::SpanCollector}};
 
 
     async fn setup(db: MySqlPool) -> (AppState, SessionRepository) {
-        let db_conn = Database { pool: db };
-        let placeholder_client = BasicClient::new(
-            ClientId::new("test-client-id".to_string()),
-            Some(ClientSecret::new("test-client-secret".to_string())),
-            AuthUrl::new("https://test.auth.url".to_string()).unwrap(),
-            Some(TokenUrl::new("https://test.token.url".to_string()).unwrap())
-        );
-        let app_state = AppState::new(db_conn, placeholder_client).await.unwrap();
+        let app_state = setup_app_state(db).await;
         let session_repository = SessionRepository::new(&app_state.database);
         (app_state, session_repository)
     }
@@ -191,10 +380,14 @@ mod tests {
         };        
 
         let cookies = build_cookies("SESSION", "test_session_id");
-        let result = validate_csrf_token(&app_state, &auth_request, &cookies).await;
+        let result = validate_csrf_token(&app_state, "google", &auth_request, &cookies).await;
+
+        let authorisation_state = result.as_ref().unwrap();
+        assert_eq!(authorisation_state.pkce_verifier.secret(), "test_pkce_verifier");
+        assert_eq!(authorisation_state.nonce.as_deref(), Some("test_nonce"));
 
         let session = sqlx::query!(
-            r#"SELECT expires_at FROM sessions WHERE session_id = ?"#,
+            r#"SELECT used_at FROM sessions WHERE session_id = ?"#,
             "test_session_id"
         )
         .fetch_one(app_state.database.get_pool())
@@ -202,7 +395,10 @@ mod tests {
         .expect("Failed to fetch session");
 
         assert!(result.is_ok());
-        assert!(session.expires_at.unwrap() < Utc::now());
+        assert!(session.used_at.is_some());
+
+        let replayed = validate_csrf_token(&app_state, "google", &auth_request, &cookies).await;
+        assert_eq!(replayed.unwrap_err(), AppError::TokenError(TokenError::LoginStateExpired));
     }
 
     #[sqlx::test(fixtures("./../../tests/fixtures/session.sql"))]
@@ -215,9 +411,9 @@ mod tests {
         };
 
         let cookies = build_cookies("SESSION", "expired_session_id");
-        let response = validate_csrf_token(&app_state, &auth_request, &cookies).await;
+        let response = validate_csrf_token(&app_state, "google", &auth_request, &cookies).await;
 
-        assert_error!(response, &AppError::DatabaseError(String::new()));
+        assert_eq!(response.unwrap_err(), AppError::TokenError(TokenError::LoginStateExpired));
     }
 
     #[sqlx::test]
@@ -230,9 +426,9 @@ mod tests {
         };
 
         let cookies = build_cookies("NoSessionIdCookie", "123");
-        let response = validate_csrf_token(&app_state, &auth_request, &cookies).await;
+        let response = validate_csrf_token(&app_state, "google", &auth_request, &cookies).await;
 
-        assert_error!(response, &AppError::InternalServerError(String::new()));
+        assert_eq!(response.unwrap_err(), AppError::TokenError(TokenError::LoginStateMissing));
     }
 
     #[sqlx::test(fixtures("./../../tests/fixtures/session.sql"))]
@@ -245,10 +441,385 @@ mod tests {
         };
 
         let cookies = build_cookies("SESSION", "test_session_id");
-        let response = validate_csrf_token(&app_state, &auth_request, &cookies).await;
+        let response = validate_csrf_token(&app_state, "google", &auth_request, &cookies).await;
 
         assert_error!(response, &AppError::TokenError(
             TokenError::GenericTokenError(String::new())
         ));
     }
+
+    #[sqlx::test(fixtures("./../../tests/fixtures/session.sql"))]
+    async fn test_validate_csrf_token_provider_mismatch(db: MySqlPool) {
+        let (app_state, _) = setup(db).await;
+
+        let auth_request = super::AuthRequest {
+            code: "test_code".to_string(),
+            state: "test_csrf_token".to_string(),
+        };
+
+        let cookies = build_cookies("SESSION", "test_session_id");
+        let response = validate_csrf_token(&app_state, "github", &auth_request, &cookies).await;
+
+        assert_error!(response, &AppError::TokenError(
+            TokenError::GenericTokenError(String::new())
+        ));
+    }
+
+    #[sqlx::test(fixtures("./../../tests/fixtures/session.sql"))]
+    async fn test_validate_csrf_token_missing_pkce_verifier(db: MySqlPool) {
+        let (app_state, _) = setup(db).await;
+
+        let auth_request = super::AuthRequest {
+            code: "test_code".to_string(),
+            state: "test_csrf_token".to_string(),
+        };
+
+        let cookies = build_cookies("SESSION", "no_verifier_session_id");
+        let response = validate_csrf_token(&app_state, "google", &auth_request, &cookies).await;
+
+        assert_error!(response, &AppError::TokenError(
+            TokenError::GenericTokenError(String::new())
+        ));
+    }
+
+    #[sqlx::test(fixtures("./../../tests/fixtures/users.sql"))]
+    async fn test_refresh_endpoint_rotates_tokens(db: MySqlPool) {
+        let (app_state, _) = setup(db).await;
+        let refresh_token = app_state.refresh_token_service.issue(2).await.unwrap();
+        let server = TestServer::new(create_router(app_state.clone()).await).unwrap();
+
+        let response = server.post("/auth/refresh").json(&json!({ "refresh_token": refresh_token })).await;
+
+        response.assert_status_ok();
+        let tokens: Value = response.json();
+        let claims = app_state.jwt_service.verify_access_token(tokens["access_token"].as_str().unwrap()).unwrap();
+        assert_eq!(claims.sub, "2");
+        assert_eq!(tokens["token_type"], "Bearer");
+        assert_ne!(tokens["refresh_token"], refresh_token.as_str());
+
+        let reuse = server.post("/auth/refresh").json(&json!({ "refresh_token": refresh_token })).await;
+        reuse.assert_status(http::StatusCode::UNAUTHORIZED);
+    }
+
+    #[sqlx::test]
+    async fn test_jwks_endpoint(db: MySqlPool) {
+        let (app_state, _) = setup(db).await;
+        let server = TestServer::new(create_router(app_state).await).unwrap();
+
+        let response = server.get("/.well-known/jwks.json").await;
+
+        response.assert_status_ok();
+        let jwks: Value = response.json();
+        assert_eq!(jwks["keys"][0]["kid"], "test-key");
+        assert_eq!(jwks["keys"][0]["alg"], "RS256");
+        assert!(jwks["keys"][0]["n"].is_string());
+    }
+
+    async fn setup_with_mock_provider(db: MySqlPool) -> (TestServer, MockOAuthProvider) {
+        let mock_provider = MockOAuthProvider::spawn().await;
+        let app_state = setup_app_state_with_oauth_config(db, mock_provider.oauth_config()).await;
+        (TestServer::new(create_router(app_state).await).unwrap(), mock_provider)
+    }
+
+    /// Starts the Google login, consents at the mock provider and returns the response to
+    /// the app's callback.
+    async fn login(server: &TestServer, mock_provider: &MockOAuthProvider, accept: &str) -> TestResponse {
+        let start = server.get("/auth/google").await;
+        start.assert_status(http::StatusCode::SEE_OTHER);
+        let callback = mock_provider.authorize(start.header("location").to_str().unwrap()).await;
+
+        server.get(&callback)
+            .add_cookie(start.cookie("SESSION"))
+            .add_header(http::header::ACCEPT, http::HeaderValue::from_str(accept).unwrap())
+            .await
+    }
+
+    #[sqlx::test(fixtures("./../../tests/fixtures/users.sql"))]
+    async fn test_login_protected_refresh_logout_flow(db: MySqlPool) {
+        let (server, mock_provider) = setup_with_mock_provider(db).await;
+
+        let callback = login(&server, &mock_provider, "text/html").await;
+        callback.assert_status(http::StatusCode::SEE_OTHER);
+        callback.assert_header("location", "/");
+        let user_session = callback.cookie(USER_SESSION_COOKIE_NAME);
+
+        // The first request finds the access token expired and refreshes it, the second
+        // one is served with the refreshed token.
+        mock_provider.expire_access_tokens();
+        for _ in 0..2 {
+            server.get("/protected")
+                .add_cookie(user_session.clone())
+                .await
+                .assert_text("Welcome to the protected area, Tom!");
+        }
+        assert_eq!(mock_provider.request_count(MockEndpoint::Token), 2);
+
+        let logout = server.get("/logout").add_cookie(user_session.clone()).await;
+        logout.assert_status(http::StatusCode::SEE_OTHER);
+        assert_eq!(logout.cookie(USER_SESSION_COOKIE_NAME).value(), "");
+        assert_eq!(mock_provider.revoked_tokens().len(), 1);
+        assert!(mock_provider.revoked_tokens()[0].starts_with("mock-refresh-"));
+
+        let after_logout = server.get("/protected").add_cookie(user_session).await;
+        after_logout.assert_status(http::StatusCode::SEE_OTHER);
+    }
+
+    #[sqlx::test(fixtures("./../../tests/fixtures/users.sql"))]
+    async fn test_logout_when_revocation_fails(db: MySqlPool) {
+        let (server, mock_provider) = setup_with_mock_provider(db).await;
+        let user_session = login(&server, &mock_provider, "text/html").await.cookie(USER_SESSION_COOKIE_NAME);
+        mock_provider.respond_once(MockEndpoint::Revoke, http::StatusCode::SERVICE_UNAVAILABLE, json!({ "error": "temporarily_unavailable" }));
+
+        let logout = server.get("/logout").add_cookie(user_session.clone()).await;
+
+        logout.assert_status(http::StatusCode::SEE_OTHER);
+        assert_eq!(logout.cookie(USER_SESSION_COOKIE_NAME).value(), "");
+        server.get("/protected").add_cookie(user_session).await.assert_status(http::StatusCode::SEE_OTHER);
+    }
+
+    #[sqlx::test(fixtures("./../../tests/fixtures/users.sql"))]
+    async fn test_logout_with_bearer_token_and_no_cookies(db: MySqlPool) {
+        let (server, mock_provider) = setup_with_mock_provider(db).await;
+        let tokens: Value = login(&server, &mock_provider, "application/json").await.json();
+
+        let logout = server.get("/logout").authorization_bearer(tokens["access_token"].as_str().unwrap()).await;
+
+        logout.assert_status(http::StatusCode::SEE_OTHER);
+        assert_eq!(logout.cookie(USER_SESSION_COOKIE_NAME).value(), "");
+    }
+
+    #[sqlx::test(fixtures("./../../tests/fixtures/users.sql"))]
+    async fn test_api_client_login_and_refresh(db: MySqlPool) {
+        let (server, mock_provider) = setup_with_mock_provider(db).await;
+        mock_provider.set_user(MockUser {
+            sub: "897239842378324289342".to_string(),
+            email: "gt@lift.com".to_string(),
+            email_verified: true,
+            given_name: "George".to_string(),
+            family_name: "Thomas".to_string(),
+        });
+
+        let callback = login(&server, &mock_provider, "application/json").await;
+        callback.assert_status_ok();
+        let tokens: Value = callback.json();
+
+        server.get("/protected")
+            .authorization_bearer(tokens["access_token"].as_str().unwrap())
+            .await
+            .assert_text("Welcome to the protected area, George!");
+
+        let refreshed = server.post("/auth/refresh").json(&json!({ "refresh_token": tokens["refresh_token"] })).await;
+        refreshed.assert_status_ok();
+        let refreshed: Value = refreshed.json();
+        server.get("/protected")
+            .authorization_bearer(refreshed["access_token"].as_str().unwrap())
+            .await
+            .assert_text("Welcome to the protected area, George!");
+    }
+
+    #[sqlx::test(fixtures("./../../tests/fixtures/users.sql"))]
+    async fn test_browser_session_is_exchanged_for_api_tokens(db: MySqlPool) {
+        let (server, mock_provider) = setup_with_mock_provider(db).await;
+        let user_session = login(&server, &mock_provider, "text/html").await.cookie(USER_SESSION_COOKIE_NAME);
+
+        let exchanged = server.post("/auth/token").add_cookie(user_session).await;
+
+        exchanged.assert_status_ok();
+        let tokens: Value = exchanged.json();
+        assert!(tokens["refresh_token"].is_string());
+        let access_token = tokens["access_token"].as_str().unwrap();
+        let me = server.get("/api/v1/me").authorization_bearer(access_token).await;
+        assert_eq!(me.json::<Value>()["data"]["email"], "TestEmail@lift.com");
+
+        server.post("/auth/token").authorization_bearer(access_token).await.assert_status(http::StatusCode::FORBIDDEN);
+        server.post("/auth/token").await.assert_status(http::StatusCode::UNAUTHORIZED);
+    }
+
+    #[sqlx::test(fixtures("./../../tests/fixtures/users.sql"))]
+    async fn test_login_spans_are_nested_under_the_request(db: MySqlPool) {
+        let (server, mock_provider) = setup_with_mock_provider(db).await;
+        let collector = SpanCollector::install();
+
+        login(&server, &mock_provider, "text/html").await.assert_status(http::StatusCode::SEE_OTHER);
+
+        let spans = collector.spans();
+        for name in ["exchange_authorisation_code", "consume_csrf_token", "find_user_by_identity", "add_user_session"] {
+            let span = spans.iter().find(|span| span.name == name).unwrap_or_else(|| panic!("no {} span", name));
+            assert_eq!(span.parent.as_deref(), Some("request"), "{} is not nested under the request", name);
+        }
+        assert_eq!(collector.span("exchange_authorisation_code").unwrap().field("provider"), Some("google"));
+        let request_ids: Vec<_> = spans.iter().filter(|span| span.name == "request").filter_map(|span| span.field("request_id")).collect();
+        assert_eq!(request_ids.len(), 2);
+        assert_ne!(request_ids[0], request_ids[1]);
+    }
+
+    #[sqlx::test(fixtures("./../../tests/fixtures/users.sql"))]
+    async fn test_failed_code_exchange_does_not_log_in(db: MySqlPool) {
+        let (server, mock_provider) = setup_with_mock_provider(db).await;
+        mock_provider.respond_once(MockEndpoint::Token, http::StatusCode::BAD_REQUEST, json!({ "error": "invalid_grant" }));
+
+        let callback = login(&server, &mock_provider, "text/html").await;
+
+        callback.assert_status(http::StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(callback.maybe_cookie(USER_SESSION_COOKIE_NAME).is_none());
+    }
+
+    #[sqlx::test(fixtures("./../../tests/fixtures/users.sql"))]
+    async fn test_unverified_email_does_not_log_in(db: MySqlPool) {
+        let (server, mock_provider) = setup_with_mock_provider(db).await;
+        mock_provider.set_user(MockUser { sub: "583231".to_string(), email_verified: false, ..MockUser::tom() });
+
+        let callback = login(&server, &mock_provider, "text/html").await;
+
+        callback.assert_status(http::StatusCode::BAD_REQUEST);
+        assert_eq!(mock_provider.request_count(MockEndpoint::Token), 1);
+    }
+
+    #[sqlx::test(fixtures("./../../tests/fixtures/users.sql"))]
+    async fn test_parallel_callbacks_with_the_same_state_log_in_once(db: MySqlPool) {
+        let (server, mock_provider) = setup_with_mock_provider(db).await;
+        let start = server.get("/auth/google").await;
+        let callback = mock_provider.authorize(start.header("location").to_str().unwrap()).await;
+
+        let (first, second) = tokio::join!(
+            server.get(&callback).add_cookie(start.cookie("SESSION")),
+            server.get(&callback).add_cookie(start.cookie("SESSION")),
+        );
+
+        let logged_in = [&first, &second].into_iter()
+            .filter(|response| response.maybe_cookie(USER_SESSION_COOKIE_NAME).is_some())
+            .count();
+        assert_eq!(logged_in, 1);
+        assert_eq!(mock_provider.request_count(MockEndpoint::Token), 1);
+    }
+
+    #[sqlx::test(fixtures("./../../tests/fixtures/users.sql"))]
+    async fn test_reused_callback_shows_login_expired_page(db: MySqlPool) {
+        let (server, mock_provider) = setup_with_mock_provider(db).await;
+        let start = server.get("/auth/google").await;
+        let callback = mock_provider.authorize(start.header("location").to_str().unwrap()).await;
+        server.get(&callback).add_cookie(start.cookie("SESSION")).await.assert_status(http::StatusCode::SEE_OTHER);
+
+        let page = server.get(&callback).add_cookie(start.cookie("SESSION")).await;
+        page.assert_status(http::StatusCode::UNAUTHORIZED);
+        page.assert_text_contains("Your login expired");
+        page.assert_text_contains(r#"href="/auth/google""#);
+
+        let api = server.get(&callback)
+            .add_cookie(start.cookie("SESSION"))
+            .add_header(http::header::ACCEPT, http::HeaderValue::from_static("application/json"))
+            .await;
+        api.assert_status(http::StatusCode::UNAUTHORIZED);
+        assert_eq!(api.json::<Value>()["code"], "login_expired");
+    }
+
+    #[sqlx::test(fixtures("./../../tests/fixtures/users.sql"))]
+    async fn test_callback_without_session_cookie(db: MySqlPool) {
+        let (server, _) = setup_with_mock_provider(db).await;
+
+        let response = server.get("/auth/google/authorized?code=test_code&state=test_state")
+            .add_header(http::header::ACCEPT, http::HeaderValue::from_static("application/json"))
+            .await;
+
+        response.assert_status(http::StatusCode::BAD_REQUEST);
+        assert_eq!(response.json::<Value>()["code"], "login_state_missing");
+    }
+
+    #[sqlx::test(fixtures("./../../tests/fixtures/users.sql"))]
+    async fn test_declined_consent_is_reported(db: MySqlPool) {
+        let (server, mock_provider) = setup_with_mock_provider(db).await;
+        let start = server.get("/auth/google").await;
+
+        let page = server.get("/auth/google/authorized?error=access_denied&error_description=%3Cscript%3E&state=test_state")
+            .add_cookie(start.cookie("SESSION"))
+            .await;
+
+        page.assert_status(http::StatusCode::UNAUTHORIZED);
+        page.assert_text_contains("The login was cancelled.");
+        assert!(!page.text().contains("<script>"));
+        assert_eq!(mock_provider.request_count(MockEndpoint::Token), 0);
+
+        let api = server.get("/auth/google/authorized?error=%3Cscript%3E&error_description=Visit%20evil.com")
+            .add_header(http::header::ACCEPT, http::HeaderValue::from_static("application/json"))
+            .await;
+        let problem = api.json::<Value>();
+        assert_eq!(problem["code"], "provider_error");
+        assert_eq!(problem["detail"], "The OAuth provider could not complete the login");
+    }
+
+    #[sqlx::test(fixtures("./../../tests/fixtures/users.sql"))]
+    async fn test_login_returns_to_the_requested_page(db: MySqlPool) {
+        let (server, mock_provider) = setup_with_mock_provider(db).await;
+
+        for (return_to, location) in [("%2Fprotected%3Ftab%3D1", "/protected?tab=1"), ("https%3A%2F%2Fevil.com%2F", "/")] {
+            let start = server.get(&format!("/auth/google?return_to={}", return_to)).await;
+            let callback = mock_provider.authorize(start.header("location").to_str().unwrap()).await;
+
+            let response = server.get(&callback).add_cookie(start.cookie("SESSION")).await;
+
+            response.assert_status(http::StatusCode::SEE_OTHER);
+            response.assert_header("location", location);
+        }
+    }
+
+    #[sqlx::test(fixtures("./../../tests/fixtures/users.sql"))]
+    async fn test_logout_return_to_is_validated(db: MySqlPool) {
+        let (server, mock_provider) = setup_with_mock_provider(db).await;
+
+        for (return_to, location) in [("%2Fgoodbye", "/goodbye"), ("%2F%2Fevil.com", "/")] {
+            let user_session = login(&server, &mock_provider, "text/html").await.cookie(USER_SESSION_COOKIE_NAME);
+
+            let logout = server.get(&format!("/logout?return_to={}", return_to)).add_cookie(user_session).await;
+
+            logout.assert_status(http::StatusCode::SEE_OTHER);
+            logout.assert_header("location", location);
+        }
+    }
+
+    fn set_cookies(response: &TestResponse) -> Vec<String> {
+        response.headers()
+            .get_all(http::header::SET_COOKIE)
+            .iter()
+            .map(|value| value.to_str().unwrap().to_string())
+            .collect()
+    }
+
+    #[sqlx::test(fixtures("./../../tests/fixtures/users.sql"))]
+    async fn test_login_and_logout_set_cookie_headers(db: MySqlPool) {
+        let (server, mock_provider) = setup_with_mock_provider(db).await;
+
+        let start = server.get("/auth/google").await;
+        let login_state = start.cookie(LOGIN_STATE_COOKIE_NAME);
+        assert_eq!(set_cookies(&start), [format!("SESSION={}; HttpOnly; SameSite=Lax; Secure; Path=/; Max-Age=3600", login_state.value())]);
+
+        let callback = mock_provider.authorize(start.header("location").to_str().unwrap()).await;
+        let callback = server.get(&callback).add_cookie(login_state).await;
+        let user_session = callback.cookie(USER_SESSION_COOKIE_NAME);
+        assert_eq!(set_cookies(&callback), [
+            format!("USER_SESSION={}; HttpOnly; SameSite=Lax; Secure; Path=/; Max-Age=604800", user_session.value()),
+            "SESSION=; HttpOnly; SameSite=Lax; Secure; Path=/; Max-Age=0".to_string(),
+        ]);
+
+        let logout = server.get("/logout").add_cookie(user_session).await;
+        assert_eq!(set_cookies(&logout), ["USER_SESSION=; HttpOnly; SameSite=Lax; Secure; Path=/; Max-Age=0"]);
+    }
+
+    #[sqlx::test(fixtures("./../../tests/fixtures/users.sql"))]
+    async fn test_prefixed_cookies_are_set_and_read(db: MySqlPool) {
+        let mock_provider = MockOAuthProvider::spawn().await;
+        let cookie_policy = CookiePolicy::from_config(&CookieConfig { name_prefix: "__Host-".to_string(), ..CookieConfig::default() });
+        let app_state = setup_app_state_with_oauth_config(db, mock_provider.oauth_config()).await.with_cookie_policy(cookie_policy);
+        let server = TestServer::new(create_router(app_state).await).unwrap();
+
+        let start = server.get("/auth/google").await;
+        let callback = mock_provider.authorize(start.header("location").to_str().unwrap()).await;
+        let callback = server.get(&callback).add_cookie(start.cookie("__Host-SESSION")).await;
+        callback.assert_status(http::StatusCode::SEE_OTHER);
+        assert!(callback.maybe_cookie(USER_SESSION_COOKIE_NAME).is_none());
+
+        server.get("/protected")
+            .add_cookie(callback.cookie("__Host-USER_SESSION"))
+            .await
+            .assert_text("Welcome to the protected area, Tom!");
+    }
 }
@@ -0,0 +1,104 @@
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use serde_json::json;
+
+use crate::AppState;
+
+/// Probe endpoints, polled by Kubernetes and left out of request logging.
+pub const PROBE_PATHS: [&str; 2] = ["/healthz", "/readyz"];
+
+/// Liveness: the process is up and serving requests.
+pub async fn healthz() -> impl IntoResponse {
+    Json(json!({ "status": "ok" }))
+}
+
+/// Readiness: the database is reachable, migrations are applied and OAuth is configured.
+/// Responds 503 with the same report when any check fails.
+pub async fn readyz(State(app_state): State<AppState>) -> impl IntoResponse {
+    let report = app_state.health_service.readiness().await;
+    let status = if report.is_ready() { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    (status, Json(report))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum_test::TestServer;
+    use http::StatusCode;
+    use serde_json::{json, Value};
+    use sqlx::MySqlPool;
+
+    use crate::{route::create_router, service::{health_service::HealthService, oauth_provider::ProviderRegistry}, state::app_state::AppState, test_utils::setup_app_state};
+
+    async fn setup(app_state: AppState) -> TestServer {
+        TestServer::new(create_router(app_state).await).unwrap()
+    }
+
+    fn check<'a>(report: &'a Value, name: &str) -> &'a Value {
+        report["checks"].as_array().unwrap().iter().find(|check| check["name"] == name).unwrap()
+    }
+
+    #[sqlx::test]
+    async fn test_healthz(db: MySqlPool) {
+        let server = setup(setup_app_state(db).await).await;
+
+        let response = server.get("/healthz").await;
+
+        response.assert_status_ok();
+        response.assert_json(&json!({ "status": "ok" }));
+    }
+
+    #[sqlx::test]
+    async fn test_readyz_reports_every_check(db: MySqlPool) {
+        let server = setup(setup_app_state(db).await).await;
+
+        let response = server.get("/readyz").await;
+
+        response.assert_status_ok();
+        let report: Value = response.json();
+        assert_eq!(report["status"], "ok");
+        for name in ["shutdown", "database", "migrations", "oauth"] {
+            assert_eq!(check(&report, name)["status"], "ok");
+            assert!(check(&report, name)["latency_ms"].is_f64());
+        }
+    }
+
+    #[sqlx::test]
+    async fn test_readyz_fails_when_database_is_unreachable(db: MySqlPool) {
+        let app_state = setup_app_state(db.clone()).await;
+        db.close().await;
+        let server = setup(app_state).await;
+
+        let response = server.get("/readyz").await;
+
+        response.assert_status(StatusCode::SERVICE_UNAVAILABLE);
+        let report: Value = response.json();
+        assert_eq!(report["status"], "failed");
+        assert_eq!(check(&report, "database")["status"], "failed");
+        assert_eq!(check(&report, "database")["error"], "database unavailable");
+    }
+
+    #[sqlx::test]
+    async fn test_readyz_fails_without_oauth_provider(db: MySqlPool) {
+        let mut app_state = setup_app_state(db).await;
+        app_state.health_service = HealthService::new(&app_state.database, &ProviderRegistry::default(), &app_state.shutdown);
+        let server = setup(app_state).await;
+
+        let response = server.get("/readyz").await;
+
+        response.assert_status(StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(check(&response.json(), "oauth")["status"], "failed");
+    }
+
+    #[sqlx::test]
+    async fn test_readyz_fails_once_shutdown_starts(db: MySqlPool) {
+        let app_state = setup_app_state(db).await;
+        app_state.shutdown.trigger();
+        let server = setup(app_state).await;
+
+        let response = server.get("/readyz").await;
+
+        response.assert_status(StatusCode::SERVICE_UNAVAILABLE);
+        let report: Value = response.json();
+        assert_eq!(check(&report, "shutdown")["error"], "shutting down");
+        assert_eq!(check(&report, "database")["status"], "ok");
+    }
+}
@@ -0,0 +1,84 @@
+use axum::{extract::State, http::header::CONTENT_TYPE, response::IntoResponse};
+use prometheus::TEXT_FORMAT;
+
+use crate::{error::app_error::AppError, AppState};
+
+pub const METRICS_PATH: &str = "/metrics";
+
+/// Prometheus scrape endpoint.
+pub async fn metrics(State(app_state): State<AppState>) -> Result<impl IntoResponse, AppError> {
+    let body = app_state.metrics.render(app_state.database.get_pool())?;
+    Ok(([(CONTENT_TYPE, TEXT_FORMAT)], body))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum_test::TestServer;
+    use serde_json::json;
+    use sqlx::MySqlPool;
+
+    use crate::{route::{create_router, metrics_router}, service::oauth_provider::AccessTokenInfo, test_utils::{mock_oauth_provider::{MockEndpoint, MockOAuthProvider}, setup_app_state, setup_app_state_with_oauth_config}};
+
+    #[sqlx::test]
+    async fn test_metrics_counts_requests_by_route_template(db: MySqlPool) {
+        let app_state = setup_app_state(db).await;
+        let server = TestServer::new(create_router(app_state.clone()).await).unwrap();
+        let metrics_server = TestServer::new(metrics_router(app_state)).unwrap();
+        server.get("/healthz").await.assert_status_ok();
+        server.get("/auth/unknown").await;
+        server.method(http::Method::from_bytes(b"PURGE").unwrap(), "/healthz").await;
+
+        let response = metrics_server.get("/metrics").await;
+
+        response.assert_status_ok();
+        assert!(response.header("content-type").to_str().unwrap().starts_with("text/plain"));
+        let metrics = response.text();
+        assert!(metrics.contains(r#"http_requests_total{method="GET",route="/healthz",status="200"} 1"#));
+        assert!(metrics.contains(r#"http_requests_total{method="GET",route="/auth/{provider}",status="404"} 1"#));
+        assert!(metrics.contains(r#"http_request_duration_seconds_count{method="GET",route="/healthz",status="200"} 1"#));
+        assert!(metrics.contains(r#"db_pool_connections{state="max"}"#));
+        assert!(metrics.contains(r#"method="OTHER""#));
+        assert!(!metrics.contains("PURGE"));
+    }
+
+    #[sqlx::test(fixtures("./../../tests/fixtures/users.sql"))]
+    async fn test_metrics_record_failed_login_and_upstream_error(db: MySqlPool) {
+        let mock_provider = MockOAuthProvider::spawn().await;
+        mock_provider.respond_once(MockEndpoint::Token, http::StatusCode::BAD_REQUEST, json!({ "error": "invalid_grant" }));
+        let app_state = setup_app_state_with_oauth_config(db, mock_provider.oauth_config()).await;
+        let server = TestServer::new(create_router(app_state.clone()).await).unwrap();
+        let metrics_server = TestServer::new(metrics_router(app_state)).unwrap();
+
+        let start = server.get("/auth/google").await;
+        let callback = mock_provider.authorize(start.header("location").to_str().unwrap()).await;
+        server.get(&callback).add_cookie(start.cookie("SESSION")).await;
+
+        let metrics = metrics_server.get("/metrics").await.text();
+        assert!(metrics.contains(r#"auth_logins_started_total{provider="google"} 1"#));
+        assert!(metrics.contains(r#"auth_logins_failed_total{provider="google"} 1"#));
+        assert!(metrics.contains(r#"oauth_upstream_errors_total{operation="complete_login",provider="google"} 1"#));
+        assert!(metrics.contains(r#"oauth_upstream_request_duration_seconds_count{operation="complete_login",provider="google"} 1"#));
+        assert!(!metrics.contains("auth_logins_completed_total{"));
+    }
+
+    #[sqlx::test]
+    async fn test_metrics_are_not_served_on_the_public_router(db: MySqlPool) {
+        let server = TestServer::new(create_router(setup_app_state(db).await).await).unwrap();
+
+        server.get("/metrics").await.assert_status_not_found();
+    }
+
+    #[sqlx::test]
+    async fn test_metrics_count_token_info_cache_lookups(db: MySqlPool) {
+        let app_state = setup_app_state(db).await;
+        let metrics_server = TestServer::new(metrics_router(app_state.clone())).unwrap();
+        app_state.token_info_cache.get("google", "access-token").await;
+        app_state.token_info_cache.insert("google", "access-token", AccessTokenInfo { subject: "1".to_string(), expires_in: Some(3600) }).await;
+        app_state.token_info_cache.get("google", "access-token").await;
+
+        let metrics = metrics_server.get("/metrics").await.text();
+
+        assert!(metrics.contains(r#"token_info_cache_lookups_total{provider="google",result="hit"} 1"#));
+        assert!(metrics.contains(r#"token_info_cache_lookups_total{provider="google",result="miss"} 1"#));
+    }
+}
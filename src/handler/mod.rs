@@ -1 +1,6 @@
+pub mod account_handler;
+pub mod api_response;
 pub mod auth_handler;
+pub mod health_handler;
+pub mod metrics_handler;
+pub mod user_handler;
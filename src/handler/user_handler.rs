@@ -0,0 +1,95 @@
+use axum::{extract::{rejection::JsonRejection, State}, response::IntoResponse, Json};
+
+use crate::{error::app_error::AppError, extractor::auth_user::AuthUser, handler::api_response::ApiResponse, service::user_service::ProfileUpdate, AppState};
+
+pub async fn get_me(
+    AuthUser(user): AuthUser,
+    State(app_state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let profile = app_state.user_service.get_profile(user.user_id).await?;
+    Ok(ApiResponse::new(profile))
+}
+
+pub async fn update_me(
+    AuthUser(user): AuthUser,
+    State(app_state): State<AppState>,
+    update: Result<Json<ProfileUpdate>, JsonRejection>,
+) -> Result<impl IntoResponse, AppError> {
+    let Json(update) = update.map_err(|rejection| AppError::BadRequest(rejection.body_text()))?;
+    tracing::debug!("Updating profile of user with ID: {}", user.user_id);
+    let profile = app_state.user_service.update_profile(user.user_id, update).await?;
+    Ok(ApiResponse::new(profile))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum_test::TestServer;
+    use http::StatusCode;
+    use serde_json::{json, Value};
+    use sqlx::MySqlPool;
+
+    use crate::{route::create_router, state::app_state::UserContext, test_utils::setup_app_state};
+
+    /// A server and an access token for Tom, who has signed in with Google.
+    async fn setup(db: MySqlPool) -> (TestServer, String) {
+        let app_state = setup_app_state(db).await;
+        let tom = UserContext { user_id: 1, email: "TestEmail@lift.com".to_string(), name: "Tom".to_string(), roles: vec!["user".to_string()] };
+        let token = app_state.jwt_service.issue_access_token(&tom).unwrap();
+        let server = TestServer::new(create_router(app_state).await).unwrap();
+        (server, token.access_token)
+    }
+
+    #[sqlx::test(fixtures("./../../tests/fixtures/users.sql"))]
+    async fn test_get_me(db: MySqlPool) {
+        let (server, token) = setup(db).await;
+
+        let response = server.get("/api/v1/me").authorization_bearer(token).await;
+
+        response.assert_status_ok();
+        let body: Value = response.json();
+        assert_eq!(body["data"]["id"], 1);
+        assert_eq!(body["data"]["email"], "TestEmail@lift.com");
+        assert_eq!(body["data"]["first_name"], "Tom");
+        assert_eq!(body["data"]["last_name"], "Gill");
+        assert_eq!(body["data"]["providers"], json!(["google"]));
+        assert!(body["data"]["created_at"].is_string());
+    }
+
+    #[sqlx::test(fixtures("./../../tests/fixtures/users.sql"))]
+    async fn test_get_me_requires_authentication(db: MySqlPool) {
+        let (server, _) = setup(db).await;
+
+        let response = server.get("/api/v1/me").await;
+
+        response.assert_status(StatusCode::UNAUTHORIZED);
+        assert_eq!(response.json::<Value>()["code"], "unauthorized");
+    }
+
+    #[sqlx::test(fixtures("./../../tests/fixtures/users.sql"))]
+    async fn test_update_me(db: MySqlPool) {
+        let (server, token) = setup(db).await;
+
+        let response = server.patch("/api/v1/me")
+            .authorization_bearer(&token)
+            .json(&json!({ "first_name": "Thomas" }))
+            .await;
+
+        response.assert_status_ok();
+        let body: Value = response.json();
+        assert_eq!(body["data"]["first_name"], "Thomas");
+        assert_eq!(body["data"]["last_name"], "Gill");
+    }
+
+    #[sqlx::test(fixtures("./../../tests/fixtures/users.sql"))]
+    async fn test_update_me_rejects_read_only_fields(db: MySqlPool) {
+        let (server, token) = setup(db).await;
+
+        let response = server.patch("/api/v1/me")
+            .authorization_bearer(&token)
+            .json(&json!({ "email": "someone-else@lift.com" }))
+            .await;
+
+        response.assert_status(StatusCode::BAD_REQUEST);
+        assert_eq!(response.json::<Value>()["code"], "bad_request");
+    }
+}
@@ -6,55 +6,67 @@ pub mod service;
 pub mod state;
 pub mod repository;
 pub mod error;
+pub mod extractor;
 
 #[cfg(test)]
 pub mod test_utils;
 
 
+use std::{path::Path, sync::Arc, time::Duration};
+
 use anyhow::{Context, Result};
-use axum::{extract::State, response::IntoResponse};
-use config::{database::Database, parameter};
+use axum::{extract::RawQuery, response::IntoResponse};
+use config::{app_config::{AppConfig, CliArgs}, database::Database, oauth_provider_config::OAuthProviderConfig, parameter, secret_file::watch_secret_file, telemetry};
 use error::app_error::AppError;
+use extractor::auth_user::AuthUser;
 use http::Method;
-use middleware::log;
-use oauth2::{basic::BasicClient, AuthUrl, ClientId, ClientSecret, RedirectUrl, RevocationUrl, TokenUrl};
-use route::create_router;
+use route::{create_router, metrics_router};
 use serde::{Deserialize, Serialize};
+use service::{cookie_policy::CookiePolicy, generic_oauth_provider::{GenericOAuthProvider, GenericProviderSettings, ProfileFormat}, google_token_service::{GoogleTokenService, TokenServiceTrait}, jwt_service::JwtService, oauth_provider::ProviderRegistry, return_to_policy::ReturnToPolicy, session_sweeper::SessionSweeper, shutdown_signal::{wait_for_termination, ShutdownSignal}, token_cipher::TokenCipher};
 use state::app_state::AppState;
+use tokio::task::JoinHandle;
 use tower_http::cors::{Any, CorsLayer};
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 #[tokio::main]
 async fn main() -> Result<(), AppError> {
     parameter::init();
 
-    tracing_subscriber::registry()
-        .with(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| format!("sqlx=debug,{}=debug", env!("CARGO_CRATE_NAME")).into()),
-        )
-        .with(tracing_subscriber::fmt::layer())
-        .init();
-
-    tracing::info!("Starting up the application...");
+    let cli_args = CliArgs::parse(std::env::args().skip(1))?;
+    let config = AppConfig::load(cli_args.config_file.as_deref().map(Path::new))?;
+    if cli_args.print_config {
+        println!("{}", config.redacted().to_toml()?);
+        return Ok(());
+    }
 
+    let _telemetry = telemetry::init(&config.telemetry)?;
 
-    let database_url = parameter::get("DATABASE_URL")?;
-    let db = Database::new(&database_url).await?;
-    let oauth_client = get_oauth_client()?;
-    let app_state = AppState::new(db, oauth_client).await?;
+    tracing::info!("Starting up the application...");
+    config.validate()?;
+
+    let database_url = config.database.url.as_deref().unwrap_or_default();
+    let db = Database::new(database_url, config.database.max_connections).await?;
+    let shutdown = ShutdownSignal::default();
+    let providers = get_provider_registry(&config, &shutdown)?;
+    let token_cipher = TokenCipher::from_base64(config.session.encryption_key.as_deref().unwrap_or_default())?;
+    let jwt_service = JwtService::from_config(&config.jwt)?;
+    let app_state = AppState::new(db, providers, token_cipher, jwt_service, shutdown.clone()).await?
+        .with_bootstrap_admin_email(config.bootstrap_admin_email.clone())
+        .with_return_to_policy(ReturnToPolicy::from_config(&config.return_to))
+        .with_cookie_policy(CookiePolicy::from_config(&config.cookie));
+    let database = app_state.database.clone();
+    let metrics_app = metrics_router(app_state.clone());
+    let session_sweeper = spawn_session_sweeper(&config, &app_state);
 
     let cors = CorsLayer::new()
         .allow_origin(Any)
-        .allow_methods([Method::GET, Method::POST, Method::OPTIONS])
+        .allow_methods([Method::GET, Method::POST, Method::PATCH, Method::OPTIONS])
         .allow_headers(Any);
 
     let app = create_router(app_state)
         .await
-        .layer(cors)
-        .layer(axum::middleware::from_fn(log::log_request));
+        .layer(cors);
 
-    let listener = tokio::net::TcpListener::bind("127.0.0.1:3000")
+    let listener = tokio::net::TcpListener::bind(&config.server.listen_address)
         .await
         .context("failed to bind TcpListener")
         .unwrap();
@@ -67,11 +79,49 @@ async fn main() -> Result<(), AppError> {
             .unwrap()
     );
 
-    axum::serve(listener, app).await.unwrap();
+    let metrics_listener = tokio::net::TcpListener::bind(&config.server.metrics_listen_address)
+        .await
+        .context("failed to bind metrics TcpListener")?;
+    tracing::debug!("Serving metrics on {}", config.server.metrics_listen_address);
+    // Left running through the drain so the scraper still sees it; it ends with the process.
+    tokio::spawn(async move {
+        if let Err(error) = axum::serve(metrics_listener, metrics_app).await {
+            tracing::error!("Metrics server error: {error}");
+        }
+    });
+
+    let shutdown_delay = Duration::from_secs(config.server.shutdown_delay_seconds);
+    let shutdown_timeout = Duration::from_secs(config.server.shutdown_timeout_seconds);
+    let server = axum::serve(listener, app).with_graceful_shutdown(stop_accepting(shutdown.clone(), shutdown_delay));
+    tokio::select! {
+        result = async { server.await } => result.context("server error")?,
+        _ = async {
+            shutdown.triggered().await;
+            tokio::time::sleep(shutdown_delay + shutdown_timeout).await;
+        } => tracing::warn!("In-flight requests did not finish within {}s, shutting down anyway", shutdown_timeout.as_secs()),
+    }
+
+    // A sweep in progress is let finish, as closing the pool would fail it half way.
+    if let Some(session_sweeper) = session_sweeper {
+        if tokio::time::timeout(shutdown_timeout, session_sweeper).await.is_err() {
+            tracing::warn!("Session sweep did not finish within {}s, shutting down anyway", shutdown_timeout.as_secs());
+        }
+    }
+    database.get_pool().close().await;
+    tracing::info!("Shutdown complete");
 
     Ok(())
 }
 
+/// Waits for SIGTERM or SIGINT, marks the app not-ready and gives the load balancer
+/// `delay` to notice before the server stops accepting connections and drains.
+async fn stop_accepting(shutdown: ShutdownSignal, delay: Duration) {
+    wait_for_termination().await;
+    tracing::info!("Shutting down, draining for {}s before refusing connections", delay.as_secs());
+    shutdown.trigger();
+    tokio::time::sleep(delay).await;
+}
+
 // TODO - Bad naming - need to redo the structs for google responses.
 #[derive(Debug, Serialize, Deserialize)]
 pub struct User {
@@ -81,43 +131,67 @@ pub struct User {
     email: String,
 }
 
-async fn index(State(app_state): State<AppState>) -> impl IntoResponse {
-    match app_state.user_context.read().await.as_ref() {
-        Some(user) => format!(
+/// A `return_to` left by the auth middleware is passed on to the login links, still encoded.
+async fn index(user: Option<AuthUser>, RawQuery(query): RawQuery) -> impl IntoResponse {
+    let return_to = query
+        .and_then(|query| query.split('&').find(|param| param.starts_with("return_to=")).map(|param| format!("?{}", param)))
+        .unwrap_or_default();
+    match user {
+        Some(AuthUser(user)) => format!(
             "Hey {}! You're logged in!\nYou may now access `/protected`.\nLog out with `/logout`.",
             user.name
         ),
-        None => "You're not logged in.\nVisit `/auth/google` to do so.".to_string(),
+        None => format!("You're not logged in.\nVisit `/auth/google{return_to}` (or `/auth/{{provider}}{return_to}` for another configured provider) to do so."),
     }
 }
 
-async fn protected(State(app_state): State<AppState>) -> Result<impl IntoResponse, AppError> {
-    match app_state.user_context.read().await.as_ref() {
-        Some(user) => Ok(format!("Welcome to the protected area, {}!", user.name)),
-        None => Err(anyhow::anyhow!("You're not logged in.").into()),
+async fn protected(AuthUser(user): AuthUser) -> impl IntoResponse {
+    format!("Welcome to the protected area, {}!", user.name)
+}
+
+async fn admin(AuthUser(user): AuthUser) -> impl IntoResponse {
+    format!("Welcome to the admin area, {}!", user.name)
+}
+
+fn get_provider_registry(config: &AppConfig, shutdown: &ShutdownSignal) -> Result<ProviderRegistry, AppError> {
+    let mut providers = ProviderRegistry::default();
+    let google = GoogleTokenService::new(OAuthProviderConfig::google(&config.google)?)?;
+    watch_google_client_secret(config, shutdown, google.clone());
+    providers.register(Arc::new(google));
+
+    for format in [ProfileFormat::GitHub, ProfileFormat::Microsoft, ProfileFormat::GitLab, ProfileFormat::Oidc] {
+        if let Some(settings) = GenericProviderSettings::from_config(format, &config.providers)? {
+            providers.register(Arc::new(GenericOAuthProvider::new(settings)?));
+        }
     }
+
+    Ok(providers)
 }
 
-fn get_oauth_client() -> Result<BasicClient, AppError> {
-    let client_id = parameter::get("GOOGLE_CLIENT_ID")?;
-    let client_secret = parameter::get("GOOGLE_CLIENT_SECRET")?;
-    let redirect_url = parameter::get("GOOGLE_REDIRECT_URI")?;
-    let auth_url = parameter::get("GOOGLE_AUTH_URI")?;
-    let token_url = parameter::get("GOOGLE_TOKEN_URI")?;
-    let revocation_url = parameter::get("GOOGLE_REVOCATION_URI")?;
-
-
-    Ok(BasicClient::new(
-            ClientId::new(client_id),
-            Some(ClientSecret::new(client_secret)),
-            AuthUrl::new(auth_url).context("failed to create new authorization server URL")?,
-            Some(TokenUrl::new(token_url).context("failed to create new token endpoint URL")?),
-        )
-        .set_redirect_uri(
-            RedirectUrl::new(redirect_url).context("failed to create new redirection URL")?,
-        )
-        .set_revocation_uri(
-            RevocationUrl::new(revocation_url).context("failed to create new revocation URL")?,
-        )
-    )
+/// Rotating the mounted Google client secret takes effect without a restart.
+fn watch_google_client_secret(config: &AppConfig, shutdown: &ShutdownSignal, google: GoogleTokenService) {
+    let Some(path) = config.secret_file("GOOGLE_CLIENT_SECRET") else {
+        return;
+    };
+    if config.secrets.reload_interval_seconds == 0 {
+        return;
+    }
+
+    let interval = Duration::from_secs(config.secrets.reload_interval_seconds);
+    watch_secret_file(path.to_path_buf(), interval, shutdown.clone(), move |client_secret| {
+        if let Err(error) = google.set_client_secret(client_secret) {
+            tracing::error!("Failed to apply the reloaded Google client secret: {}", error);
+        }
+    });
+}
+
+/// The sweeper stops once shutdown is triggered; `None` when sweeping is disabled.
+fn spawn_session_sweeper(config: &AppConfig, app_state: &AppState) -> Option<JoinHandle<()>> {
+    if config.session.sweep_interval_seconds == 0 {
+        return None;
+    }
+
+    let interval = Duration::from_secs(config.session.sweep_interval_seconds);
+    let sweeper = SessionSweeper::new(app_state.session_repository.clone(), app_state.metrics.clone(), config.session.sweep_batch_size);
+    Some(sweeper.spawn(interval, app_state.shutdown.clone()))
 }
@@ -1,73 +1,357 @@
 use axum::{
     extract::{Request, State},
+    http::{header::AUTHORIZATION, HeaderValue, Method},
     middleware::Next,
-    response::{IntoResponse, Redirect},
-    http::header::SET_COOKIE,
+    response::{IntoResponse, Redirect, Response},
 };
 
-use axum_extra::extract::cookie::{Cookie, CookieJar};
+use axum_extra::extract::cookie::CookieJar;
+use oauth2::url::form_urlencoded;
 
-use crate::{error::app_error::AppError, repository::user_repository::UserRepositoryTrait, service::google_token_service::{GoogleTokenService, TokenServiceTrait}, state::app_state::UserContext, AppState};
+use crate::{error::{app_error::AppError, token_error::TokenError}, repository::user_repository::UserRepositoryTrait, service::{cookie_policy::USER_SESSION_COOKIE_NAME, metrics::Outcome, oauth_provider::{AccessTokenInfo, OAuthProvider}, session_service::UserSession}, state::app_state::UserContext, AppState};
 
 // TODO - Add appropriate error responses
 pub async fn auth(
     State(app_state): State<AppState>,
-    State(google_token_service): State<GoogleTokenService>,
     req: Request,
     next: Next,
 ) -> Result<impl IntoResponse, AppError> {
     tracing::debug!("Authenticating request");
+    let authorization = req.headers().get(AUTHORIZATION).cloned();
     let cookies = CookieJar::from_headers(req.headers());
-    if let Some(access_token_cookie) = cookies.get("access_token") {
-        let access_token = access_token_cookie.value().to_string();
-        if (validate_and_set_user_context(&app_state, &google_token_service, &access_token).await?).is_some() {
-            return Ok(next.run(req).await);
-        }
+    match authenticate(&app_state, authorization.as_ref(), &cookies).await? {
+        Some(user_context) => Ok(run_as_user(user_context, req, next).await),
+        None => Ok(Redirect::to(&login_redirect(&req)).into_response()),
+    }
+}
+
+/// Sends anonymous browsers to the login page, remembering the page they asked for so the
+/// login can return there. Only GET requests can be replayed by a redirect.
+fn login_redirect(req: &Request) -> String {
+    let Some(path_and_query) = req.uri().path_and_query().filter(|_| req.method() == Method::GET) else {
+        return "/".to_string();
+    };
+    let return_to: String = form_urlencoded::byte_serialize(path_and_query.as_str().as_bytes()).collect();
+    format!("/?return_to={}", return_to)
+}
+
+/// `auth` for the JSON API: anonymous requests get a 401 instead of a redirect to the login page.
+pub async fn api_auth(
+    State(app_state): State<AppState>,
+    req: Request,
+    next: Next,
+) -> Result<impl IntoResponse, AppError> {
+    let authorization = req.headers().get(AUTHORIZATION).cloned();
+    let cookies = CookieJar::from_headers(req.headers());
+    match authenticate(&app_state, authorization.as_ref(), &cookies).await? {
+        Some(user_context) => Ok(run_as_user(user_context, req, next).await),
+        None => Err(AppError::Unauthorized),
+    }
+}
+
+/// Resolves the user when credentials are present, but lets anonymous requests through.
+pub async fn optional_auth(
+    State(app_state): State<AppState>,
+    req: Request,
+    next: Next,
+) -> Result<impl IntoResponse, AppError> {
+    let authorization = req.headers().get(AUTHORIZATION).cloned();
+    let cookies = CookieJar::from_headers(req.headers());
+    match authenticate(&app_state, authorization.as_ref(), &cookies).await? {
+        Some(user_context) => Ok(run_as_user(user_context, req, next).await),
+        None => Ok(next.run(req).await),
+    }
+}
+
+/// Route guard that only lets through users holding `role`. Layer it inside `auth` (or
+/// `optional_auth`), e.g. `middleware::from_fn_with_state(ADMIN_ROLE, require_role)`.
+pub async fn require_role(
+    State(role): State<&'static str>,
+    req: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    let user_context = req.extensions().get::<UserContext>().ok_or(AppError::Unauthorized)?;
+    if !user_context.has_role(role) {
+        tracing::debug!(user_id = user_context.user_id, role, "User lacks the required role");
+        return Err(AppError::Forbidden);
+    }
+    Ok(next.run(req).await)
+}
+
+async fn run_as_user(user_context: UserContext, mut req: Request, next: Next) -> Response {
+    req.extensions_mut().insert(user_context);
+    next.run(req).await
+}
+
+/// API clients authenticate with a first-party JWT in the `Authorization` header, browsers
+/// with the session cookie. A request that sends a bearer token is never downgraded to the
+/// cookie, so a bad token is rejected rather than redirected. The token's user is looked up
+/// on every request, so a deleted user is locked out before their tokens expire.
+async fn authenticate(
+    app_state: &AppState,
+    authorization: Option<&HeaderValue>,
+    cookies: &CookieJar,
+) -> Result<Option<UserContext>, AppError> {
+    if let Some(authorization) = authorization {
+        let access_token = bearer_token(authorization)?;
+        let claims = app_state.jwt_service.verify_access_token(access_token)?;
+        let user_context = app_state.user_repository.find_user_by_id(claims.user_id()?).await?
+            .ok_or(TokenError::InvalidToken)?;
+        return Ok(Some(user_context));
     }
 
-    if let Some(refresh_token_cookie) = cookies.get("refresh_token") {
-        let refresh_token = refresh_token_cookie.value().to_string();
-        return handle_refresh_token(&app_state, &google_token_service, &refresh_token, req, next).await;
+    let Some(user_session_cookie) = cookies.get(&app_state.cookie_policy.name(USER_SESSION_COOKIE_NAME)) else {
+        return Ok(None);
+    };
+    let user_session_id = user_session_cookie.value();
+    let Some(user_session) = app_state.session_service.get_session(user_session_id).await? else {
+        return Ok(None);
+    };
+    let provider = app_state.providers.get(&user_session.provider)?;
+
+    if introspect_access_token(app_state, provider.as_ref(), &user_session.access_token).await.is_some() {
+        return app_state.user_repository.find_user_by_id(user_session.user_id).await;
     }
-    Ok(Redirect::to("/").into_response())
+
+    handle_refresh_token(app_state, provider.as_ref(), user_session_id, user_session).await
+}
+
+fn bearer_token(authorization: &HeaderValue) -> Result<&str, AppError> {
+    let access_token = authorization
+        .to_str()
+        .ok()
+        .and_then(|value| value.split_once(' '))
+        .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("Bearer"))
+        .map(|(_, token)| token.trim())
+        .filter(|token| !token.is_empty())
+        .ok_or(TokenError::MissingToken)?;
+    Ok(access_token)
 }
 
-async fn validate_and_set_user_context(
+async fn introspect_access_token(
     app_state: &AppState,
-    google_token_service: &GoogleTokenService,
+    provider: &dyn OAuthProvider,
     access_token: &str,
-) -> Result<Option<UserContext>, AppError> {
-    if let Ok(google_token_info) = google_token_service.get_token_info(access_token).await {
-        let existing_user = app_state.user_repository.find_user_by_google_id(&google_token_info.user_id).await?;
-        if let Some(user_context) = existing_user {
-            app_state.set_user_context(user_context.clone()).await;
-            return Ok(Some(user_context));
-        }
+) -> Option<AccessTokenInfo> {
+    if let Some(token_info) = app_state.token_info_cache.get(provider.name(), access_token).await {
+        return Some(token_info);
     }
-    Ok(None)
+
+    let token_info = provider.validate_access_token(access_token).await.ok()?;
+    app_state.token_info_cache.insert(provider.name(), access_token, token_info.clone()).await;
+    Some(token_info)
 }
 
 async fn handle_refresh_token(
     app_state: &AppState,
-    google_token_service: &GoogleTokenService,
-    refresh_token: &str,
-    req: Request,
-    next: Next,
-) -> Result<http::Response<axum::body::Body>, AppError> {
-    if let Ok(new_access_token) = 
-    google_token_service.refresh_access_token(refresh_token.to_string()).await {
-        let new_access_token_cookie = Cookie::build(("access_token", new_access_token.secret().to_string()))
-            .path("/")
-            .http_only(true)
-            .build();
-        let mut response = next.run(req).await.into_response();
-        response.headers_mut().append(
-            SET_COOKIE,
-            new_access_token_cookie.to_string().parse().unwrap(),
+    provider: &dyn OAuthProvider,
+    user_session_id: &str,
+    user_session: UserSession,
+) -> Result<Option<UserContext>, AppError> {
+    let Some(refresh_token) = user_session.refresh_token.as_deref() else {
+        return Ok(None);
+    };
+    let refreshed = provider.refresh(refresh_token).await;
+    app_state.metrics.token_refreshed(provider.name(), Outcome::of(&refreshed));
+    if let Ok(new_access_token) = refreshed {
+        app_state.session_service.update_access_token(user_session_id, &new_access_token).await?;
+        app_state.token_info_cache.invalidate(provider.name(), &user_session.access_token).await;
+        return app_state.user_repository.find_user_by_id(user_session.user_id).await;
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use axum::{response::IntoResponse, routing::get, Json, Router};
+    use axum_extra::{extract::cookie::Cookie, headers::{authorization::Bearer, Authorization}, TypedHeader};
+    use axum_test::TestServer;
+    use http::StatusCode;
+    use serde_json::json;
+    use sqlx::MySqlPool;
+
+    use crate::{config::oauth_provider_config::OAuthProviderConfig, repository::role_repository::{RoleRepository, RoleRepositoryTrait, ADMIN_ROLE}, route::create_router, service::{cookie_policy::USER_SESSION_COOKIE_NAME, token_info_cache::TokenInfoCacheStats}, state::app_state::UserContext, test_utils::{placeholder_oauth_config, setup_app_state, setup_app_state_with_oauth_config}};
+
+    const TOM_GOOGLE_ID: &str = "110235950686105464135";
+    const PATRICK_GOOGLE_ID: &str = "107329637626229533241";
+
+    /// Stub of Google's token info endpoint. Tom's token is answered slowly so that
+    /// Patrick's request is resolved while Tom's is still in flight.
+    async fn spawn_token_info_stub() -> String {
+        let app = Router::new().route("/tokeninfo", get(|TypedHeader(Authorization(bearer)): TypedHeader<Authorization<Bearer>>| async move {
+            let user_id = match bearer.token() {
+                "tom-access-token" => {
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    TOM_GOOGLE_ID
+                }
+                "patrick-access-token" => PATRICK_GOOGLE_ID,
+                _ => return StatusCode::UNAUTHORIZED.into_response(),
+            };
+            Json(json!({
+                "audience": "test-client-id",
+                "email": "test@lift.com",
+                "expires_in": 3600,
+                "issued_to": "test-client-id",
+                "user_id": user_id,
+            })).into_response()
+        }));
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        format!("http://{address}/tokeninfo")
+    }
+
+    async fn token_info_stub_config() -> OAuthProviderConfig {
+        OAuthProviderConfig { token_info_url: spawn_token_info_stub().await, ..placeholder_oauth_config() }
+    }
+
+    #[sqlx::test(fixtures("./../../tests/fixtures/users.sql"))]
+    async fn test_concurrent_users_do_not_share_identity(db: MySqlPool) {
+        let app_state = setup_app_state_with_oauth_config(db, token_info_stub_config().await).await;
+        let tom_session = app_state.session_service.create_session(1, "google", "tom-access-token", Some("tom-refresh-token")).await.unwrap();
+        let patrick_session = app_state.session_service.create_session(2, "google", "patrick-access-token", Some("patrick-refresh-token")).await.unwrap();
+        let server = TestServer::new(create_router(app_state).await).unwrap();
+
+        for _ in 0..5 {
+            let (tom, patrick) = tokio::join!(
+                server.get("/protected").add_cookie(Cookie::new(USER_SESSION_COOKIE_NAME, tom_session.clone())),
+                server.get("/protected").add_cookie(Cookie::new(USER_SESSION_COOKIE_NAME, patrick_session.clone())),
+            );
+
+            tom.assert_text("Welcome to the protected area, Tom!");
+            patrick.assert_text("Welcome to the protected area, Patrick!");
+        }
+
+        let (tom, anonymous) = tokio::join!(
+            server.get("/").add_cookie(Cookie::new(USER_SESSION_COOKIE_NAME, tom_session.clone())),
+            server.get("/"),
         );
-        if (validate_and_set_user_context(app_state, google_token_service, new_access_token.secret()).await?).is_some() {
-            return Ok(response);
+
+        tom.assert_text_contains("Hey Tom!");
+        anonymous.assert_text_contains("You're not logged in.");
+    }
+
+    #[sqlx::test(fixtures("./../../tests/fixtures/users.sql"))]
+    async fn test_token_info_is_cached_between_requests(db: MySqlPool) {
+        let app_state = setup_app_state_with_oauth_config(db, token_info_stub_config().await).await;
+        let patrick_session = app_state.session_service.create_session(2, "google", "patrick-access-token", Some("patrick-refresh-token")).await.unwrap();
+        let server = TestServer::new(create_router(app_state.clone()).await).unwrap();
+
+        for _ in 0..3 {
+            server.get("/protected")
+                .add_cookie(Cookie::new(USER_SESSION_COOKIE_NAME, patrick_session.clone()))
+                .await
+                .assert_text("Welcome to the protected area, Patrick!");
         }
+
+        assert_eq!(app_state.token_info_cache.stats(), TokenInfoCacheStats { hits: 2, misses: 1 });
+    }
+
+    #[sqlx::test(fixtures("./../../tests/fixtures/users.sql"))]
+    async fn test_missing_credentials_redirects(db: MySqlPool) {
+        let app_state = setup_app_state(db).await;
+        let server = TestServer::new(create_router(app_state).await).unwrap();
+
+        let response = server.get("/protected?tab=1").await;
+
+        response.assert_status(StatusCode::SEE_OTHER);
+        response.assert_header("location", "/?return_to=%2Fprotected%3Ftab%3D1");
+    }
+
+    #[sqlx::test(fixtures("./../../tests/fixtures/users.sql"))]
+    async fn test_unknown_session_redirects(db: MySqlPool) {
+        let app_state = setup_app_state(db).await;
+        let server = TestServer::new(create_router(app_state).await).unwrap();
+
+        let response = server.get("/protected")
+            .add_cookie(Cookie::new(USER_SESSION_COOKIE_NAME, "unknown-session-id"))
+            .await;
+
+        response.assert_status(StatusCode::SEE_OTHER);
+        response.assert_header("location", "/?return_to=%2Fprotected");
+    }
+
+    #[sqlx::test(fixtures("./../../tests/fixtures/users.sql"))]
+    async fn test_bearer_token_authenticates(db: MySqlPool) {
+        let app_state = setup_app_state(db).await;
+        let patrick = UserContext { user_id: 2, email: "TestEmail-2@lift.com".to_string(), name: "Patrick".to_string(), roles: Vec::new() };
+        let issued = app_state.jwt_service.issue_access_token(&patrick).unwrap();
+        let server = TestServer::new(create_router(app_state).await).unwrap();
+
+        server.get("/protected")
+            .authorization_bearer(issued.access_token)
+            .await
+            .assert_text("Welcome to the protected area, Patrick!");
+    }
+
+    #[sqlx::test(fixtures("./../../tests/fixtures/users.sql"))]
+    async fn test_bad_bearer_tokens_are_rejected(db: MySqlPool) {
+        let app_state = setup_app_state(db).await;
+        let server = TestServer::new(create_router(app_state).await).unwrap();
+
+        let missing = server.get("/protected").authorization("Bearer ").await;
+        missing.assert_status(StatusCode::UNAUTHORIZED);
+        assert_eq!(missing.json::<serde_json::Value>()["code"], "missing_token");
+
+        let invalid = server.get("/protected").authorization_bearer("not-a-jwt").await;
+        invalid.assert_status(StatusCode::UNAUTHORIZED);
+        assert_eq!(invalid.json::<serde_json::Value>()["code"], "invalid_token");
+    }
+
+    #[sqlx::test(fixtures("./../../tests/fixtures/users.sql"))]
+    async fn test_bearer_token_of_deleted_user_is_rejected(db: MySqlPool) {
+        let app_state = setup_app_state(db).await;
+        let patrick = UserContext { user_id: 2, email: "TestEmail-2@lift.com".to_string(), name: "Patrick".to_string(), roles: Vec::new() };
+        let issued = app_state.jwt_service.issue_access_token(&patrick).unwrap();
+        sqlx::query("DELETE FROM users WHERE id = 2").execute(app_state.database.get_pool()).await.unwrap();
+        let server = TestServer::new(create_router(app_state).await).unwrap();
+
+        let response = server.get("/protected").authorization_bearer(issued.access_token).await;
+
+        response.assert_status(StatusCode::UNAUTHORIZED);
+        assert_eq!(response.json::<serde_json::Value>()["code"], "invalid_token");
+    }
+
+    #[sqlx::test(fixtures("./../../tests/fixtures/users.sql"))]
+    async fn test_admin_routes_require_admin_role(db: MySqlPool) {
+        let app_state = setup_app_state(db).await;
+        let patrick = UserContext { user_id: 2, email: "TestEmail-2@lift.com".to_string(), name: "Patrick".to_string(), roles: Vec::new() };
+        let tom = UserContext { user_id: 1, email: "TestEmail@lift.com".to_string(), name: "Tom".to_string(), roles: Vec::new() };
+        let role_repository = RoleRepository::new(&app_state.database);
+        role_repository.assign_role(tom.user_id, ADMIN_ROLE).await.unwrap();
+        let user_token = app_state.jwt_service.issue_access_token(&patrick).unwrap();
+        let admin_token = app_state.jwt_service.issue_access_token(&tom).unwrap();
+        let server = TestServer::new(create_router(app_state).await).unwrap();
+
+        let anonymous = server.get("/admin").await;
+        anonymous.assert_status(StatusCode::SEE_OTHER);
+
+        let forbidden = server.get("/admin").authorization_bearer(user_token.access_token).await;
+        forbidden.assert_status(StatusCode::FORBIDDEN);
+        assert_eq!(forbidden.json::<serde_json::Value>()["code"], "forbidden");
+
+        server.get("/admin")
+            .authorization_bearer(admin_token.access_token.clone())
+            .await
+            .assert_text("Welcome to the admin area, Tom!");
+
+        role_repository.revoke_role(tom.user_id, ADMIN_ROLE).await.unwrap();
+        server.get("/admin")
+            .authorization_bearer(admin_token.access_token)
+            .await
+            .assert_status(StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_require_role_without_user_is_unauthorized() {
+        let app = Router::new()
+            .route("/", get(|| async { "ok" }))
+            .layer(axum::middleware::from_fn_with_state("admin", super::require_role));
+        let server = TestServer::new(app).unwrap();
+
+        server.get("/").await.assert_status(StatusCode::UNAUTHORIZED);
     }
-    Ok(Redirect::to("/").into_response())
 }
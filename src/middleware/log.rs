@@ -1,10 +1,13 @@
 use axum::{body::Body, extract::Request, middleware::Next, response::Response};
 
-use crate::error::app_error::AppError;
+use crate::{error::app_error::AppError, handler::health_handler::PROBE_PATHS};
 
 pub async fn log_request(req: Request, next: Next) -> Result<Response<Body>, AppError> {
     let method = req.method().clone();
     let uri = req.uri().clone();
+    if PROBE_PATHS.contains(&uri.path()) {
+        return Ok(next.run(req).await);
+    }
 
     tracing::debug!("Request {{path=\"{}\", method=\"{}\"}} Received", uri.path(), method);
 
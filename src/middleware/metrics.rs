@@ -0,0 +1,36 @@
+use std::time::Instant;
+
+use axum::{extract::{MatchedPath, Request, State}, middleware::Next, response::Response};
+use http::Method;
+
+use crate::service::metrics::Metrics;
+
+static STANDARD_METHODS: [Method; 9] = [
+    Method::GET, Method::HEAD, Method::POST, Method::PUT, Method::DELETE,
+    Method::CONNECT, Method::OPTIONS, Method::TRACE, Method::PATCH,
+];
+
+/// Counts and times every request, labelled by route template so `/auth/{provider}` is one
+/// series rather than one per provider. Likewise any non-standard method is `OTHER`.
+pub async fn track_metrics(State(metrics): State<Metrics>, req: Request, next: Next) -> Response {
+    let method = method_label(req.method()).to_string();
+    let route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+    let started = Instant::now();
+
+    let response = next.run(req).await;
+
+    metrics.observe_http_request(&method, &route, response.status().as_u16(), started.elapsed());
+    response
+}
+
+fn method_label(method: &Method) -> &str {
+    if STANDARD_METHODS.contains(method) {
+        method.as_str()
+    } else {
+        "OTHER"
+    }
+}
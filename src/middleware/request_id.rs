@@ -0,0 +1,151 @@
+use axum::{extract::Request, http::{HeaderName, HeaderValue}, middleware::Next, response::Response};
+use rand::RngCore;
+use tracing::Instrument;
+
+use crate::{config::telemetry, service::trace_context::TraceContext};
+
+pub const X_REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+pub const TRACEPARENT: HeaderName = HeaderName::from_static("traceparent");
+
+const MAX_REQUEST_ID_LENGTH: usize = 128;
+
+/// The id of the request being handled, available to handlers as a request extension.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RequestId(pub String);
+
+tokio::task_local! {
+    static CURRENT_REQUEST_ID: String;
+}
+
+/// The id of the request this task is handling, for code without access to the request,
+/// e.g. error bodies built in `IntoResponse`.
+pub fn current_request_id() -> Option<String> {
+    CURRENT_REQUEST_ID.try_with(Clone::clone).ok()
+}
+
+/// Runs the request inside a span carrying its request id and trace id. A well-formed
+/// incoming `X-Request-Id` is kept, otherwise one is generated; a valid `traceparent`
+/// makes this request part of the caller's trace. Both are returned on every response,
+/// errors included.
+pub async fn propagate_request_id(mut req: Request, next: Next) -> Response {
+    let request_id = req.headers()
+        .get(&X_REQUEST_ID)
+        .and_then(|value| value.to_str().ok())
+        .filter(|request_id| is_valid_request_id(request_id))
+        .map(str::to_string)
+        .unwrap_or_else(generate_request_id);
+    let parent = req.headers()
+        .get(&TRACEPARENT)
+        .and_then(|value| value.to_str().ok())
+        .and_then(TraceContext::parse);
+
+    let span = tracing::info_span!(
+        "request",
+        request_id = %request_id,
+        trace_id = tracing::field::Empty,
+        method = %req.method(),
+        path = %req.uri().path(),
+    );
+    let trace_context = telemetry::attach_trace_context(&span, parent);
+    span.record("trace_id", trace_context.trace_id_hex());
+    req.extensions_mut().insert(RequestId(request_id.clone()));
+
+    let mut response = CURRENT_REQUEST_ID.scope(request_id.clone(), next.run(req)).instrument(span).await;
+
+    let headers = response.headers_mut();
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        headers.insert(X_REQUEST_ID, value);
+    }
+    if let Ok(value) = HeaderValue::from_str(&trace_context.to_string()) {
+        headers.insert(TRACEPARENT, value);
+    }
+    response
+}
+
+/// Caller supplied ids end up in logs and headers, so only short, plain ids are trusted.
+fn is_valid_request_id(request_id: &str) -> bool {
+    (1..=MAX_REQUEST_ID_LENGTH).contains(&request_id.len())
+        && request_id.bytes().all(|byte| byte.is_ascii_alphanumeric() || b"-_.:".contains(&byte))
+}
+
+fn generate_request_id() -> String {
+    let mut bytes = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{routing::get, Extension, Router};
+    use axum_test::TestServer;
+    use http::{HeaderValue, StatusCode};
+
+    use crate::{error::app_error::AppError, service::trace_context::TraceContext, test_utils::span_collector::SpanCollector};
+
+    use super::{propagate_request_id, RequestId, TRACEPARENT, X_REQUEST_ID};
+
+    const TRACEPARENT_VALUE: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+    fn server() -> TestServer {
+        let app = Router::new()
+            .route("/echo", get(|Extension(RequestId(request_id)): Extension<RequestId>| async move { request_id }))
+            .route("/fails", get(|| async { Err::<(), _>(AppError::Forbidden) }))
+            .layer(axum::middleware::from_fn(propagate_request_id));
+        TestServer::new(app).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_generates_request_id() {
+        let response = server().get("/echo").await;
+
+        let request_id = response.header(X_REQUEST_ID).to_str().unwrap().to_string();
+        assert_eq!(request_id.len(), 32);
+        response.assert_text(request_id);
+    }
+
+    #[tokio::test]
+    async fn test_keeps_incoming_request_id() {
+        let response = server().get("/echo").add_header(X_REQUEST_ID, HeaderValue::from_static("lb-1234.5")).await;
+
+        response.assert_header(X_REQUEST_ID, "lb-1234.5");
+        response.assert_text("lb-1234.5");
+    }
+
+    #[tokio::test]
+    async fn test_replaces_malformed_request_id() {
+        let response = server().get("/echo").add_header(X_REQUEST_ID, HeaderValue::from_static("<script>")).await;
+
+        assert_ne!(response.header(X_REQUEST_ID), "<script>");
+    }
+
+    #[tokio::test]
+    async fn test_error_responses_carry_request_id() {
+        let response = server().get("/fails").add_header(X_REQUEST_ID, HeaderValue::from_static("failing-request")).await;
+
+        response.assert_status(StatusCode::FORBIDDEN);
+        response.assert_header(X_REQUEST_ID, "failing-request");
+        assert_eq!(response.json::<serde_json::Value>()["request_id"], "failing-request");
+    }
+
+    #[tokio::test]
+    async fn test_continues_incoming_trace() {
+        let collector = SpanCollector::install();
+
+        let response = server().get("/echo").add_header(TRACEPARENT, HeaderValue::from_static(TRACEPARENT_VALUE)).await;
+
+        let traceparent = TraceContext::parse(response.header(TRACEPARENT).to_str().unwrap()).unwrap();
+        let incoming = TraceContext::parse(TRACEPARENT_VALUE).unwrap();
+        assert_eq!(traceparent.trace_id, incoming.trace_id);
+        assert_ne!(traceparent.span_id, incoming.span_id);
+        let span = collector.span("request").unwrap();
+        assert_eq!(span.field("trace_id"), Some("4bf92f3577b34da6a3ce929d0e0e4736"));
+        assert_eq!(span.field("request_id"), response.header(X_REQUEST_ID).to_str().ok());
+    }
+
+    #[tokio::test]
+    async fn test_starts_new_trace_without_traceparent() {
+        let response = server().get("/echo").add_header(TRACEPARENT, HeaderValue::from_static("garbage")).await;
+
+        assert!(TraceContext::parse(response.header(TRACEPARENT).to_str().unwrap()).is_some());
+    }
+}
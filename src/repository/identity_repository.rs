@@ -0,0 +1,335 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::{config::database::Database, error::app_error::AppError, repository::user_repository::UserContextRecord, state::app_state::UserContext};
+
+
+#[derive(Clone)]
+pub struct IdentityRepository {
+    pub(crate) db_conn: Arc<Database>,
+}
+
+/// A provider account linked to a user. `email` is the address the provider reported when
+/// the identity was linked.
+#[derive(Debug, Eq, PartialEq, Serialize)]
+pub struct UserIdentity {
+    pub provider: String,
+    pub subject: String,
+    pub email: String,
+    pub linked_at: Option<DateTime<Utc>>,
+}
+
+#[async_trait]
+pub trait IdentityRepositoryTrait {
+    fn new(db_conn: &Arc<Database>) -> Self;
+    async fn add_user_with_identity(&self, email: &str, first_name: &str, last_name: &str, provider: &str, subject: &str) -> Result<u64, AppError>;
+    async fn link_identity(&self, user_id: u64, provider: &str, subject: &str, email: &str) -> Result<(), AppError>;
+    async fn unlink_identity(&self, user_id: u64, provider: &str) -> Result<(), AppError>;
+    async fn find_user_by_identity(&self, provider: &str, subject: &str) -> Result<Option<UserContext>, AppError>;
+    async fn list_identities(&self, user_id: u64) -> Result<Vec<UserIdentity>, AppError>;
+}
+
+#[async_trait]
+impl IdentityRepositoryTrait for IdentityRepository {
+    fn new(db_conn: &Arc<Database>) -> Self {
+        Self {
+            db_conn: Arc::clone(db_conn),
+        }
+    }
+
+    /// Creates a user together with their first identity in one transaction, so a failed
+    /// link cannot leave behind a user nobody can sign in as. A user with the same email
+    /// or an identity already linked, e.g. by a concurrent first login, is a Conflict.
+    #[tracing::instrument(skip_all, fields(provider = provider))]
+    async fn add_user_with_identity(&self, email: &str, first_name: &str, last_name: &str, provider: &str, subject: &str) -> Result<u64, AppError> {
+        let mut transaction = self.db_conn.get_pool().begin().await?;
+
+        let user = sqlx::query!(
+            r#"
+                INSERT INTO users (email, first_name, last_name)
+                VALUES (?, ?, ?)
+            "#,
+            email,
+            first_name,
+            last_name,
+        )
+        .execute(&mut *transaction)
+        .await
+        .map_err(conflict_on_unique_violation)?;
+        let user_id = user.last_insert_id();
+
+        sqlx::query!(
+            r#"
+                INSERT INTO user_identities (user_id, provider, subject, email)
+                VALUES (?, ?, ?, ?)
+            "#,
+            user_id,
+            provider,
+            subject,
+            email
+        )
+        .execute(&mut *transaction)
+        .await
+        .map_err(conflict_on_unique_violation)?;
+
+        transaction.commit().await?;
+        Ok(user_id)
+    }
+
+    /// A concurrent link of the same identity can pass the check below before either insert
+    /// lands; the loser then hits a unique key and is answered like the check would have.
+    #[tracing::instrument(skip_all, fields(user_id = user_id, provider = provider))]
+    async fn link_identity(&self, user_id: u64, provider: &str, subject: &str, email: &str) -> Result<(), AppError> {
+        let existing_user = self.find_user_by_identity(provider, subject).await?;
+        match existing_user {
+            Some(user) if user.user_id == user_id => return Ok(()),
+            Some(_) => return Err(AppError::Conflict(format!("This {} account is already linked to another user", provider))),
+            None => {}
+        }
+
+        let inserted = sqlx::query!(
+            r#"
+                INSERT INTO user_identities (user_id, provider, subject, email)
+                VALUES (?, ?, ?, ?)
+            "#,
+            user_id,
+            provider,
+            subject,
+            email
+        )
+        .execute(self.db_conn.get_pool())
+        .await;
+
+        match inserted {
+            Ok(_) => Ok(()),
+            Err(error) if is_unique_violation(&error) => {
+                match self.find_user_by_identity(provider, subject).await? {
+                    Some(user) if user.user_id == user_id => Ok(()),
+                    Some(_) => Err(AppError::Conflict(format!("This {} account is already linked to another user", provider))),
+                    None => Err(AppError::Conflict(format!("Another {} account is already linked to this user", provider))),
+                }
+            }
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    /// Locks the user's identities while unlinking so that two concurrent requests cannot
+    /// remove the last two identities between them.
+    #[tracing::instrument(skip_all, fields(user_id = user_id, provider = provider))]
+    async fn unlink_identity(&self, user_id: u64, provider: &str) -> Result<(), AppError> {
+        let mut transaction = self.db_conn.get_pool().begin().await?;
+
+        let linked_providers = sqlx::query_scalar!(
+            r#"
+                SELECT provider FROM user_identities WHERE user_id = ? FOR UPDATE
+            "#,
+            user_id
+        )
+        .fetch_all(&mut *transaction)
+        .await?;
+
+        if !linked_providers.iter().any(|linked_provider| linked_provider == provider) {
+            return Err(AppError::NotFound(format!("No {} identity is linked to this account", provider)));
+        }
+        if linked_providers.len() <= 1 {
+            return Err(AppError::Conflict("Cannot unlink the last remaining identity".to_string()));
+        }
+
+        sqlx::query!(
+            r#"
+                DELETE FROM user_identities WHERE user_id = ? AND provider = ?
+            "#,
+            user_id,
+            provider
+        )
+        .execute(&mut *transaction)
+        .await?;
+
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    #[tracing::instrument(skip_all, fields(provider = provider))]
+    async fn find_user_by_identity(&self, provider: &str, subject: &str) -> Result<Option<UserContext>, AppError> {
+        let user_context = sqlx::query_as!(
+            UserContextRecord,
+            r#"
+            SELECT
+                CAST(users.id as unsigned) AS user_id,
+                users.email,
+                users.first_name AS name,
+                GROUP_CONCAT(roles.name) AS roles
+            FROM user_identities
+            JOIN users ON users.id = user_identities.user_id
+            LEFT JOIN user_roles ON user_roles.user_id = users.id
+            LEFT JOIN roles ON roles.id = user_roles.role_id
+            WHERE user_identities.provider = ? AND user_identities.subject = ?
+            GROUP BY users.id
+            "#,
+            provider,
+            subject
+        )
+        .fetch_optional(self.db_conn.get_pool())
+        .await?;
+
+        Ok(user_context.map(UserContext::from))
+    }
+
+    #[tracing::instrument(skip_all, fields(user_id = user_id))]
+    async fn list_identities(&self, user_id: u64) -> Result<Vec<UserIdentity>, AppError> {
+        let identities = sqlx::query_as!(
+            UserIdentity,
+            r#"
+                SELECT provider, subject, email, linked_at
+                FROM user_identities
+                WHERE user_id = ?
+                ORDER BY linked_at, id
+            "#,
+            user_id
+        )
+        .fetch_all(self.db_conn.get_pool())
+        .await?;
+
+        Ok(identities)
+    }
+}
+
+fn is_unique_violation(error: &sqlx::Error) -> bool {
+    error.as_database_error().is_some_and(|error| error.is_unique_violation())
+}
+
+fn conflict_on_unique_violation(error: sqlx::Error) -> AppError {
+    if is_unique_violation(&error) {
+        AppError::Conflict("The user or identity already exists".to_string())
+    } else {
+        error.into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use sqlx::MySqlPool;
+
+    use crate::{assert_error, config::database::Database, error::app_error::AppError, repository::user_repository::{UserRepository, UserRepositoryTrait}};
+
+    use super::{IdentityRepository, IdentityRepositoryTrait};
+
+    async fn get_identity_repository(db: MySqlPool) -> IdentityRepository {
+        let db_conn = Database { pool: db };
+        IdentityRepository::new(&Arc::new(db_conn))
+    }
+
+    #[sqlx::test(fixtures("./../../tests/fixtures/users.sql"))]
+    async fn test_find_user_by_identity(db: MySqlPool) {
+        let identity_repository = get_identity_repository(db).await;
+
+        let user_context = identity_repository.find_user_by_identity("google", "107329637626229533241").await.unwrap();
+        assert_eq!(user_context.map(|user| user.user_id), Some(2));
+
+        let user_context = identity_repository.find_user_by_identity("github", "107329637626229533241").await.unwrap();
+        assert!(user_context.is_none());
+    }
+
+    #[sqlx::test(fixtures("./../../tests/fixtures/users.sql"))]
+    async fn test_add_user_with_identity(db: MySqlPool) {
+        let identity_repository = get_identity_repository(db).await;
+
+        let user_id = identity_repository.add_user_with_identity("gt@lift.com", "George", "Thomas", "github", "583231").await.unwrap();
+
+        let user_context = identity_repository.find_user_by_identity("github", "583231").await.unwrap().unwrap();
+        assert_eq!(user_context.user_id, user_id);
+        assert_eq!(user_context.email, "gt@lift.com");
+    }
+
+    #[sqlx::test(fixtures("./../../tests/fixtures/users.sql"))]
+    async fn test_add_user_with_linked_identity_leaves_no_user(db: MySqlPool) {
+        let identity_repository = get_identity_repository(db).await;
+        let user_repository = UserRepository::new(&identity_repository.db_conn);
+
+        let result = identity_repository.add_user_with_identity("gt@lift.com", "George", "Thomas", "google", "110235950686105464135").await;
+
+        assert_error!(result, &AppError::Conflict(String::new()));
+        assert!(user_repository.find_user_by_email("gt@lift.com").await.unwrap().is_none());
+    }
+
+    #[sqlx::test(fixtures("./../../tests/fixtures/users.sql"))]
+    async fn test_link_and_list_identities(db: MySqlPool) {
+        let identity_repository = get_identity_repository(db).await;
+
+        identity_repository.link_identity(1, "github", "583231", "tom@github.com").await.unwrap();
+
+        let identities = identity_repository.list_identities(1).await.unwrap();
+        let providers: Vec<&str> = identities.iter().map(|identity| identity.provider.as_str()).collect();
+        assert_eq!(providers, vec!["google", "github"]);
+        assert_eq!(identities[1].email, "tom@github.com");
+    }
+
+    #[sqlx::test(fixtures("./../../tests/fixtures/users.sql"))]
+    async fn test_link_identity_owned_by_another_user(db: MySqlPool) {
+        let identity_repository = get_identity_repository(db).await;
+
+        let result = identity_repository.link_identity(1, "google", "107329637626229533241", "TestEmail-2@lift.com").await;
+
+        assert_error!(result, &AppError::Conflict(String::new()));
+    }
+
+    #[sqlx::test(fixtures("./../../tests/fixtures/users.sql"))]
+    async fn test_link_identity_in_parallel(db: MySqlPool) {
+        let identity_repository = get_identity_repository(db).await;
+
+        let attempts = [1, 2, 1, 2, 1, 2, 1, 2].map(|user_id| {
+            let identity_repository = identity_repository.clone();
+            tokio::spawn(async move { (user_id, identity_repository.link_identity(user_id, "github", "583231", "octocat@github.com").await) })
+        });
+        let mut owners = Vec::new();
+        for attempt in attempts {
+            match attempt.await.unwrap() {
+                (user_id, Ok(())) => owners.push(user_id),
+                (_, result) => {
+                    assert_error!(result, &AppError::Conflict(String::new()));
+                }
+            }
+        }
+
+        owners.dedup();
+        assert_eq!(owners.len(), 1);
+        let owner = identity_repository.find_user_by_identity("github", "583231").await.unwrap().unwrap();
+        assert_eq!(owner.user_id, owners[0]);
+    }
+
+    #[sqlx::test(fixtures("./../../tests/fixtures/users.sql"))]
+    async fn test_unlink_identity(db: MySqlPool) {
+        let identity_repository = get_identity_repository(db).await;
+
+        identity_repository.link_identity(1, "github", "583231", "tom@github.com").await.unwrap();
+        identity_repository.unlink_identity(1, "google").await.unwrap();
+
+        let identities = identity_repository.list_identities(1).await.unwrap();
+        assert_eq!(identities.len(), 1);
+        assert_eq!(identities[0].provider, "github");
+    }
+
+    #[sqlx::test(fixtures("./../../tests/fixtures/users.sql"))]
+    async fn test_unlink_last_identity(db: MySqlPool) {
+        let identity_repository = get_identity_repository(db).await;
+
+        let result = identity_repository.unlink_identity(1, "google").await;
+
+        assert_error!(result, &AppError::Conflict(String::new()));
+        assert_eq!(identity_repository.list_identities(1).await.unwrap().len(), 1);
+    }
+
+    #[sqlx::test(fixtures("./../../tests/fixtures/users.sql"))]
+    async fn test_unlink_unknown_identity(db: MySqlPool) {
+        let identity_repository = get_identity_repository(db).await;
+
+        let result = identity_repository.unlink_identity(1, "github").await;
+
+        assert_error!(result, &AppError::NotFound(String::new()));
+    }
+}
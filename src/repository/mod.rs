@@ -1,2 +1,5 @@
+pub mod identity_repository;
+pub mod refresh_token_repository;
+pub mod role_repository;
 pub mod user_repository;
 pub mod session_repository;
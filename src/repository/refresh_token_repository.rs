@@ -0,0 +1,222 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+
+use crate::{config::database::Database, error::app_error::AppError};
+
+
+#[derive(Clone)]
+pub struct RefreshTokenRepository {
+    pub(crate) db_conn: Arc<Database>,
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub struct RefreshTokenRecord {
+    pub id: u64,
+    pub family_id: String,
+    pub user_id: u64,
+    pub expires_at: DateTime<Utc>,
+    pub rotated_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+#[async_trait]
+pub trait RefreshTokenRepositoryTrait {
+    fn new(db_conn: &Arc<Database>) -> Self;
+    async fn add_refresh_token(&self, family_id: &str, user_id: u64, token_hash: &str, expires_at: DateTime<Utc>) -> Result<(), AppError>;
+    async fn find_refresh_token(&self, token_hash: &str) -> Result<Option<RefreshTokenRecord>, AppError>;
+    async fn rotate_refresh_token(&self, id: u64, family_id: &str, user_id: u64, token_hash: &str, expires_at: DateTime<Utc>) -> Result<bool, AppError>;
+    async fn revoke_family(&self, family_id: &str) -> Result<(), AppError>;
+}
+
+#[async_trait]
+impl RefreshTokenRepositoryTrait for RefreshTokenRepository {
+    fn new(db_conn: &Arc<Database>) -> Self {
+        Self {
+            db_conn: Arc::clone(db_conn),
+        }
+    }
+
+    #[tracing::instrument(skip_all, fields(user_id = user_id))]
+    async fn add_refresh_token(&self, family_id: &str, user_id: u64, token_hash: &str, expires_at: DateTime<Utc>) -> Result<(), AppError> {
+        sqlx::query!(
+            r#"
+                INSERT INTO refresh_tokens (family_id, user_id, token_hash, expires_at)
+                VALUES (?, ?, ?, ?)
+            "#,
+            family_id,
+            user_id,
+            token_hash,
+            expires_at
+        )
+        .execute(self.db_conn.get_pool())
+        .await?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn find_refresh_token(&self, token_hash: &str) -> Result<Option<RefreshTokenRecord>, AppError> {
+        let refresh_token = sqlx::query_as!(
+            RefreshTokenRecord,
+            r#"
+                SELECT
+                    CAST(id as unsigned) AS id,
+                    family_id,
+                    CAST(user_id as unsigned) AS user_id,
+                    expires_at,
+                    rotated_at,
+                    revoked_at
+                FROM refresh_tokens
+                WHERE token_hash = ?
+            "#,
+            token_hash
+        )
+        .fetch_optional(self.db_conn.get_pool())
+        .await?;
+
+        Ok(refresh_token)
+    }
+
+    /// Marks the token rotated and adds its successor to the family in one transaction.
+    /// The family's rows stay locked until it commits, so a concurrent `revoke_family`
+    /// either runs first and the rotation fails, or waits and revokes the new token too.
+    /// Returns `false` when the token was already rotated or revoked, so that of two
+    /// concurrent rotations of the same token only one succeeds.
+    #[tracing::instrument(skip_all, fields(user_id = user_id))]
+    async fn rotate_refresh_token(&self, id: u64, family_id: &str, user_id: u64, token_hash: &str, expires_at: DateTime<Utc>) -> Result<bool, AppError> {
+        let mut transaction = self.db_conn.get_pool().begin().await?;
+
+        sqlx::query!(
+            r#"
+                SELECT id FROM refresh_tokens
+                WHERE family_id = ?
+                FOR UPDATE
+            "#,
+            family_id
+        )
+        .fetch_all(&mut *transaction)
+        .await?;
+
+        let result = sqlx::query!(
+            r#"
+                UPDATE refresh_tokens
+                SET rotated_at = NOW()
+                WHERE id = ? AND rotated_at IS NULL AND revoked_at IS NULL
+            "#,
+            id
+        )
+        .execute(&mut *transaction)
+        .await?;
+        if result.rows_affected() != 1 {
+            return Ok(false);
+        }
+
+        sqlx::query!(
+            r#"
+                INSERT INTO refresh_tokens (family_id, user_id, token_hash, expires_at)
+                VALUES (?, ?, ?, ?)
+            "#,
+            family_id,
+            user_id,
+            token_hash,
+            expires_at
+        )
+        .execute(&mut *transaction)
+        .await?;
+
+        transaction.commit().await?;
+        Ok(true)
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn revoke_family(&self, family_id: &str) -> Result<(), AppError> {
+        sqlx::query!(
+            r#"
+                UPDATE refresh_tokens
+                SET revoked_at = NOW()
+                WHERE family_id = ? AND revoked_at IS NULL
+            "#,
+            family_id
+        )
+        .execute(self.db_conn.get_pool())
+        .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use chrono::{Duration, Utc};
+    use sqlx::MySqlPool;
+
+    use crate::config::database::Database;
+
+    use super::{RefreshTokenRepository, RefreshTokenRepositoryTrait};
+
+    async fn get_refresh_token_repository(db: MySqlPool) -> RefreshTokenRepository {
+        let db_conn = Database { pool: db };
+        RefreshTokenRepository::new(&Arc::new(db_conn))
+    }
+
+    #[sqlx::test(fixtures("./../../tests/fixtures/users.sql"))]
+    async fn test_add_and_find_refresh_token(db: MySqlPool) {
+        let refresh_token_repository = get_refresh_token_repository(db).await;
+
+        refresh_token_repository.add_refresh_token("family", 1, "hash", Utc::now() + Duration::days(1)).await.unwrap();
+
+        let refresh_token = refresh_token_repository.find_refresh_token("hash").await.unwrap().unwrap();
+        assert_eq!(refresh_token.family_id, "family");
+        assert_eq!(refresh_token.user_id, 1);
+        assert!(refresh_token.rotated_at.is_none());
+        assert!(refresh_token_repository.find_refresh_token("unknown").await.unwrap().is_none());
+    }
+
+    #[sqlx::test(fixtures("./../../tests/fixtures/users.sql"))]
+    async fn test_rotate_refresh_token_only_once(db: MySqlPool) {
+        let refresh_token_repository = get_refresh_token_repository(db).await;
+        let expires_at = Utc::now() + Duration::days(1);
+
+        refresh_token_repository.add_refresh_token("family", 1, "hash", expires_at).await.unwrap();
+        let refresh_token = refresh_token_repository.find_refresh_token("hash").await.unwrap().unwrap();
+
+        assert!(refresh_token_repository.rotate_refresh_token(refresh_token.id, "family", 1, "next", expires_at).await.unwrap());
+        assert!(!refresh_token_repository.rotate_refresh_token(refresh_token.id, "family", 1, "again", expires_at).await.unwrap());
+
+        assert!(refresh_token_repository.find_refresh_token("hash").await.unwrap().unwrap().rotated_at.is_some());
+        assert_eq!(refresh_token_repository.find_refresh_token("next").await.unwrap().unwrap().family_id, "family");
+        assert!(refresh_token_repository.find_refresh_token("again").await.unwrap().is_none());
+    }
+
+    #[sqlx::test(fixtures("./../../tests/fixtures/users.sql"))]
+    async fn test_rotate_refresh_token_of_revoked_family(db: MySqlPool) {
+        let refresh_token_repository = get_refresh_token_repository(db).await;
+        let expires_at = Utc::now() + Duration::days(1);
+
+        refresh_token_repository.add_refresh_token("family", 1, "hash", expires_at).await.unwrap();
+        let refresh_token = refresh_token_repository.find_refresh_token("hash").await.unwrap().unwrap();
+        refresh_token_repository.revoke_family("family").await.unwrap();
+
+        assert!(!refresh_token_repository.rotate_refresh_token(refresh_token.id, "family", 1, "next", expires_at).await.unwrap());
+        assert!(refresh_token_repository.find_refresh_token("next").await.unwrap().is_none());
+    }
+
+    #[sqlx::test(fixtures("./../../tests/fixtures/users.sql"))]
+    async fn test_revoke_family(db: MySqlPool) {
+        let refresh_token_repository = get_refresh_token_repository(db).await;
+
+        refresh_token_repository.add_refresh_token("family", 1, "first", Utc::now() + Duration::days(1)).await.unwrap();
+        refresh_token_repository.add_refresh_token("family", 1, "second", Utc::now() + Duration::days(1)).await.unwrap();
+        refresh_token_repository.add_refresh_token("other", 1, "third", Utc::now() + Duration::days(1)).await.unwrap();
+
+        refresh_token_repository.revoke_family("family").await.unwrap();
+
+        assert!(refresh_token_repository.find_refresh_token("first").await.unwrap().unwrap().revoked_at.is_some());
+        assert!(refresh_token_repository.find_refresh_token("second").await.unwrap().unwrap().revoked_at.is_some());
+        assert!(refresh_token_repository.find_refresh_token("third").await.unwrap().unwrap().revoked_at.is_none());
+    }
+}
@@ -0,0 +1,132 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+
+use crate::{config::database::Database, error::app_error::AppError};
+
+
+pub const ADMIN_ROLE: &str = "admin";
+
+#[derive(Clone)]
+pub struct RoleRepository {
+    pub(crate) db_conn: Arc<Database>,
+}
+
+#[async_trait]
+pub trait RoleRepositoryTrait {
+    fn new(db_conn: &Arc<Database>) -> Self;
+    async fn assign_role(&self, user_id: u64, role: &str) -> Result<(), AppError>;
+    async fn revoke_role(&self, user_id: u64, role: &str) -> Result<(), AppError>;
+}
+
+#[async_trait]
+impl RoleRepositoryTrait for RoleRepository {
+    fn new(db_conn: &Arc<Database>) -> Self {
+        Self {
+            db_conn: Arc::clone(db_conn),
+        }
+    }
+
+    /// Assigning a role the user already has is a no-op.
+    #[tracing::instrument(skip_all, fields(user_id = user_id))]
+    async fn assign_role(&self, user_id: u64, role: &str) -> Result<(), AppError> {
+        let result = sqlx::query!(
+            r#"
+                INSERT IGNORE INTO user_roles (user_id, role_id)
+                SELECT ?, id FROM roles WHERE name = ?
+            "#,
+            user_id,
+            role
+        )
+        .execute(self.db_conn.get_pool())
+        .await?;
+
+        if result.rows_affected() == 0 && !self.role_exists(role).await? {
+            return Err(AppError::NotFound(format!("Unknown role {}", role)));
+        }
+        Ok(())
+    }
+
+    #[tracing::instrument(skip_all, fields(user_id = user_id))]
+    async fn revoke_role(&self, user_id: u64, role: &str) -> Result<(), AppError> {
+        sqlx::query!(
+            r#"
+                DELETE user_roles FROM user_roles
+                JOIN roles ON roles.id = user_roles.role_id
+                WHERE user_roles.user_id = ? AND roles.name = ?
+            "#,
+            user_id,
+            role
+        )
+        .execute(self.db_conn.get_pool())
+        .await?;
+
+        Ok(())
+    }
+}
+
+impl RoleRepository {
+    #[tracing::instrument(skip_all)]
+    async fn role_exists(&self, role: &str) -> Result<bool, AppError> {
+        let count = sqlx::query_scalar!(
+            r#"
+                SELECT COUNT(*) FROM roles WHERE name = ?
+            "#,
+            role
+        )
+        .fetch_one(self.db_conn.get_pool())
+        .await?;
+
+        Ok(count > 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use sqlx::MySqlPool;
+
+    use crate::{assert_error, config::database::Database, error::app_error::AppError, repository::user_repository::{UserRepository, UserRepositoryTrait}};
+
+    use super::{RoleRepository, RoleRepositoryTrait, ADMIN_ROLE};
+
+    async fn get_repositories(db: MySqlPool) -> (RoleRepository, UserRepository) {
+        let db_conn = Arc::new(Database { pool: db });
+        (RoleRepository::new(&db_conn), UserRepository::new(&db_conn))
+    }
+
+    #[sqlx::test(fixtures("./../../tests/fixtures/users.sql"))]
+    async fn test_assigned_roles_are_loaded_with_the_user(db: MySqlPool) {
+        let (role_repository, user_repository) = get_repositories(db).await;
+
+        role_repository.assign_role(1, ADMIN_ROLE).await.unwrap();
+        role_repository.assign_role(1, "user").await.unwrap();
+        role_repository.assign_role(1, ADMIN_ROLE).await.unwrap();
+
+        let mut roles = user_repository.find_user_by_id(1).await.unwrap().unwrap().roles;
+        roles.sort();
+        assert_eq!(roles, vec!["admin", "user"]);
+        assert!(user_repository.find_user_by_id(2).await.unwrap().unwrap().roles.is_empty());
+    }
+
+    #[sqlx::test(fixtures("./../../tests/fixtures/users.sql"))]
+    async fn test_revoke_role(db: MySqlPool) {
+        let (role_repository, user_repository) = get_repositories(db).await;
+
+        role_repository.assign_role(1, ADMIN_ROLE).await.unwrap();
+        role_repository.revoke_role(1, ADMIN_ROLE).await.unwrap();
+
+        let user_context = user_repository.find_user_by_email("TestEmail@lift.com").await.unwrap().unwrap();
+        assert!(!user_context.has_role(ADMIN_ROLE));
+    }
+
+    #[sqlx::test(fixtures("./../../tests/fixtures/users.sql"))]
+    async fn test_assign_unknown_role(db: MySqlPool) {
+        let (role_repository, _) = get_repositories(db).await;
+
+        let result = role_repository.assign_role(1, "superuser").await;
+
+        assert_error!(result, &AppError::NotFound(String::new()));
+    }
+}
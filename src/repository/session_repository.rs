@@ -1,22 +1,50 @@
 use std::sync::Arc;
 
 use async_trait::async_trait;
-use chrono::{Duration, Utc};
+use chrono::{DateTime, Duration, Utc};
 
 use crate::{config::database::Database, error::app_error::AppError};
 
+/// How long a started login can be completed.
+pub const CSRF_SESSION_LIFETIME_MINUTES: i64 = 60;
 
 #[derive(Clone)]
 pub struct SessionRepository {
     pub(crate) db_conn: Arc<Database>,
 }
 
+#[derive(Debug, Eq, PartialEq)]
+pub struct CsrfSession {
+    pub provider: String,
+    pub csrf_token: String,
+    pub pkce_verifier: Option<String>,
+    pub nonce: Option<String>,
+    /// Set when the login links another provider to this already signed-in user.
+    pub link_user_id: Option<u64>,
+    /// Where to send the browser after the login, already checked against the allowlist.
+    pub return_to: Option<String>,
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub struct UserSessionRecord {
+    pub user_id: u64,
+    pub provider: String,
+    pub access_token: String,
+    pub refresh_token: Option<String>,
+}
+
 #[async_trait]
 pub trait SessionRepositoryTrait {
     fn new(db_conn: &Arc<Database>) -> Self;
-    async fn add_csrf_token(&self, session_id: &str, csrf_token: &str) -> Result<(), AppError>;
+    async fn add_csrf_token(&self, session_id: &str, csrf_session: &CsrfSession) -> Result<(), AppError>;
     async fn expire_session(&self, session_id: &str) -> Result<(), AppError>;
-    async fn get_csrf_token_by_session_id(&self, session_id: &str) -> Result<String, AppError>;
+    async fn delete_expired_csrf_sessions(&self, limit: u32) -> Result<u64, AppError>;
+    async fn get_csrf_session_by_session_id(&self, session_id: &str) -> Result<CsrfSession, AppError>;
+    async fn consume_csrf_token(&self, session_id: &str) -> Result<Option<CsrfSession>, AppError>;
+    async fn add_user_session(&self, session_id: &str, user_id: u64, provider: &str, access_token: &str, refresh_token: Option<&str>, expires_at: DateTime<Utc>) -> Result<(), AppError>;
+    async fn get_user_session(&self, session_id: &str) -> Result<Option<UserSessionRecord>, AppError>;
+    async fn update_user_session_access_token(&self, session_id: &str, access_token: &str) -> Result<(), AppError>;
+    async fn delete_user_session(&self, session_id: &str) -> Result<(), AppError>;
 }
 
 #[async_trait]
@@ -27,15 +55,21 @@ impl SessionRepositoryTrait for SessionRepository {
         }
     }
 
-    async fn add_csrf_token(&self, session_id: &str, csrf_token: &str) -> Result<(), AppError> {
-        let expires_at = Utc::now() + Duration::hours(1);
+    #[tracing::instrument(skip_all, fields(provider = csrf_session.provider))]
+    async fn add_csrf_token(&self, session_id: &str, csrf_session: &CsrfSession) -> Result<(), AppError> {
+        let expires_at = Utc::now() + Duration::minutes(CSRF_SESSION_LIFETIME_MINUTES);
         sqlx::query!(
             r#"
-                INSERT INTO sessions (session_id, csrf_token, expires_at)
-                VALUES (?, ?, ?)
+                INSERT INTO sessions (session_id, provider, csrf_token, pkce_verifier, nonce, link_user_id, return_to, expires_at)
+                VALUES (?, ?, ?, ?, ?, ?, ?, ?)
                 "#,
             session_id,
-            csrf_token,
+            csrf_session.provider,
+            csrf_session.csrf_token,
+            csrf_session.pkce_verifier,
+            csrf_session.nonce,
+            csrf_session.link_user_id,
+            csrf_session.return_to,
             expires_at
         )
         .execute(self.db_conn.get_pool())
@@ -44,6 +78,7 @@ impl SessionRepositoryTrait for SessionRepository {
         Ok(())
     }
     
+    #[tracing::instrument(skip_all)]
     async fn expire_session(&self, session_id: &str) -> Result<(), AppError> {
         sqlx::query!(
             r#"
@@ -59,17 +94,165 @@ impl SessionRepositoryTrait for SessionRepository {
         Ok(())
     }
 
-    async fn get_csrf_token_by_session_id (&self, session_id: &str) -> Result<String, AppError> {
-        let session = sqlx::query!(
+    /// Deletes up to `limit` expired or already used CSRF sessions, oldest first, and returns
+    /// how many were deleted.
+    #[tracing::instrument(skip_all, fields(limit = limit))]
+    async fn delete_expired_csrf_sessions(&self, limit: u32) -> Result<u64, AppError> {
+        let result = sqlx::query!(
+            r#"
+                DELETE FROM sessions
+                WHERE expires_at <= NOW() OR used_at IS NOT NULL
+                ORDER BY expires_at
+                LIMIT ?
+            "#,
+            limit
+        )
+        .execute(self.db_conn.get_pool())
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn get_csrf_session_by_session_id(&self, session_id: &str) -> Result<CsrfSession, AppError> {
+        let session = sqlx::query_as!(
+            CsrfSession,
             r#"
-                SELECT csrf_token FROM sessions WHERE session_id = ? AND expires_at > NOW()
+                SELECT
+                    provider,
+                    csrf_token,
+                    pkce_verifier,
+                    nonce,
+                    CAST(link_user_id as unsigned) AS link_user_id,
+                    return_to
+                FROM sessions
+                WHERE session_id = ? AND expires_at > NOW() AND used_at IS NULL
             "#,
             session_id
         )
         .fetch_one(self.db_conn.get_pool())
         .await?;
     
-        Ok(session.csrf_token)
+        Ok(session)
+    }
+
+    /// Returns the login session and marks it used in one transaction. The row stays locked
+    /// until the update commits, so of two concurrent callbacks with the same state only
+    /// one gets the session; the other re-reads the row once it is unlocked and finds it
+    /// used. `used_at` is checked rather than `expires_at`, since `NOW()` is fixed per
+    /// statement and only has second precision. `None` for unknown, expired or already
+    /// used sessions.
+    #[tracing::instrument(skip_all)]
+    async fn consume_csrf_token(&self, session_id: &str) -> Result<Option<CsrfSession>, AppError> {
+        let mut transaction = self.db_conn.get_pool().begin().await?;
+
+        let session = sqlx::query_as!(
+            CsrfSession,
+            r#"
+                SELECT
+                    provider,
+                    csrf_token,
+                    pkce_verifier,
+                    nonce,
+                    CAST(link_user_id as unsigned) AS link_user_id,
+                    return_to
+                FROM sessions
+                WHERE session_id = ? AND expires_at > NOW() AND used_at IS NULL
+                FOR UPDATE
+            "#,
+            session_id
+        )
+        .fetch_optional(&mut *transaction)
+        .await?;
+        let Some(session) = session else {
+            return Ok(None);
+        };
+
+        sqlx::query!(
+            r#"
+                UPDATE sessions
+                SET used_at = NOW()
+                WHERE session_id = ?
+            "#,
+            session_id
+        )
+        .execute(&mut *transaction)
+        .await?;
+
+        transaction.commit().await?;
+        Ok(Some(session))
+    }
+
+    #[tracing::instrument(skip_all, fields(user_id = user_id, provider = provider))]
+    async fn add_user_session(&self, session_id: &str, user_id: u64, provider: &str, access_token: &str, refresh_token: Option<&str>, expires_at: DateTime<Utc>) -> Result<(), AppError> {
+        sqlx::query!(
+            r#"
+                INSERT INTO user_sessions (session_id, user_id, provider, access_token, refresh_token, expires_at)
+                VALUES (?, ?, ?, ?, ?, ?)
+            "#,
+            session_id,
+            user_id,
+            provider,
+            access_token,
+            refresh_token,
+            expires_at
+        )
+        .execute(self.db_conn.get_pool())
+        .await?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn get_user_session(&self, session_id: &str) -> Result<Option<UserSessionRecord>, AppError> {
+        let user_session = sqlx::query_as!(
+            UserSessionRecord,
+            r#"
+                SELECT
+                    CAST(user_id as unsigned) AS user_id,
+                    provider,
+                    access_token,
+                    refresh_token
+                FROM user_sessions
+                WHERE session_id = ? AND expires_at > NOW()
+            "#,
+            session_id
+        )
+        .fetch_optional(self.db_conn.get_pool())
+        .await?;
+
+        Ok(user_session)
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn update_user_session_access_token(&self, session_id: &str, access_token: &str) -> Result<(), AppError> {
+        sqlx::query!(
+            r#"
+                UPDATE user_sessions
+                SET access_token = ?
+                WHERE session_id = ?
+            "#,
+            access_token,
+            session_id
+        )
+        .execute(self.db_conn.get_pool())
+        .await?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn delete_user_session(&self, session_id: &str) -> Result<(), AppError> {
+        sqlx::query!(
+            r#"
+                DELETE FROM user_sessions WHERE session_id = ?
+            "#,
+            session_id
+        )
+        .execute(self.db_conn.get_pool())
+        .await?;
+
+        Ok(())
     }
 }
 
@@ -77,49 +260,92 @@ impl SessionRepositoryTrait for SessionRepository {
 mod tests {
     use std::sync::Arc;
 
-    use ::chrono::{DateTime, Utc};
+    use ::chrono::{DateTime, Duration, Utc};
     use sqlx::MySqlPool;
 
     use crate::repository::session_repository::SessionRepositoryTrait;
     use crate::config::database::Database;
 
-    use super::SessionRepository;
+    use super::{CsrfSession, SessionRepository, UserSessionRecord};
 
     async fn get_session_repository(db: MySqlPool) -> SessionRepository {
         let db_conn = Database { pool: db };
         SessionRepository::new(&Arc::new(db_conn))
     }
 
+    fn csrf_session(link_user_id: Option<u64>) -> CsrfSession {
+        CsrfSession {
+            provider: "github".to_string(),
+            csrf_token: "eQ5MCnz-erkK9Xfm4O3JRA".to_string(),
+            pkce_verifier: Some("kR2dAPxVbRYJmYF5t0sUrnXw".to_string()),
+            nonce: Some("N1bTq3mVx8".to_string()),
+            link_user_id,
+            return_to: None,
+        }
+    }
+
     #[sqlx::test]
     async fn test_add_csrf_token(db: MySqlPool) {
         let session_repository = get_session_repository(db).await;
 
-        let response = session_repository.add_csrf_token("8M2q73XaSqa67eE8Zi", "eQ5MCnz-erkK9Xfm4O3JRA").await;
+        let response = session_repository.add_csrf_token("8M2q73XaSqa67eE8Zi", &csrf_session(None)).await;
         assert!(response.is_ok());
     }
 
+    #[sqlx::test]
+    async fn test_add_csrf_token_pkce_verifier_round_trip(db: MySqlPool) {
+        let session_repository = get_session_repository(db).await;
+
+        let _ = session_repository.add_csrf_token("8M2q73XaSqa67eE8Zi", &csrf_session(None)).await;
+
+        let stored = session_repository.get_csrf_session_by_session_id("8M2q73XaSqa67eE8Zi").await.unwrap();
+        assert_eq!(stored, csrf_session(None));
+    }
+
+    #[sqlx::test]
+    async fn test_add_csrf_token_link_user_id_round_trip(db: MySqlPool) {
+        let session_repository = get_session_repository(db).await;
+
+        let _ = session_repository.add_csrf_token("8M2q73XaSqa67eE8Zi", &csrf_session(Some(1))).await;
+
+        let csrf_session = session_repository.get_csrf_session_by_session_id("8M2q73XaSqa67eE8Zi").await.unwrap();
+        assert_eq!(csrf_session.link_user_id, Some(1));
+    }
+
+    #[sqlx::test]
+    async fn test_add_csrf_token_return_to_round_trip(db: MySqlPool) {
+        let session_repository = get_session_repository(db).await;
+        let return_to = CsrfSession { return_to: Some("/account/identities?tab=linked".to_string()), ..csrf_session(None) };
+
+        session_repository.add_csrf_token("8M2q73XaSqa67eE8Zi", &return_to).await.unwrap();
+
+        let consumed = session_repository.consume_csrf_token("8M2q73XaSqa67eE8Zi").await.unwrap().unwrap();
+        assert_eq!(consumed.return_to.as_deref(), Some("/account/identities?tab=linked"));
+    }
+
     #[sqlx::test(fixtures("./../../tests/fixtures/session.sql"))]
-    async fn test_get_csrf_token_by_session_id(db: MySqlPool) {
+    async fn test_get_csrf_session_by_session_id(db: MySqlPool) {
         let session_repository = get_session_repository(db).await;
 
-        let csrf_token = session_repository.get_csrf_token_by_session_id("test_session_id").await;
-        assert!(csrf_token.is_ok());
-        assert_eq!(csrf_token.unwrap(), "test_csrf_token");
+        let csrf_session = session_repository.get_csrf_session_by_session_id("test_session_id").await;
+        let csrf_session = csrf_session.unwrap();
+        assert_eq!(csrf_session.csrf_token, "test_csrf_token");
+        assert_eq!(csrf_session.provider, "google");
     }
 
     #[sqlx::test(fixtures("./../../tests/fixtures/session.sql"))]
-    async fn test_get_csrf_token_by_expired_session_id(db: MySqlPool) {
+    async fn test_get_csrf_session_by_expired_session_id(db: MySqlPool) {
         let session_repository = get_session_repository(db).await;
 
-        let result = session_repository.get_csrf_token_by_session_id("expired_session_id").await;
+        let result = session_repository.get_csrf_session_by_session_id("expired_session_id").await;
         assert!(result.is_err());
     }
 
     #[sqlx::test(fixtures("./../../tests/fixtures/session.sql"))]
-    async fn test_get_csrf_token_by_non_existent_session_id(db: MySqlPool) {
+    async fn test_get_csrf_session_by_non_existent_session_id(db: MySqlPool) {
         let session_repository = get_session_repository(db).await;
 
-        let result = session_repository.get_csrf_token_by_session_id("non_existent_session_id").await;
+        let result = session_repository.get_csrf_session_by_session_id("non_existent_session_id").await;
         assert!(result.is_err());
     }
 
@@ -144,4 +370,130 @@ mod tests {
 
         assert!(session.expires_at.unwrap() < current_time);
     }
+
+    #[sqlx::test(fixtures("./../../tests/fixtures/users.sql"))]
+    async fn test_add_and_get_user_session(db: MySqlPool) {
+        let session_repository = get_session_repository(db).await;
+
+        let response = session_repository.add_user_session("user_session_id", 1, "google", "encrypted_access", Some("encrypted_refresh"), Utc::now() + Duration::days(1)).await;
+        assert!(response.is_ok());
+
+        let user_session = session_repository.get_user_session("user_session_id").await.unwrap();
+        assert_eq!(user_session, Some(UserSessionRecord {
+            user_id: 1,
+            provider: "google".to_string(),
+            access_token: "encrypted_access".to_string(),
+            refresh_token: Some("encrypted_refresh".to_string()),
+        }));
+    }
+
+    #[sqlx::test(fixtures("./../../tests/fixtures/users.sql"))]
+    async fn test_add_user_session_without_refresh_token(db: MySqlPool) {
+        let session_repository = get_session_repository(db).await;
+
+        let _ = session_repository.add_user_session("user_session_id", 1, "github", "encrypted_access", None, Utc::now() + Duration::days(1)).await;
+
+        let user_session = session_repository.get_user_session("user_session_id").await.unwrap().unwrap();
+        assert_eq!(user_session.provider, "github");
+        assert!(user_session.refresh_token.is_none());
+    }
+
+    #[sqlx::test(fixtures("./../../tests/fixtures/users.sql"))]
+    async fn test_get_expired_user_session(db: MySqlPool) {
+        let session_repository = get_session_repository(db).await;
+
+        let _ = session_repository.add_user_session("user_session_id", 1, "google", "encrypted_access", Some("encrypted_refresh"), Utc::now() - Duration::minutes(1)).await;
+
+        let user_session = session_repository.get_user_session("user_session_id").await.unwrap();
+        assert!(user_session.is_none());
+    }
+
+    #[sqlx::test(fixtures("./../../tests/fixtures/users.sql"))]
+    async fn test_update_user_session_access_token(db: MySqlPool) {
+        let session_repository = get_session_repository(db).await;
+
+        let _ = session_repository.add_user_session("user_session_id", 1, "google", "encrypted_access", Some("encrypted_refresh"), Utc::now() + Duration::days(1)).await;
+        let response = session_repository.update_user_session_access_token("user_session_id", "new_encrypted_access").await;
+        assert!(response.is_ok());
+
+        let user_session = session_repository.get_user_session("user_session_id").await.unwrap().unwrap();
+        assert_eq!(user_session.access_token, "new_encrypted_access");
+    }
+
+    #[sqlx::test(fixtures("./../../tests/fixtures/users.sql"))]
+    async fn test_delete_user_session(db: MySqlPool) {
+        let session_repository = get_session_repository(db).await;
+
+        let _ = session_repository.add_user_session("user_session_id", 1, "google", "encrypted_access", Some("encrypted_refresh"), Utc::now() + Duration::days(1)).await;
+        let response = session_repository.delete_user_session("user_session_id").await;
+        assert!(response.is_ok());
+
+        let user_session = session_repository.get_user_session("user_session_id").await.unwrap();
+        assert!(user_session.is_none());
+    }
+
+    #[sqlx::test(fixtures("./../../tests/fixtures/session.sql"))]
+    async fn test_delete_expired_csrf_sessions(db: MySqlPool) {
+        let session_repository = get_session_repository(db).await;
+        session_repository.expire_session("no_verifier_session_id").await.unwrap();
+
+        assert_eq!(session_repository.delete_expired_csrf_sessions(1).await.unwrap(), 1);
+        assert_eq!(session_repository.delete_expired_csrf_sessions(10).await.unwrap(), 1);
+        assert_eq!(session_repository.delete_expired_csrf_sessions(10).await.unwrap(), 0);
+        assert!(session_repository.get_csrf_session_by_session_id("test_session_id").await.is_ok());
+    }
+
+    #[sqlx::test(fixtures("./../../tests/fixtures/session.sql"))]
+    async fn test_session_ids_are_unique(db: MySqlPool) {
+        let session_repository = get_session_repository(db).await;
+
+        let result = session_repository.add_csrf_token("test_session_id", &csrf_session(None)).await;
+
+        assert!(result.is_err());
+    }
+
+    #[sqlx::test(fixtures("./../../tests/fixtures/session.sql"))]
+    async fn test_consume_csrf_token_only_once(db: MySqlPool) {
+        let session_repository = get_session_repository(db).await;
+
+        let csrf_session = session_repository.consume_csrf_token("test_session_id").await.unwrap().unwrap();
+        assert_eq!(csrf_session.csrf_token, "test_csrf_token");
+
+        assert!(session_repository.consume_csrf_token("test_session_id").await.unwrap().is_none());
+        assert!(session_repository.consume_csrf_token("expired_session_id").await.unwrap().is_none());
+        assert!(session_repository.get_csrf_session_by_session_id("test_session_id").await.is_err());
+    }
+
+    #[sqlx::test(fixtures("./../../tests/fixtures/session.sql"))]
+    async fn test_used_csrf_session_stays_used_whatever_its_expiry(db: MySqlPool) {
+        let session_repository = get_session_repository(db).await;
+        session_repository.consume_csrf_token("test_session_id").await.unwrap().unwrap();
+
+        // A concurrent callback compares `expires_at` with its own, possibly earlier, `NOW()`.
+        sqlx::query("UPDATE sessions SET expires_at = NOW() + INTERVAL 1 HOUR WHERE session_id = 'test_session_id'")
+            .execute(session_repository.db_conn.get_pool())
+            .await
+            .unwrap();
+
+        assert!(session_repository.consume_csrf_token("test_session_id").await.unwrap().is_none());
+        assert!(session_repository.get_csrf_session_by_session_id("test_session_id").await.is_err());
+    }
+
+    #[sqlx::test(fixtures("./../../tests/fixtures/session.sql"))]
+    async fn test_consume_csrf_token_in_parallel(db: MySqlPool) {
+        let session_repository = get_session_repository(db).await;
+
+        let attempts = (0..8).map(|_| {
+            let session_repository = session_repository.clone();
+            tokio::spawn(async move { session_repository.consume_csrf_token("test_session_id").await })
+        });
+        let mut successes = 0;
+        for attempt in attempts.collect::<Vec<_>>() {
+            if attempt.await.unwrap().unwrap().is_some() {
+                successes += 1;
+            }
+        }
+
+        assert_eq!(successes, 1);
+    }
 }
@@ -2,6 +2,8 @@ use std::sync::Arc;
 
 use anyhow::Context;
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
 
 use crate::{config::database::Database, error::app_error::AppError, state::app_state::UserContext};
 
@@ -11,11 +13,74 @@ pub struct UserRepository {
     pub(crate) db_conn: Arc<Database>,
 }
 
+/// A user row with its role names aggregated into a comma separated list.
+pub(crate) struct UserContextRecord {
+    pub user_id: u64,
+    pub email: String,
+    pub name: String,
+    pub roles: Option<String>,
+}
+
+impl From<UserContextRecord> for UserContext {
+    fn from(record: UserContextRecord) -> Self {
+        Self {
+            user_id: record.user_id,
+            email: record.email,
+            name: record.name,
+            roles: record.roles
+                .map(|roles| roles.split(',').map(str::to_string).collect())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// Everything the API exposes about a user, including the providers they can sign in with.
+#[derive(Debug, Eq, PartialEq, Serialize)]
+pub struct UserProfile {
+    pub id: u64,
+    pub email: String,
+    pub first_name: String,
+    pub last_name: Option<String>,
+    pub created_at: Option<DateTime<Utc>>,
+    pub providers: Vec<String>,
+    pub roles: Vec<String>,
+}
+
+struct UserProfileRecord {
+    id: u64,
+    email: String,
+    first_name: String,
+    last_name: Option<String>,
+    created_at: Option<DateTime<Utc>>,
+    providers: Option<String>,
+    roles: Option<String>,
+}
+
+impl From<UserProfileRecord> for UserProfile {
+    fn from(record: UserProfileRecord) -> Self {
+        let split = |list: Option<String>| list
+            .map(|list| list.split(',').map(str::to_string).collect())
+            .unwrap_or_default();
+        Self {
+            id: record.id,
+            email: record.email,
+            first_name: record.first_name,
+            last_name: record.last_name,
+            created_at: record.created_at,
+            providers: split(record.providers),
+            roles: split(record.roles),
+        }
+    }
+}
+
 #[async_trait]
 pub trait UserRepositoryTrait {
     fn new(db_conn: &Arc<Database>) -> Self;
-    async fn add_user(&self, google_id: &str, email: &str, first_name: &str, last_name: &str) -> Result<u64, AppError>;
-    async fn find_user_by_google_id(&self, google_id: &str) -> Result<Option<UserContext>, AppError>;
+    async fn add_user(&self, email: &str, first_name: &str, last_name: &str) -> Result<u64, AppError>;
+    async fn find_user_by_email(&self, email: &str) -> Result<Option<UserContext>, AppError>;
+    async fn find_user_by_id(&self, user_id: u64) -> Result<Option<UserContext>, AppError>;
+    async fn find_user_profile(&self, user_id: u64) -> Result<Option<UserProfile>, AppError>;
+    async fn update_user_names(&self, user_id: u64, first_name: Option<&str>, last_name: Option<&str>) -> Result<(), AppError>;
 }
 
 #[async_trait]
@@ -26,14 +91,14 @@ impl UserRepositoryTrait for UserRepository {
         }
     }
 
-    async fn add_user(&self, google_id: &str, email: &str, first_name: &str, last_name: &str) -> Result<u64, AppError> {
+    #[tracing::instrument(skip_all)]
+    async fn add_user(&self, email: &str, first_name: &str, last_name: &str) -> Result<u64, AppError> {
         tracing::debug!("Creating a new user");
         let user = sqlx::query!(
             r#"
-                INSERT INTO users (google_id, email, first_name, last_name)
-                VALUES (?, ?, ?, ?)
+                INSERT INTO users (email, first_name, last_name)
+                VALUES (?, ?, ?)
             "#,
-            google_id,
             email,
             first_name,
             last_name,
@@ -45,23 +110,100 @@ impl UserRepositoryTrait for UserRepository {
         Ok(user.last_insert_id())
     }
 
-    async fn find_user_by_google_id(&self, google_id: &str) -> Result<Option<UserContext>, AppError> {
-        let user_context = sqlx::query_as!(
-            UserContext,
+    #[tracing::instrument(skip_all)]
+    async fn find_user_by_email(&self, email: &str) -> Result<Option<UserContext>, AppError> {
+        let user = sqlx::query_as!(
+            UserContextRecord,
             r#"
             SELECT 
-                CAST(id as unsigned) AS user_id, 
-                email, 
-                first_name AS name
+                CAST(users.id as unsigned) AS user_id, 
+                users.email, 
+                users.first_name AS name,
+                GROUP_CONCAT(roles.name) AS roles
             FROM users
-            WHERE google_id = ?
+            LEFT JOIN user_roles ON user_roles.user_id = users.id
+            LEFT JOIN roles ON roles.id = user_roles.role_id
+            WHERE users.email = ?
+            GROUP BY users.id
             "#,
-            google_id
+            email
         )
         .fetch_optional(self.db_conn.get_pool())
         .await?;
-    
-        Ok(user_context)
+
+        Ok(user.map(UserContext::from))
+    }
+
+    #[tracing::instrument(skip_all, fields(user_id = user_id))]
+    async fn find_user_by_id(&self, user_id: u64) -> Result<Option<UserContext>, AppError> {
+        let user = sqlx::query_as!(
+            UserContextRecord,
+            r#"
+            SELECT 
+                CAST(users.id as unsigned) AS user_id, 
+                users.email, 
+                users.first_name AS name,
+                GROUP_CONCAT(roles.name) AS roles
+            FROM users
+            LEFT JOIN user_roles ON user_roles.user_id = users.id
+            LEFT JOIN roles ON roles.id = user_roles.role_id
+            WHERE users.id = ?
+            GROUP BY users.id
+            "#,
+            user_id
+        )
+        .fetch_optional(self.db_conn.get_pool())
+        .await?;
+
+        Ok(user.map(UserContext::from))
+    }
+
+    #[tracing::instrument(skip_all, fields(user_id = user_id))]
+    async fn find_user_profile(&self, user_id: u64) -> Result<Option<UserProfile>, AppError> {
+        let profile = sqlx::query_as!(
+            UserProfileRecord,
+            r#"
+            SELECT
+                CAST(users.id as unsigned) AS id,
+                users.email,
+                users.first_name,
+                users.last_name,
+                users.created_at,
+                GROUP_CONCAT(DISTINCT user_identities.provider ORDER BY user_identities.provider) AS providers,
+                GROUP_CONCAT(DISTINCT roles.name ORDER BY roles.name) AS roles
+            FROM users
+            LEFT JOIN user_identities ON user_identities.user_id = users.id
+            LEFT JOIN user_roles ON user_roles.user_id = users.id
+            LEFT JOIN roles ON roles.id = user_roles.role_id
+            WHERE users.id = ?
+            GROUP BY users.id
+            "#,
+            user_id
+        )
+        .fetch_optional(self.db_conn.get_pool())
+        .await?;
+
+        Ok(profile.map(UserProfile::from))
+    }
+
+    /// Names left as `None` keep their current value.
+    #[tracing::instrument(skip_all, fields(user_id = user_id))]
+    async fn update_user_names(&self, user_id: u64, first_name: Option<&str>, last_name: Option<&str>) -> Result<(), AppError> {
+        sqlx::query!(
+            r#"
+                UPDATE users
+                SET first_name = COALESCE(?, first_name), last_name = COALESCE(?, last_name)
+                WHERE id = ?
+            "#,
+            first_name,
+            last_name,
+            user_id,
+        )
+        .execute(self.db_conn.get_pool())
+        .await
+        .context("Failed to update user")?;
+
+        Ok(())
     }
 }
 
@@ -84,7 +226,7 @@ mod tests {
     async fn test_add_user_valid(db: MySqlPool) {
         let user_repository = get_user_repository(db).await;
 
-        let response = user_repository.add_user("123456789", "test@lift.com", "John", "Smith").await;
+        let response = user_repository.add_user("test@lift.com", "John", "Smith").await;
         assert!(response.is_ok());
     }
 
@@ -92,25 +234,54 @@ mod tests {
     async fn test_add_user_duplicate(db: MySqlPool) {
         let user_repository = get_user_repository(db).await;
 
-        let _ = user_repository.add_user("123456789", "test@lift.com", "John", "Smith").await;
-        let response = user_repository.add_user("123456789", "test@lift.com", "John", "Smith").await;
+        let _ = user_repository.add_user("test@lift.com", "John", "Smith").await;
+        let response = user_repository.add_user("test@lift.com", "John", "Smith").await;
         assert!(response.is_err());
     }
 
-    #[sqlx::test]
-    async fn test_find_user_by_google_id_non_existent(db: MySqlPool) {
+    #[sqlx::test(fixtures("./../../tests/fixtures/users.sql"))]
+    async fn test_find_user_by_email(db: MySqlPool) {
         let user_repository = get_user_repository(db).await;
 
-        let user_context = user_repository.find_user_by_google_id("non_existent_id").await.unwrap();
+        let user_context = user_repository.find_user_by_email("TestEmail-2@lift.com").await.unwrap();
+        assert_eq!(user_context.map(|user| user.user_id), Some(2));
+
+        let user_context = user_repository.find_user_by_email("nobody@lift.com").await.unwrap();
         assert!(user_context.is_none());
     }
 
-    #[sqlx::test]
-    async fn test_find_user_by_google_id_existing(db: MySqlPool) {
+    #[sqlx::test(fixtures("./../../tests/fixtures/users.sql"))]
+    async fn test_find_user_by_id(db: MySqlPool) {
         let user_repository = get_user_repository(db).await;
 
-        let _ = user_repository.add_user("123456789", "test@lift.com", "John", "Smith").await;
-        let user_context = user_repository.find_user_by_google_id("123456789").await.unwrap();
-        assert!(user_context.is_some());
+        let user_context = user_repository.find_user_by_id(2).await.unwrap();
+        assert_eq!(user_context.map(|user| user.name), Some("Patrick".to_string()));
+
+        let user_context = user_repository.find_user_by_id(99).await.unwrap();
+        assert!(user_context.is_none());
+    }
+
+    #[sqlx::test(fixtures("./../../tests/fixtures/users.sql"))]
+    async fn test_find_user_profile(db: MySqlPool) {
+        let user_repository = get_user_repository(db).await;
+
+        let profile = user_repository.find_user_profile(1).await.unwrap().unwrap();
+        assert_eq!(profile.email, "TestEmail@lift.com");
+        assert_eq!(profile.last_name.as_deref(), Some("Gill"));
+        assert_eq!(profile.providers, vec!["google"]);
+        assert!(profile.roles.is_empty());
+
+        assert!(user_repository.find_user_profile(99).await.unwrap().is_none());
+    }
+
+    #[sqlx::test(fixtures("./../../tests/fixtures/users.sql"))]
+    async fn test_update_user_names(db: MySqlPool) {
+        let user_repository = get_user_repository(db).await;
+
+        user_repository.update_user_names(1, None, Some("Gillespie")).await.unwrap();
+
+        let profile = user_repository.find_user_profile(1).await.unwrap().unwrap();
+        assert_eq!(profile.first_name, "Tom");
+        assert_eq!(profile.last_name.as_deref(), Some("Gillespie"));
     }
 }
@@ -1,23 +1,73 @@
-use axum::{middleware, routing::get, Router};
+use axum::{middleware, routing::{get, post}, Router};
 
 use crate::{
-    handler::auth_handler::{auth_callback, google_auth, logout},
-    index,
-    middleware::auth as auth_middleware,
-    protected, AppState,
+    handler::{account_handler::{link_identity, list_identities, unlink_identity}, auth_handler::{auth_callback, jwks, logout, provider_auth, refresh, session_token}, health_handler::{healthz, readyz}, metrics_handler::{metrics, METRICS_PATH}, user_handler::{get_me, update_me}},
+    admin, index,
+    middleware::{auth as auth_middleware, log::log_request, metrics::track_metrics, request_id::propagate_request_id},
+    protected,
+    repository::role_repository::ADMIN_ROLE,
+    AppState,
 };
 
-pub fn public_routes() -> Router<AppState> {
+pub fn public_routes(app_state: AppState) -> Router<AppState> {
     Router::new()
-        .route("/", get(index))
-        .route("/auth/google", get(google_auth))
-        .route("/auth/authorized", get(auth_callback))
+        .route("/", get(index).layer(middleware::from_fn_with_state(
+            app_state.clone(),
+            auth_middleware::optional_auth,
+        )))
+        .route("/.well-known/jwks.json", get(jwks))
+        .route("/auth/refresh", post(refresh))
+        .route("/auth/token", post(session_token).layer(middleware::from_fn_with_state(
+            app_state,
+            auth_middleware::api_auth,
+        )))
+        .route("/auth/{provider}", get(provider_auth))
+        .route("/auth/{provider}/authorized", get(auth_callback))
+}
+
+/// Probes, deliberately outside every auth layer.
+pub fn health_routes() -> Router<AppState> {
+    Router::new()
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+}
+
+/// The scrape endpoint, served on its own listen address rather than the public router.
+pub fn metrics_router(app_state: AppState) -> Router {
+    Router::new()
+        .route(METRICS_PATH, get(metrics))
+        .with_state(app_state)
 }
 
 pub fn protected_routes(app_state: AppState) -> Router<AppState> {
     Router::new()
         .route("/protected", get(protected))
         .route("/logout", get(logout))
+        .route("/account/identities", get(list_identities))
+        .route("/account/identities/{provider}", post(link_identity).delete(unlink_identity))
+        .layer(middleware::from_fn_with_state(
+            app_state,
+            auth_middleware::auth,
+        ))
+}
+
+/// The versioned JSON API, nested under `/api/v1`.
+pub fn api_routes(app_state: AppState) -> Router<AppState> {
+    Router::new()
+        .route("/me", get(get_me).patch(update_me))
+        .layer(middleware::from_fn_with_state(
+            app_state,
+            auth_middleware::api_auth,
+        ))
+}
+
+pub fn admin_routes(app_state: AppState) -> Router<AppState> {
+    Router::new()
+        .route("/admin", get(admin))
+        .layer(middleware::from_fn_with_state(
+            ADMIN_ROLE,
+            auth_middleware::require_role,
+        ))
         .layer(middleware::from_fn_with_state(
             app_state,
             auth_middleware::auth,
@@ -26,7 +76,13 @@ pub fn protected_routes(app_state: AppState) -> Router<AppState> {
 
 pub async fn create_router(app_state: AppState) -> Router {
     Router::new()
-        .merge(public_routes())
+        .merge(health_routes())
+        .merge(public_routes(app_state.clone()))
         .merge(protected_routes(app_state.clone()))
+        .merge(admin_routes(app_state.clone()))
+        .nest("/api/v1", api_routes(app_state.clone()))
+        .layer(middleware::from_fn(log_request))
+        .layer(middleware::from_fn_with_state(app_state.metrics.clone(), track_metrics))
+        .layer(middleware::from_fn(propagate_request_id))
         .with_state(app_state)
 }
@@ -0,0 +1,130 @@
+use anyhow::Context;
+use axum_extra::extract::cookie::{Cookie, SameSite};
+use cookie::time::Duration;
+use http::{header::SET_COOKIE, HeaderMap, HeaderValue};
+
+use crate::{config::app_config::CookieConfig, error::app_error::AppError, repository::session_repository::CSRF_SESSION_LIFETIME_MINUTES, service::session_service::USER_SESSION_LIFETIME_DAYS};
+
+/// Points at the login (CSRF) state of a login in progress.
+pub const LOGIN_STATE_COOKIE_NAME: &str = "SESSION";
+/// Points at the signed-in user's session.
+pub const USER_SESSION_COOKIE_NAME: &str = "USER_SESSION";
+
+/// Builds every cookie the application sets, so they all share the configured attributes
+/// and expire together with what they point at. Names are the constants above with the
+/// configured prefix; use [`CookiePolicy::name`] to read them back.
+#[derive(Clone, Debug)]
+pub struct CookiePolicy {
+    name_prefix: String,
+    domain: Option<String>,
+    secure: bool,
+    same_site: SameSite,
+}
+
+impl Default for CookiePolicy {
+    fn default() -> Self {
+        Self::from_config(&CookieConfig::default())
+    }
+}
+
+impl CookiePolicy {
+    /// Expects a validated config; an unknown `same_site` falls back to `Lax`.
+    pub fn from_config(config: &CookieConfig) -> Self {
+        let same_site = match config.same_site.to_ascii_lowercase().as_str() {
+            "strict" => SameSite::Strict,
+            "none" => SameSite::None,
+            _ => SameSite::Lax,
+        };
+        Self {
+            name_prefix: config.name_prefix.clone(),
+            domain: config.domain.clone(),
+            secure: config.secure,
+            same_site,
+        }
+    }
+
+    pub fn name(&self, name: &str) -> String {
+        format!("{}{}", self.name_prefix, name)
+    }
+
+    pub fn login_state(&self, session_id: String) -> Cookie<'static> {
+        self.build(LOGIN_STATE_COOKIE_NAME, session_id, Duration::minutes(CSRF_SESSION_LIFETIME_MINUTES))
+    }
+
+    pub fn user_session(&self, session_id: String) -> Cookie<'static> {
+        self.build(USER_SESSION_COOKIE_NAME, session_id, Duration::days(USER_SESSION_LIFETIME_DAYS))
+    }
+
+    /// Deletes the cookie from the browser. It carries the same attributes as the cookie it
+    /// replaces, otherwise browsers treat it as a different cookie.
+    pub fn removal(&self, name: &str) -> Cookie<'static> {
+        self.build(name, String::new(), Duration::ZERO)
+    }
+
+    pub fn append(&self, headers: &mut HeaderMap, cookie: Cookie<'static>) -> Result<(), AppError> {
+        let value = HeaderValue::from_str(&cookie.to_string()).context("Failed to parse header value")?;
+        headers.append(SET_COOKIE, value);
+        Ok(())
+    }
+
+    fn build(&self, name: &str, value: String, max_age: Duration) -> Cookie<'static> {
+        let mut cookie = Cookie::build((self.name(name), value))
+            .http_only(true)
+            .secure(self.secure)
+            .same_site(self.same_site)
+            .path("/")
+            .max_age(max_age)
+            .build();
+        if let Some(domain) = &self.domain {
+            cookie.set_domain(domain.clone());
+        }
+        cookie
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::HeaderMap;
+
+    use crate::config::app_config::CookieConfig;
+
+    use super::{CookiePolicy, USER_SESSION_COOKIE_NAME};
+
+    #[test]
+    fn test_default_cookies() {
+        let policy = CookiePolicy::default();
+
+        assert_eq!(policy.login_state("abc".to_string()).to_string(), "SESSION=abc; HttpOnly; SameSite=Lax; Secure; Path=/; Max-Age=3600");
+        assert_eq!(policy.user_session("def".to_string()).to_string(), "USER_SESSION=def; HttpOnly; SameSite=Lax; Secure; Path=/; Max-Age=604800");
+        assert_eq!(policy.removal(USER_SESSION_COOKIE_NAME).to_string(), "USER_SESSION=; HttpOnly; SameSite=Lax; Secure; Path=/; Max-Age=0");
+    }
+
+    #[test]
+    fn test_configured_cookies() {
+        let host_prefixed = CookiePolicy::from_config(&CookieConfig {
+            name_prefix: "__Host-".to_string(),
+            same_site: "strict".to_string(),
+            ..CookieConfig::default()
+        });
+        let with_domain = CookiePolicy::from_config(&CookieConfig {
+            domain: Some("lift.com".to_string()),
+            secure: false,
+            ..CookieConfig::default()
+        });
+
+        assert_eq!(host_prefixed.name(USER_SESSION_COOKIE_NAME), "__Host-USER_SESSION");
+        assert_eq!(host_prefixed.user_session("def".to_string()).to_string(), "__Host-USER_SESSION=def; HttpOnly; SameSite=Strict; Secure; Path=/; Max-Age=604800");
+        assert_eq!(with_domain.login_state("abc".to_string()).to_string(), "SESSION=abc; HttpOnly; SameSite=Lax; Path=/; Domain=lift.com; Max-Age=3600");
+    }
+
+    #[test]
+    fn test_append_keeps_every_cookie() {
+        let policy = CookiePolicy::default();
+        let mut headers = HeaderMap::new();
+
+        policy.append(&mut headers, policy.user_session("def".to_string())).unwrap();
+        policy.append(&mut headers, policy.removal("SESSION")).unwrap();
+
+        assert_eq!(headers.get_all(http::header::SET_COOKIE).iter().count(), 2);
+    }
+}
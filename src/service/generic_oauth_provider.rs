@@ -0,0 +1,613 @@
+use std::fmt;
+
+use anyhow::Context;
+use async_trait::async_trait;
+use oauth2::{basic::BasicClient, reqwest::async_http_client, AccessToken, AuthUrl, AuthorizationCode, ClientId, ClientSecret, CsrfToken, PkceCodeChallenge, PkceCodeVerifier, RedirectUrl, RefreshToken, RevocationUrl, Scope, StandardRevocableToken, TokenResponse, TokenUrl};
+use reqwest::{header::USER_AGENT, Client};
+use serde::{de::DeserializeOwned, Deserialize};
+
+use crate::{config::app_config::{GenericProviderConfig, ProvidersConfig, REDACTED}, error::{app_error::AppError, token_error::TokenError}, service::oauth_provider::{AccessTokenInfo, AuthorisationRequest, NormalizedProfile, OAuthProvider, ProviderLogin, ProviderTokens}};
+
+/// How a provider shapes its user profile response.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ProfileFormat {
+    GitHub,
+    Microsoft,
+    GitLab,
+    Oidc,
+}
+
+impl ProfileFormat {
+    pub fn provider_name(&self) -> &'static str {
+        match self {
+            ProfileFormat::GitHub => "github",
+            ProfileFormat::Microsoft => "microsoft",
+            ProfileFormat::GitLab => "gitlab",
+            ProfileFormat::Oidc => "oidc",
+        }
+    }
+
+    fn config<'a>(&self, providers: &'a ProvidersConfig) -> &'a GenericProviderConfig {
+        match self {
+            ProfileFormat::GitHub => &providers.github,
+            ProfileFormat::Microsoft => &providers.microsoft,
+            ProfileFormat::GitLab => &providers.gitlab,
+            ProfileFormat::Oidc => &providers.oidc,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct GenericProviderSettings {
+    pub format: ProfileFormat,
+    pub client_id: String,
+    pub client_secret: String,
+    pub redirect_url: String,
+    pub auth_url: String,
+    pub token_url: String,
+    pub revocation_url: Option<String>,
+    pub userinfo_url: String,
+    pub scopes: Vec<String>,
+    /// Treat the profile email as verified when the profile has no `email_verified` claim.
+    pub trust_email: bool,
+}
+
+impl fmt::Debug for GenericProviderSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GenericProviderSettings")
+            .field("format", &self.format)
+            .field("client_id", &self.client_id)
+            .field("client_secret", &REDACTED)
+            .field("redirect_url", &self.redirect_url)
+            .field("auth_url", &self.auth_url)
+            .field("token_url", &self.token_url)
+            .field("revocation_url", &self.revocation_url)
+            .field("userinfo_url", &self.userinfo_url)
+            .field("scopes", &self.scopes)
+            .field("trust_email", &self.trust_email)
+            .finish()
+    }
+}
+
+impl GenericProviderSettings {
+    /// Endpoints and scopes that are not configured default to the provider's public values.
+    /// Returns `None` when the provider has no client id configured.
+    pub fn from_config(format: ProfileFormat, providers: &ProvidersConfig) -> Result<Option<Self>, AppError> {
+        let config = format.config(providers);
+        let Some(client_id) = config.client_id.clone() else {
+            return Ok(None);
+        };
+
+        let defaults = default_endpoints(format, config);
+        let required = |value: &Option<String>, default: Option<String>, key: &str| {
+            value.clone()
+                .or(default)
+                .ok_or_else(|| AppError::ConfigurationError(format!("providers.{}.{} is required", format.provider_name(), key)))
+        };
+
+        Ok(Some(Self {
+            format,
+            client_id,
+            client_secret: required(&config.client_secret, None, "client_secret")?,
+            redirect_url: required(&config.redirect_uri, None, "redirect_uri")?,
+            auth_url: required(&config.auth_uri, defaults.auth_url, "auth_uri")?,
+            token_url: required(&config.token_uri, defaults.token_url, "token_uri")?,
+            revocation_url: config.revocation_uri.clone().or(defaults.revocation_url),
+            userinfo_url: required(&config.userinfo_uri, defaults.userinfo_url, "userinfo_uri")?,
+            scopes: config.scopes
+                .as_deref()
+                .unwrap_or(&defaults.scopes)
+                .split_whitespace()
+                .map(str::to_string)
+                .collect(),
+            trust_email: config.trust_email.unwrap_or(defaults.trust_email),
+        }))
+    }
+}
+
+struct DefaultEndpoints {
+    auth_url: Option<String>,
+    token_url: Option<String>,
+    revocation_url: Option<String>,
+    userinfo_url: Option<String>,
+    scopes: String,
+    trust_email: bool,
+}
+
+fn default_endpoints(format: ProfileFormat, config: &GenericProviderConfig) -> DefaultEndpoints {
+    match format {
+        ProfileFormat::GitHub => DefaultEndpoints {
+            auth_url: Some("https://github.com/login/oauth/authorize".to_string()),
+            token_url: Some("https://github.com/login/oauth/access_token".to_string()),
+            revocation_url: None,
+            userinfo_url: Some("https://api.github.com/user".to_string()),
+            scopes: "read:user user:email".to_string(),
+            trust_email: false,
+        },
+        ProfileFormat::Microsoft => {
+            let tenant = config.tenant.as_deref().unwrap_or("common");
+            let multi_tenant = ["common", "organizations", "consumers"].contains(&tenant);
+            DefaultEndpoints {
+                auth_url: Some(format!("https://login.microsoftonline.com/{tenant}/oauth2/v2.0/authorize")),
+                token_url: Some(format!("https://login.microsoftonline.com/{tenant}/oauth2/v2.0/token")),
+                revocation_url: None,
+                userinfo_url: Some("https://graph.microsoft.com/oidc/userinfo".to_string()),
+                scopes: "openid profile email offline_access".to_string(),
+                trust_email: !multi_tenant,
+            }
+        }
+        ProfileFormat::GitLab => {
+            let base_url = config.base_url.as_deref().unwrap_or("https://gitlab.com");
+            DefaultEndpoints {
+                auth_url: Some(format!("{base_url}/oauth/authorize")),
+                token_url: Some(format!("{base_url}/oauth/token")),
+                revocation_url: Some(format!("{base_url}/oauth/revoke")),
+                userinfo_url: Some(format!("{base_url}/oauth/userinfo")),
+                scopes: "openid profile email".to_string(),
+                trust_email: false,
+            }
+        }
+        ProfileFormat::Oidc => DefaultEndpoints {
+            auth_url: None,
+            token_url: None,
+            revocation_url: None,
+            userinfo_url: None,
+            scopes: "openid profile email".to_string(),
+            trust_email: false,
+        },
+    }
+}
+
+/// An authorization code + PKCE provider whose identity comes from a profile endpoint.
+/// Used for GitHub, Microsoft Entra, GitLab and any standards-compliant OIDC provider.
+/// No id token is verified, the profile is fetched with the access token from the code
+/// exchange, so no `nonce` is sent either.
+#[derive(Clone)]
+pub struct GenericOAuthProvider {
+    format: ProfileFormat,
+    oauth_client: BasicClient,
+    http_client: Client,
+    userinfo_url: String,
+    scopes: Vec<String>,
+    trust_email: bool,
+}
+
+impl GenericOAuthProvider {
+    pub fn new(settings: GenericProviderSettings) -> Result<Self, AppError> {
+        let mut oauth_client = BasicClient::new(
+                ClientId::new(settings.client_id),
+                Some(ClientSecret::new(settings.client_secret)),
+                AuthUrl::new(settings.auth_url).context("failed to create new authorization server URL")?,
+                Some(TokenUrl::new(settings.token_url).context("failed to create new token endpoint URL")?),
+            )
+            .set_redirect_uri(
+                RedirectUrl::new(settings.redirect_url).context("failed to create new redirection URL")?,
+            );
+        if let Some(revocation_url) = settings.revocation_url {
+            oauth_client = oauth_client.set_revocation_uri(
+                RevocationUrl::new(revocation_url).context("failed to create new revocation URL")?,
+            );
+        }
+
+        Ok(Self {
+            format: settings.format,
+            oauth_client,
+            http_client: Client::new(),
+            userinfo_url: settings.userinfo_url,
+            scopes: settings.scopes,
+            trust_email: settings.trust_email,
+        })
+    }
+
+    async fn fetch_json<T: DeserializeOwned>(&self, url: &str, access_token: &str) -> Result<T, AppError> {
+        let response = self.http_client
+            .get(url)
+            .bearer_auth(access_token)
+            .header(USER_AGENT, env!("CARGO_PKG_NAME"))
+            .send()
+            .await
+            .with_context(|| format!("Failed to send request to {} profile endpoint", self.name()))?;
+
+        if !response.status().is_success() {
+            return Err(TokenError::InvalidToken.into());
+        }
+
+        Ok(response
+            .json::<T>()
+            .await
+            .with_context(|| format!("Failed to parse {} profile response", self.name()))?)
+    }
+
+    async fn fetch_profile(&self, access_token: &str) -> Result<NormalizedProfile, AppError> {
+        match self.format {
+            ProfileFormat::GitHub => {
+                let user: GitHubUser = self.fetch_json(&self.userinfo_url, access_token).await?;
+                let emails: Vec<GitHubEmail> = self.fetch_json(&github_emails_url(&self.userinfo_url), access_token).await?;
+                map_github_profile(user, &emails)
+            }
+            ProfileFormat::Microsoft | ProfileFormat::GitLab | ProfileFormat::Oidc => {
+                let user_info: OidcUserInfo = self.fetch_json(&self.userinfo_url, access_token).await?;
+                map_oidc_profile(self.format, user_info, self.trust_email)
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl OAuthProvider for GenericOAuthProvider {
+    fn name(&self) -> &str {
+        self.format.provider_name()
+    }
+
+    async fn authorisation_request(&self) -> Result<AuthorisationRequest, AppError> {
+        let (pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
+        let mut request = self.oauth_client
+            .authorize_url(CsrfToken::new_random)
+            .set_pkce_challenge(pkce_challenge);
+        for scope in &self.scopes {
+            request = request.add_scope(Scope::new(scope.clone()));
+        }
+        let (url, csrf_token) = request.url();
+
+        Ok(AuthorisationRequest { url, csrf_token, pkce_verifier, nonce: None })
+    }
+
+    async fn complete_login(&self, code: String, pkce_verifier: PkceCodeVerifier, _nonce: Option<&str>) -> Result<ProviderLogin, AppError> {
+        let token = self.oauth_client
+            .exchange_code(AuthorizationCode::new(code))
+            .set_pkce_verifier(pkce_verifier)
+            .request_async(async_http_client)
+            .await
+            .with_context(|| format!("failed in sending request to {} authorization server", self.name()))?;
+
+        let access_token = token.access_token().secret().to_string();
+        let profile = self.fetch_profile(&access_token).await?;
+
+        Ok(ProviderLogin {
+            tokens: ProviderTokens {
+                access_token,
+                refresh_token: token.refresh_token().map(|refresh_token| refresh_token.secret().to_string()),
+            },
+            profile,
+        })
+    }
+
+    async fn validate_access_token(&self, access_token: &str) -> Result<AccessTokenInfo, AppError> {
+        let profile = self.fetch_profile(access_token).await?;
+        Ok(AccessTokenInfo {
+            subject: profile.subject,
+            expires_in: None,
+        })
+    }
+
+    async fn refresh(&self, refresh_token: &str) -> Result<String, AppError> {
+        let token_response = self.oauth_client
+            .exchange_refresh_token(&RefreshToken::new(refresh_token.to_string()))
+            .request_async(async_http_client)
+            .await
+            .with_context(|| format!("failed to refresh {} access token", self.name()))?;
+
+        Ok(token_response.access_token().secret().to_string())
+    }
+
+    async fn revoke(&self, token: &str) -> Result<(), AppError> {
+        let revocable_token: StandardRevocableToken = AccessToken::new(token.to_string()).into();
+        let request = match self.oauth_client.revoke_token(revocable_token) {
+            Ok(request) => request,
+            Err(_) => {
+                tracing::debug!("{} does not support token revocation", self.name());
+                return Ok(());
+            }
+        };
+
+        if let Err(error) = request.request_async(async_http_client).await {
+            tracing::debug!("{} token revocation failed: {:?}", self.name(), error);
+            return Err(TokenError::InvalidToken.into());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct GitHubUser {
+    id: u64,
+    login: String,
+    name: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GitHubEmail {
+    email: String,
+    primary: bool,
+    verified: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct OidcUserInfo {
+    sub: String,
+    email: Option<String>,
+    email_verified: Option<bool>,
+    given_name: Option<String>,
+    family_name: Option<String>,
+    name: Option<String>,
+}
+
+fn github_emails_url(userinfo_url: &str) -> String {
+    format!("{}/emails", userinfo_url.trim_end_matches('/'))
+}
+
+fn split_name(name: &str) -> (String, String) {
+    match name.trim().split_once(char::is_whitespace) {
+        Some((given_name, family_name)) => (given_name.to_string(), family_name.trim().to_string()),
+        None => (name.trim().to_string(), String::new()),
+    }
+}
+
+/// Prefers the primary address if it is verified, then any verified one, so an unverified
+/// primary does not hide a verified secondary. Unverified addresses are a last resort.
+fn map_github_profile(user: GitHubUser, emails: &[GitHubEmail]) -> Result<NormalizedProfile, AppError> {
+    let email = emails
+        .iter()
+        .find(|email| email.primary && email.verified)
+        .or_else(|| emails.iter().find(|email| email.verified))
+        .or_else(|| emails.iter().find(|email| email.primary))
+        .ok_or_else(|| AppError::BadRequest("github did not return an email address".to_string()))?;
+    let (given_name, family_name) = split_name(user.name.as_deref().unwrap_or(&user.login));
+
+    Ok(NormalizedProfile {
+        provider: ProfileFormat::GitHub.provider_name().to_string(),
+        subject: user.id.to_string(),
+        email: email.email.clone(),
+        email_verified: email.verified,
+        given_name,
+        family_name,
+    })
+}
+
+/// A missing `email_verified` claim falls back to `trust_email`; an explicit `false` never does.
+fn map_oidc_profile(format: ProfileFormat, user_info: OidcUserInfo, trust_email: bool) -> Result<NormalizedProfile, AppError> {
+    let email = user_info.email
+        .ok_or_else(|| AppError::BadRequest(format!("{} did not return an email address", format.provider_name())))?;
+    let (given_name, family_name) = match (user_info.given_name, user_info.family_name) {
+        (Some(given_name), family_name) => (given_name, family_name.unwrap_or_default()),
+        (None, _) => split_name(user_info.name.as_deref().unwrap_or(&email)),
+    };
+
+    Ok(NormalizedProfile {
+        provider: format.provider_name().to_string(),
+        subject: user_info.sub,
+        email,
+        email_verified: user_info.email_verified.unwrap_or(trust_email),
+        given_name,
+        family_name,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{routing::{get, post}, Json, Router};
+    use oauth2::PkceCodeVerifier;
+    use serde_json::json;
+
+    use crate::{assert_error, config::app_config::{GenericProviderConfig, ProvidersConfig}, error::app_error::AppError, service::oauth_provider::{NormalizedProfile, OAuthProvider}};
+
+    use super::{map_github_profile, map_oidc_profile, GenericOAuthProvider, GenericProviderSettings, GitHubEmail, GitHubUser, OidcUserInfo, ProfileFormat};
+
+    fn configured(config: GenericProviderConfig) -> GenericProviderConfig {
+        GenericProviderConfig {
+            client_id: Some("client-id".to_string()),
+            client_secret: Some("client-secret".to_string()),
+            redirect_uri: Some("http://127.0.0.1:3000/auth/microsoft/authorized".to_string()),
+            ..config
+        }
+    }
+
+    #[test]
+    fn test_settings_from_config_apply_defaults() {
+        let providers = ProvidersConfig {
+            microsoft: configured(GenericProviderConfig { tenant: Some("lift".to_string()), ..Default::default() }),
+            ..Default::default()
+        };
+
+        let settings = GenericProviderSettings::from_config(ProfileFormat::Microsoft, &providers).unwrap().unwrap();
+
+        assert_eq!(settings.auth_url, "https://login.microsoftonline.com/lift/oauth2/v2.0/authorize");
+        assert_eq!(settings.scopes, vec!["openid", "profile", "email", "offline_access"]);
+        assert!(settings.trust_email);
+        assert!(GenericProviderSettings::from_config(ProfileFormat::GitHub, &providers).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_settings_from_config_trust_email() {
+        let settings = |microsoft: GenericProviderConfig| {
+            let providers = ProvidersConfig { microsoft: configured(microsoft), ..Default::default() };
+            GenericProviderSettings::from_config(ProfileFormat::Microsoft, &providers).unwrap().unwrap()
+        };
+
+        assert!(!settings(GenericProviderConfig::default()).trust_email);
+        assert!(!settings(GenericProviderConfig { tenant: Some("organizations".to_string()), ..Default::default() }).trust_email);
+        assert!(settings(GenericProviderConfig { trust_email: Some(true), ..Default::default() }).trust_email);
+        assert!(!settings(GenericProviderConfig { tenant: Some("lift".to_string()), trust_email: Some(false), ..Default::default() }).trust_email);
+    }
+
+    #[test]
+    fn test_settings_from_config_requires_oidc_endpoints() {
+        let providers = ProvidersConfig { oidc: configured(GenericProviderConfig::default()), ..Default::default() };
+
+        let result = GenericProviderSettings::from_config(ProfileFormat::Oidc, &providers);
+
+        assert_error!(result, &AppError::ConfigurationError(String::new()));
+    }
+
+    fn github_user(name: Option<&str>) -> GitHubUser {
+        GitHubUser { id: 583231, login: "octocat".to_string(), name: name.map(str::to_string) }
+    }
+
+    #[test]
+    fn test_map_github_profile_uses_primary_email() {
+        let emails = vec![
+            GitHubEmail { email: "old@lift.com".to_string(), primary: false, verified: true },
+            GitHubEmail { email: "octo@lift.com".to_string(), primary: true, verified: true },
+        ];
+
+        let profile = map_github_profile(github_user(Some("Mona Lisa Octocat")), &emails).unwrap();
+
+        assert_eq!(profile, NormalizedProfile {
+            provider: "github".to_string(),
+            subject: "583231".to_string(),
+            email: "octo@lift.com".to_string(),
+            email_verified: true,
+            given_name: "Mona".to_string(),
+            family_name: "Lisa Octocat".to_string(),
+        });
+    }
+
+    #[test]
+    fn test_map_github_profile_skips_unverified_primary_email() {
+        let emails = vec![
+            GitHubEmail { email: "new@lift.com".to_string(), primary: true, verified: false },
+            GitHubEmail { email: "octo@lift.com".to_string(), primary: false, verified: true },
+        ];
+
+        let profile = map_github_profile(github_user(None), &emails).unwrap();
+
+        assert_eq!(profile.email, "octo@lift.com");
+        assert!(profile.email_verified);
+    }
+
+    #[test]
+    fn test_map_github_profile_without_name_or_email() {
+        let profile = map_github_profile(github_user(None), &[]);
+        assert_error!(profile, &AppError::BadRequest(String::new()));
+
+        let emails = vec![GitHubEmail { email: "octo@lift.com".to_string(), primary: true, verified: false }];
+        let profile = map_github_profile(github_user(None), &emails).unwrap();
+        assert_eq!(profile.given_name, "octocat");
+        assert!(!profile.email_verified);
+    }
+
+    #[test]
+    fn test_map_oidc_profile() {
+        let user_info = OidcUserInfo {
+            sub: "AAAAAAAAAAAAAAAAAAAAAIkzqFVrSaSaFHy782bbtaQ".to_string(),
+            email: Some("megan@lift.com".to_string()),
+            email_verified: None,
+            given_name: None,
+            family_name: None,
+            name: Some("Megan Bowen".to_string()),
+        };
+
+        let profile = map_oidc_profile(ProfileFormat::Microsoft, user_info, false).unwrap();
+
+        assert_eq!(profile.provider, "microsoft");
+        assert_eq!(profile.given_name, "Megan");
+        assert_eq!(profile.family_name, "Bowen");
+        assert!(!profile.email_verified);
+    }
+
+    #[test]
+    fn test_map_oidc_profile_keeps_explicit_unverified_email() {
+        let user_info = OidcUserInfo {
+            sub: "248289761001".to_string(),
+            email: Some("jane@lift.com".to_string()),
+            email_verified: Some(false),
+            given_name: Some("Jane".to_string()),
+            family_name: Some("Doe".to_string()),
+            name: None,
+        };
+
+        let profile = map_oidc_profile(ProfileFormat::Oidc, user_info, true).unwrap();
+
+        assert!(!profile.email_verified);
+    }
+
+    async fn spawn_provider_stub() -> String {
+        let app = Router::new()
+            .route("/token", post(|| async {
+                Json(json!({ "access_token": "gho_access", "token_type": "bearer", "scope": "read:user,user:email" }))
+            }))
+            .route("/user", get(|| async {
+                Json(json!({ "id": 583231, "login": "octocat", "name": "Mona Octocat" }))
+            }))
+            .route("/user/emails", get(|| async {
+                Json(json!([{ "email": "octo@lift.com", "primary": true, "verified": true }]))
+            }))
+            // Microsoft Graph's userinfo, which has no `email_verified` claim.
+            .route("/oidc/userinfo", get(|| async {
+                Json(json!({
+                    "sub": "OLu859SGc2Sr9ZsqbkG-QbeLgJlb41KcdiPoLYNpSFA",
+                    "name": "Megan Bowen",
+                    "family_name": "Bowen",
+                    "given_name": "Megan",
+                    "picture": "https://graph.microsoft.com/v1.0/me/photo/$value",
+                    "email": "megan@lift.com"
+                }))
+            }));
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        format!("http://{address}")
+    }
+
+    fn stub_settings(format: ProfileFormat, base_url: &str) -> GenericProviderSettings {
+        GenericProviderSettings {
+            format,
+            client_id: "test-client-id".to_string(),
+            client_secret: "test-client-secret".to_string(),
+            redirect_url: "http://127.0.0.1:3000/auth/github/authorized".to_string(),
+            auth_url: format!("{base_url}/authorize"),
+            token_url: format!("{base_url}/token"),
+            revocation_url: None,
+            userinfo_url: format!("{base_url}/user"),
+            scopes: vec!["read:user".to_string(), "user:email".to_string()],
+            trust_email: false,
+        }
+    }
+
+    fn stub_provider(format: ProfileFormat, base_url: &str) -> GenericOAuthProvider {
+        GenericOAuthProvider::new(stub_settings(format, base_url)).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_authorisation_request_has_no_nonce() {
+        let provider = stub_provider(ProfileFormat::Oidc, "https://idp.lift.com");
+
+        let request = provider.authorisation_request().await.unwrap();
+
+        assert!(request.nonce.is_none());
+        assert!(!request.url.query_pairs().any(|(name, _)| name == "nonce"));
+    }
+
+    #[tokio::test]
+    async fn test_complete_login_against_stub() {
+        let base_url = spawn_provider_stub().await;
+        let provider = stub_provider(ProfileFormat::GitHub, &base_url);
+
+        let login = provider.complete_login("code".to_string(), PkceCodeVerifier::new("verifier".to_string()), None).await.unwrap();
+
+        assert_eq!(login.tokens.access_token, "gho_access");
+        assert!(login.tokens.refresh_token.is_none());
+        assert_eq!(login.profile.subject, "583231");
+        assert_eq!(login.profile.email, "octo@lift.com");
+        assert!(provider.revoke("gho_access").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_complete_login_with_microsoft_userinfo() {
+        let base_url = spawn_provider_stub().await;
+        let settings = GenericProviderSettings {
+            userinfo_url: format!("{base_url}/oidc/userinfo"),
+            trust_email: true,
+            ..stub_settings(ProfileFormat::Microsoft, &base_url)
+        };
+        let provider = GenericOAuthProvider::new(settings).unwrap();
+
+        let login = provider.complete_login("code".to_string(), PkceCodeVerifier::new("verifier".to_string()), None).await.unwrap();
+
+        assert_eq!(login.profile.provider, "microsoft");
+        assert_eq!(login.profile.subject, "OLu859SGc2Sr9ZsqbkG-QbeLgJlb41KcdiPoLYNpSFA");
+        assert_eq!(login.profile.email, "megan@lift.com");
+        assert!(login.profile.email_verified);
+    }
+}
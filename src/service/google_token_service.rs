@@ -1,11 +1,35 @@
+use std::sync::{Arc, RwLock};
+
 use anyhow::Context;
-use oauth2::{basic::BasicClient, reqwest::async_http_client, AccessToken, AuthorizationCode, CsrfToken, RefreshToken, Scope, StandardRevocableToken, TokenResponse};
-use reqwest::{Client, Url};
+use async_trait::async_trait;
+use oauth2::{basic::{BasicErrorResponse, BasicRevocationErrorResponse, BasicTokenIntrospectionResponse, BasicTokenType}, reqwest::async_http_client, AccessToken, AuthorizationCode, Client as OAuthClient, CsrfToken, ExtraTokenFields, PkceCodeChallenge, PkceCodeVerifier, RefreshToken, Scope, StandardRevocableToken, StandardTokenResponse, TokenResponse};
+use reqwest::Client;
 use serde::{Deserialize, Serialize};
 
-use crate::{config:: parameter, error::{app_error::AppError, token_error::TokenError}, User};
+use crate::{config::oauth_provider_config::OAuthProviderConfig, error::{app_error::AppError, token_error::TokenError}, service::{id_token_verifier::{IdTokenClaims, IdTokenIssuer, IdTokenVerifier}, oauth_provider::{AccessTokenInfo, AuthorisationRequest, NormalizedProfile, OAuthProvider, ProviderLogin, ProviderTokens}}, User};
+
+pub const GOOGLE_PROVIDER: &str = "google";
+
+/// Google returns the OpenID Connect `id_token` alongside the standard token fields.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct IdTokenFields {
+    pub id_token: Option<String>,
+}
+
+impl ExtraTokenFields for IdTokenFields {}
+
+pub type GoogleTokenResponse = StandardTokenResponse<IdTokenFields, BasicTokenType>;
 
-#[derive(Deserialize, Serialize, Debug)]
+pub type GoogleOAuthClient = OAuthClient<
+    BasicErrorResponse,
+    GoogleTokenResponse,
+    BasicTokenType,
+    BasicTokenIntrospectionResponse,
+    StandardRevocableToken,
+    BasicRevocationErrorResponse,
+>;
+
+#[derive(Clone, Deserialize, Serialize, Debug, Eq, PartialEq)]
 pub struct GoogleTokenInfo {
     pub audience: String,  // The audience for which the token was issued
     pub email: String,     // The email associated with the access token
@@ -14,16 +38,26 @@ pub struct GoogleTokenInfo {
     pub user_id: String,   // The user's unique ID
 }
 
+pub struct GoogleTokens {
+    pub access_token: AccessToken,
+    pub refresh_token: RefreshToken,
+    pub id_token: String,
+}
+
 #[derive(Clone)]
 pub struct GoogleTokenService {
-    oauth_client: BasicClient,
+    /// Shared between clones so a rotated client secret reaches every handle.
+    oauth_client: Arc<RwLock<GoogleOAuthClient>>,
+    config: OAuthProviderConfig,
     http_client: Client,
+    id_token_verifier: IdTokenVerifier,
 }
 
 pub trait TokenServiceTrait {
-    fn new(oauth_client: BasicClient) -> Self;
-    async fn generate_authorisation_url(&self) -> Result<(Url, CsrfToken), AppError>;
-    async fn exchange_authorisation_code(&self, code: String) -> Result<(AccessToken, RefreshToken), AppError>;
+    fn new(config: OAuthProviderConfig) -> Result<Self, AppError> where Self: Sized;
+    async fn generate_authorisation_url(&self) -> Result<AuthorisationRequest, AppError>;
+    async fn exchange_authorisation_code(&self, code: String, pkce_verifier: PkceCodeVerifier) -> Result<GoogleTokens, AppError>;
+    async fn verify_id_token(&self, id_token: &str, nonce: &str) -> Result<User, AppError>;
     async fn refresh_access_token(&self, refresh_token: String) -> Result<AccessToken, AppError>;
     async fn revoke_token(&self, token: String) -> Result<(), AppError>;
     async fn get_token_info(&self, access_token: &str) -> Result<GoogleTokenInfo, AppError>;
@@ -31,32 +65,40 @@ pub trait TokenServiceTrait {
 }
 
 impl TokenServiceTrait for GoogleTokenService {
-    fn new(oauth_client: BasicClient) -> Self {
-        Self {
-            oauth_client,
-            http_client: Client::new(),
-        }
+    fn new(config: OAuthProviderConfig) -> Result<Self, AppError> {
+        let http_client = Client::new();
+        let id_token_verifier = IdTokenVerifier::new(http_client.clone(), config.client_id.clone());
+        Ok(Self {
+            oauth_client: Arc::new(RwLock::new(config.oauth_client()?)),
+            config,
+            http_client,
+            id_token_verifier,
+        })
     }
 
-    async fn generate_authorisation_url(&self) -> Result<(Url, CsrfToken), AppError> {
-        let (auth_url, csrf_token) = self.oauth_client
+    #[tracing::instrument(skip_all, fields(provider = GOOGLE_PROVIDER))]
+    async fn generate_authorisation_url(&self) -> Result<AuthorisationRequest, AppError> {
+        let (pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
+        let nonce = CsrfToken::new_random().secret().to_string();
+        let oauth_client = self.oauth_client();
+        let mut authorisation_request = oauth_client
             .authorize_url(CsrfToken::new_random)
-            .add_scope(Scope::new(
-                parameter::get("GOOGLE_EMAIL_SCOPE")?,
-            ))
-            .add_scope(Scope::new(
-                parameter::get("GOOGLE_PROFILE_SCOPE")?,
-            ))
-            .add_extra_param("access_type", "offline")
-            .add_extra_param("prompt", "consent")
-            .url();
+            .add_scopes(self.config.scopes.iter().cloned().map(Scope::new))
+            .add_extra_param("nonce", &nonce)
+            .set_pkce_challenge(pkce_challenge);
+        for (name, value) in &self.config.extra_auth_params {
+            authorisation_request = authorisation_request.add_extra_param(name, value);
+        }
+        let (auth_url, csrf_token) = authorisation_request.url();
 
-        Ok((auth_url, csrf_token))
+        Ok(AuthorisationRequest { url: auth_url, csrf_token, pkce_verifier, nonce: Some(nonce) })
     }
 
-    async fn exchange_authorisation_code(&self, code: String) -> Result<(AccessToken, RefreshToken), AppError> {
-        let token = self.oauth_client
+    #[tracing::instrument(skip_all, fields(provider = GOOGLE_PROVIDER))]
+    async fn exchange_authorisation_code(&self, code: String, pkce_verifier: PkceCodeVerifier) -> Result<GoogleTokens, AppError> {
+        let token = self.oauth_client()
             .exchange_code(AuthorizationCode::new(code))
+            .set_pkce_verifier(pkce_verifier)
             .request_async(async_http_client)
             .await
             .context("failed in sending request to authorization server")?;
@@ -64,15 +106,23 @@ impl TokenServiceTrait for GoogleTokenService {
         let access_token = token.access_token().to_owned();
 
         let refresh_token = token.refresh_token().context("Missing refresh token")?.to_owned();
+
+        let id_token = token.extra_fields().id_token.clone().context("Missing id token")?;
     
-        Ok((access_token, refresh_token))
+        Ok(GoogleTokens { access_token, refresh_token, id_token })
     }
 
+    #[tracing::instrument(skip_all, fields(provider = GOOGLE_PROVIDER))]
+    async fn verify_id_token(&self, id_token: &str, nonce: &str) -> Result<User, AppError> {
+        Ok(self.verify_id_token_claims(id_token, nonce).await?.into())
+    }
+
+    #[tracing::instrument(skip_all, fields(provider = GOOGLE_PROVIDER))]
     async fn refresh_access_token(
         &self,
         refresh_token: String,
     ) -> Result<AccessToken, AppError> {
-        let token_response = self.oauth_client
+        let token_response = self.oauth_client()
             .exchange_refresh_token(&RefreshToken::new(refresh_token))
             .request_async(async_http_client)
             .await
@@ -81,26 +131,28 @@ impl TokenServiceTrait for GoogleTokenService {
         Ok(token_response.access_token().to_owned())
     }
 
+    #[tracing::instrument(skip_all, fields(provider = GOOGLE_PROVIDER))]
     async fn revoke_token(&self, token: String) -> Result<(), AppError> {
         let token = AccessToken::new(token);
         let revocable_token: StandardRevocableToken = token.into();
 
-        let response = self.oauth_client.revoke_token(revocable_token)?.request_async(async_http_client).await;
+        let response = self.oauth_client().revoke_token(revocable_token)?.request_async(async_http_client).await;
 
         if let Err(error) = response {
+            tracing::debug!("Google token revocation failed: {:?}", error);
             return Err(TokenError::InvalidToken)?;
         } 
         Ok(())
     }
 
+    #[tracing::instrument(skip_all, fields(provider = GOOGLE_PROVIDER))]
     async fn get_token_info(
         &self,
         access_token: &str,
     ) -> Result<GoogleTokenInfo, AppError> {
-        let token_info_url = parameter::get("GOOGLE_TOKEN_INFO_URI")?;
         let response = self
             .http_client
-            .get(token_info_url)
+            .get(&self.config.token_info_url)
             .bearer_auth(access_token)
             .send()
             .await
@@ -118,9 +170,10 @@ impl TokenServiceTrait for GoogleTokenService {
         Ok(google_token_info)
     }
 
+    #[tracing::instrument(skip_all, fields(provider = GOOGLE_PROVIDER))]
     async fn get_user_info(&self, access_token: &str) -> Result<User, AppError> {
         let user_data: User = self.http_client
-            .get("https://openidconnect.googleapis.com/v1/userinfo")
+            .get(&self.config.userinfo_url)
             .bearer_auth(access_token)
             .send()
             .await
@@ -132,3 +185,157 @@ impl TokenServiceTrait for GoogleTokenService {
         Ok(user_data)
     }
 }
+
+impl GoogleTokenService {
+    /// Swaps in a new client secret, e.g. after the mounted secret file was rotated.
+    pub fn set_client_secret(&self, client_secret: String) -> Result<(), AppError> {
+        let config = OAuthProviderConfig { client_secret, ..self.config.clone() };
+        let oauth_client = config.oauth_client()?;
+        *self.oauth_client.write().unwrap_or_else(|poisoned| poisoned.into_inner()) = oauth_client;
+        Ok(())
+    }
+
+    fn oauth_client(&self) -> GoogleOAuthClient {
+        self.oauth_client.read().unwrap_or_else(|poisoned| poisoned.into_inner()).clone()
+    }
+
+    #[tracing::instrument(skip_all, fields(provider = GOOGLE_PROVIDER))]
+    async fn verify_id_token_claims(&self, id_token: &str, nonce: &str) -> Result<IdTokenClaims, AppError> {
+        let issuer = IdTokenIssuer { jwks_uri: &self.config.jwks_url, issuer: &self.config.issuer };
+
+        self.id_token_verifier.verify(id_token, nonce, &issuer).await
+    }
+}
+
+#[async_trait]
+impl OAuthProvider for GoogleTokenService {
+    fn name(&self) -> &str {
+        GOOGLE_PROVIDER
+    }
+
+    async fn authorisation_request(&self) -> Result<AuthorisationRequest, AppError> {
+        self.generate_authorisation_url().await
+    }
+
+    async fn complete_login(&self, code: String, pkce_verifier: PkceCodeVerifier, nonce: Option<&str>) -> Result<ProviderLogin, AppError> {
+        let nonce = nonce.ok_or_else(|| TokenError::GenericTokenError("Missing id token nonce".to_string()))?;
+        let tokens = self.exchange_authorisation_code(code, pkce_verifier).await?;
+        let claims = self.verify_id_token_claims(&tokens.id_token, nonce).await?;
+
+        Ok(ProviderLogin {
+            tokens: ProviderTokens {
+                access_token: tokens.access_token.secret().to_string(),
+                refresh_token: Some(tokens.refresh_token.secret().to_string()),
+            },
+            profile: NormalizedProfile {
+                provider: GOOGLE_PROVIDER.to_string(),
+                subject: claims.sub,
+                email: claims.email,
+                email_verified: claims.email_verified.unwrap_or(false),
+                given_name: claims.given_name.unwrap_or_default(),
+                family_name: claims.family_name.unwrap_or_default(),
+            },
+        })
+    }
+
+    async fn validate_access_token(&self, access_token: &str) -> Result<AccessTokenInfo, AppError> {
+        let google_token_info = self.get_token_info(access_token).await?;
+        Ok(AccessTokenInfo {
+            subject: google_token_info.user_id,
+            expires_in: Some(google_token_info.expires_in),
+        })
+    }
+
+    async fn refresh(&self, refresh_token: &str) -> Result<String, AppError> {
+        let access_token = self.refresh_access_token(refresh_token.to_string()).await?;
+        Ok(access_token.secret().to_string())
+    }
+
+    async fn revoke(&self, token: &str) -> Result<(), AppError> {
+        self.revoke_token(token.to_string()).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::StatusCode;
+    use oauth2::PkceCodeChallenge;
+    use serde_json::json;
+
+    use crate::{config::oauth_provider_config::OAuthProviderConfig, test_utils::{mock_oauth_provider::{MockEndpoint, MockOAuthProvider}, placeholder_oauth_config}};
+
+    use super::{GoogleTokenService, TokenServiceTrait};
+
+    fn get_google_token_service(config: OAuthProviderConfig) -> GoogleTokenService {
+        GoogleTokenService::new(config).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_generate_authorisation_url_uses_s256_pkce() {
+        let google_token_service = get_google_token_service(placeholder_oauth_config());
+
+        let request = google_token_service.generate_authorisation_url().await.unwrap();
+
+        let query: Vec<(String, String)> = request.url.query_pairs().into_owned().collect();
+        let expected_challenge = PkceCodeChallenge::from_code_verifier_sha256(&request.pkce_verifier);
+        assert!(query.contains(&("code_challenge_method".to_string(), "S256".to_string())));
+        assert!(query.contains(&("code_challenge".to_string(), expected_challenge.as_str().to_string())));
+        assert!(query.contains(&("state".to_string(), request.csrf_token.secret().to_string())));
+    }
+
+    #[tokio::test]
+    async fn test_generate_authorisation_url_requests_openid_with_nonce() {
+        let google_token_service = get_google_token_service(placeholder_oauth_config());
+
+        let request = google_token_service.generate_authorisation_url().await.unwrap();
+
+        let query: Vec<(String, String)> = request.url.query_pairs().into_owned().collect();
+        assert!(query.contains(&("scope".to_string(), "openid email profile".to_string())));
+        assert!(query.contains(&("nonce".to_string(), request.nonce.unwrap())));
+    }
+
+    #[tokio::test]
+    async fn test_generate_authorisation_url_uses_configured_scopes_and_params() {
+        let google_token_service = get_google_token_service(OAuthProviderConfig {
+            scopes: vec!["openid".to_string(), "email".to_string()],
+            extra_auth_params: vec![("hd".to_string(), "lift.com".to_string())],
+            ..placeholder_oauth_config()
+        });
+
+        let request = google_token_service.generate_authorisation_url().await.unwrap();
+
+        let query: Vec<(String, String)> = request.url.query_pairs().into_owned().collect();
+        assert!(query.contains(&("scope".to_string(), "openid email".to_string())));
+        assert!(query.contains(&("hd".to_string(), "lift.com".to_string())));
+        assert!(!query.iter().any(|(name, _)| name == "access_type"));
+    }
+
+    #[tokio::test]
+    async fn test_get_user_info_uses_configured_endpoint() {
+        let mock_provider = MockOAuthProvider::spawn().await;
+        mock_provider.respond_once(MockEndpoint::UserInfo, StatusCode::OK, json!({
+            "sub": "110235950686105464135",
+            "email": "TestEmail@lift.com",
+            "given_name": "Tom",
+            "family_name": "Gill",
+        }));
+        let google_token_service = get_google_token_service(mock_provider.oauth_config());
+
+        let user = google_token_service.get_user_info("access-token").await.unwrap();
+
+        assert_eq!(user.given_name, "Tom");
+        assert_eq!(mock_provider.request_count(MockEndpoint::UserInfo), 1);
+    }
+
+    #[tokio::test]
+    async fn test_set_client_secret_is_used_by_every_clone() {
+        let mock_provider = MockOAuthProvider::spawn().await;
+        let google_token_service = get_google_token_service(mock_provider.oauth_config());
+        let clone = google_token_service.clone();
+
+        google_token_service.set_client_secret("rotated-secret".to_string()).unwrap();
+        let _ = clone.refresh_access_token("unknown-refresh-token".to_string()).await;
+
+        assert_eq!(mock_provider.client_secrets(), vec!["rotated-secret".to_string()]);
+    }
+}
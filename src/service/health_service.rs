@@ -0,0 +1,142 @@
+use std::{collections::HashSet, future::Future, sync::Arc, time::{Duration, Instant}};
+
+use serde::Serialize;
+use sqlx::migrate::Migrator;
+
+use crate::{config::database::Database, service::{google_token_service::GOOGLE_PROVIDER, oauth_provider::ProviderRegistry, shutdown_signal::ShutdownSignal}};
+
+/// A readiness check that takes longer than this counts as failed.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+static MIGRATOR: Migrator = sqlx::migrate!();
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckStatus {
+    Ok,
+    Failed,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HealthCheck {
+    pub name: &'static str,
+    pub status: CheckStatus,
+    pub latency_ms: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<&'static str>,
+}
+
+/// Why a check failed. `/readyz` is unauthenticated, so callers only get the fixed `reason`;
+/// the `detail`, e.g. a database error naming the host, is only logged.
+#[derive(Debug)]
+struct CheckFailure {
+    reason: &'static str,
+    detail: String,
+}
+
+impl CheckFailure {
+    fn new(reason: &'static str, detail: impl ToString) -> Self {
+        Self { reason, detail: detail.to_string() }
+    }
+}
+
+/// The outcome of every readiness check; ready only when all of them passed.
+#[derive(Debug, Serialize)]
+pub struct ReadinessReport {
+    pub status: CheckStatus,
+    pub checks: Vec<HealthCheck>,
+}
+
+impl ReadinessReport {
+    pub fn is_ready(&self) -> bool {
+        self.status == CheckStatus::Ok
+    }
+}
+
+#[derive(Clone)]
+pub struct HealthService {
+    db_conn: Arc<Database>,
+    providers: ProviderRegistry,
+    shutdown: ShutdownSignal,
+}
+
+impl HealthService {
+    pub fn new(db_conn: &Arc<Database>, providers: &ProviderRegistry, shutdown: &ShutdownSignal) -> Self {
+        Self {
+            db_conn: db_conn.clone(),
+            providers: providers.clone(),
+            shutdown: shutdown.clone(),
+        }
+    }
+
+    pub async fn readiness(&self) -> ReadinessReport {
+        let checks = vec![
+            run_check("shutdown", async { self.check_shutdown() }).await,
+            run_check("database", self.check_database()).await,
+            run_check("migrations", self.check_migrations()).await,
+            run_check("oauth", async { self.check_oauth() }).await,
+        ];
+        let status = if checks.iter().all(|check| check.status == CheckStatus::Ok) {
+            CheckStatus::Ok
+        } else {
+            CheckStatus::Failed
+        };
+        ReadinessReport { status, checks }
+    }
+
+    async fn check_database(&self) -> Result<(), CheckFailure> {
+        sqlx::query("SELECT 1")
+            .execute(self.db_conn.get_pool())
+            .await
+            .map(|_| ())
+            .map_err(|error| CheckFailure::new("database unavailable", error))
+    }
+
+    /// Every migration embedded in the binary has been applied successfully.
+    async fn check_migrations(&self) -> Result<(), CheckFailure> {
+        let applied: HashSet<i64> = sqlx::query_scalar("SELECT version FROM _sqlx_migrations WHERE success = TRUE")
+            .fetch_all(self.db_conn.get_pool())
+            .await
+            .map_err(|error| CheckFailure::new("migrations unavailable", error))?
+            .into_iter()
+            .collect();
+        let pending: Vec<String> = MIGRATOR
+            .iter()
+            .filter(|migration| migration.migration_type.is_up_migration() && !applied.contains(&migration.version))
+            .map(|migration| migration.version.to_string())
+            .collect();
+
+        if !pending.is_empty() {
+            return Err(CheckFailure::new("migrations pending", format!("pending migrations: {}", pending.join(", "))));
+        }
+        Ok(())
+    }
+
+    /// Fails as soon as shutdown starts, so traffic is drained before connections are refused.
+    fn check_shutdown(&self) -> Result<(), CheckFailure> {
+        if self.shutdown.is_triggered() {
+            return Err(CheckFailure::new("shutting down", "shutdown was triggered"));
+        }
+        Ok(())
+    }
+
+    fn check_oauth(&self) -> Result<(), CheckFailure> {
+        self.providers.get(GOOGLE_PROVIDER).map(|_| ()).map_err(|error| CheckFailure::new("oauth provider not configured", error))
+    }
+}
+
+async fn run_check(name: &'static str, check: impl Future<Output = Result<(), CheckFailure>>) -> HealthCheck {
+    let started = Instant::now();
+    let result = tokio::time::timeout(CHECK_TIMEOUT, check)
+        .await
+        .unwrap_or_else(|_| Err(CheckFailure::new("timed out", format!("timed out after {}s", CHECK_TIMEOUT.as_secs()))));
+    let latency_ms = started.elapsed().as_secs_f64() * 1000.0;
+
+    match result {
+        Ok(()) => HealthCheck { name, status: CheckStatus::Ok, latency_ms, error: None },
+        Err(failure) => {
+            tracing::warn!("Readiness check {} failed: {}", name, failure.detail);
+            HealthCheck { name, status: CheckStatus::Failed, latency_ms, error: Some(failure.reason) }
+        }
+    }
+}
@@ -0,0 +1,308 @@
+use std::{sync::Arc, time::{Duration, Instant}};
+
+use anyhow::Context;
+use jsonwebtoken::{decode, decode_header, errors::ErrorKind, jwk::{Jwk, JwkSet}, Algorithm, DecodingKey, Validation};
+use reqwest::Client;
+use serde::Deserialize;
+use tokio::sync::RwLock;
+
+use crate::{error::{app_error::AppError, token_error::TokenError}, User};
+
+/// How long a fetched key set is trusted before it is fetched again.
+const JWKS_CACHE_TTL: Duration = Duration::from_secs(60 * 60);
+/// Lower bound between fetches triggered by an unknown `kid`, so forged tokens cannot
+/// be used to hammer the JWKS endpoint.
+const JWKS_MIN_REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Deserialize)]
+pub struct IdTokenClaims {
+    pub sub: String,
+    pub email: String,
+    pub email_verified: Option<bool>,
+    pub given_name: Option<String>,
+    pub family_name: Option<String>,
+    pub nonce: Option<String>,
+}
+
+impl From<IdTokenClaims> for User {
+    fn from(claims: IdTokenClaims) -> Self {
+        Self {
+            sub: claims.sub,
+            given_name: claims.given_name.unwrap_or_default(),
+            family_name: claims.family_name.unwrap_or_default(),
+            email: claims.email,
+        }
+    }
+}
+
+/// Where the signing keys are published and who is expected to have issued the token.
+pub struct IdTokenIssuer<'a> {
+    pub jwks_uri: &'a str,
+    pub issuer: &'a str,
+}
+
+struct CachedJwks {
+    jwks_uri: String,
+    jwks: JwkSet,
+    fetched_at: Instant,
+}
+
+/// Verifies OpenID Connect id tokens locally against the provider's JWKS, which is cached
+/// and re-fetched when it goes stale or a token is signed with a key we have not seen yet.
+#[derive(Clone)]
+pub struct IdTokenVerifier {
+    http_client: Client,
+    client_id: String,
+    cache: Arc<RwLock<Option<CachedJwks>>>,
+    cache_ttl: Duration,
+    min_refresh_interval: Duration,
+}
+
+impl IdTokenVerifier {
+    pub fn new(http_client: Client, client_id: String) -> Self {
+        Self {
+            http_client,
+            client_id,
+            cache: Arc::new(RwLock::new(None)),
+            cache_ttl: JWKS_CACHE_TTL,
+            min_refresh_interval: JWKS_MIN_REFRESH_INTERVAL,
+        }
+    }
+
+    pub async fn verify(&self, id_token: &str, expected_nonce: &str, issuer: &IdTokenIssuer<'_>) -> Result<IdTokenClaims, AppError> {
+        let header = decode_header(id_token).map_err(|_| TokenError::InvalidToken)?;
+        let kid = header.kid.ok_or(TokenError::InvalidToken)?;
+        let jwk = self.find_key(&kid, issuer.jwks_uri).await?;
+        let decoding_key = DecodingKey::from_jwk(&jwk).map_err(|_| TokenError::InvalidToken)?;
+
+        let mut validation = Validation::new(Algorithm::RS256);
+        validation.set_audience(&[&self.client_id]);
+        validation.set_issuer(&[issuer.issuer, issuer.issuer.trim_start_matches("https://")]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+
+        let claims = decode::<IdTokenClaims>(id_token, &decoding_key, &validation)
+            .map_err(|error| match error.kind() {
+                ErrorKind::ExpiredSignature => TokenError::TokenExpired,
+                _ => TokenError::InvalidToken,
+            })?
+            .claims;
+
+        if claims.nonce.as_deref() != Some(expected_nonce) {
+            return Err(TokenError::GenericTokenError("Id token nonce mismatch".to_string()).into());
+        }
+
+        Ok(claims)
+    }
+
+    async fn find_key(&self, kid: &str, jwks_uri: &str) -> Result<Jwk, AppError> {
+        if let Some(cached) = self.cache.read().await.as_ref() {
+            if cached.jwks_uri == jwks_uri && cached.fetched_at.elapsed() < self.cache_ttl {
+                if let Some(jwk) = cached.jwks.find(kid) {
+                    return Ok(jwk.clone());
+                }
+            }
+        }
+
+        let mut cache = self.cache.write().await;
+        if let Some(cached) = cache.as_ref() {
+            let is_current = cached.jwks_uri == jwks_uri && cached.fetched_at.elapsed() < self.cache_ttl;
+            if let Some(jwk) = cached.jwks.find(kid).filter(|_| is_current) {
+                return Ok(jwk.clone());
+            }
+            if is_current && cached.fetched_at.elapsed() < self.min_refresh_interval {
+                tracing::warn!("Id token signed with unknown key {}", kid);
+                return Err(TokenError::InvalidToken.into());
+            }
+        }
+
+        tracing::debug!("Fetching JWKS from {}", jwks_uri);
+        let jwks = self.fetch_jwks(jwks_uri).await?;
+        let jwk = jwks.find(kid).cloned();
+        *cache = Some(CachedJwks {
+            jwks_uri: jwks_uri.to_string(),
+            jwks,
+            fetched_at: Instant::now(),
+        });
+
+        jwk.ok_or_else(|| TokenError::InvalidToken.into())
+    }
+
+    async fn fetch_jwks(&self, jwks_uri: &str) -> Result<JwkSet, AppError> {
+        let jwks = self.http_client
+            .get(jwks_uri)
+            .send()
+            .await
+            .context("Failed to send request to the JWKS endpoint")?
+            .error_for_status()
+            .context("JWKS endpoint returned an error")?
+            .json::<JwkSet>()
+            .await
+            .context("Failed to parse JWKS from response")?;
+
+        Ok(jwks)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::{atomic::{AtomicUsize, Ordering}, Arc}, time::Duration};
+
+    use axum::{extract::State, routing::get, Json, Router};
+    use chrono::Utc;
+    use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+    use reqwest::Client;
+    use serde_json::{json, Value};
+    use tokio::sync::RwLock;
+
+    use crate::{assert_error, error::{app_error::AppError, token_error::TokenError}, User};
+
+    use super::{IdTokenIssuer, IdTokenVerifier};
+
+    const SIGNING_KEY: &str = include_str!("../../tests/fixtures/id_token_signing_key.pem");
+    const JWKS: &str = include_str!("../../tests/fixtures/jwks.json");
+    const ISSUER: &str = "https://accounts.google.com";
+
+    #[derive(Clone)]
+    struct JwksStub {
+        jwks: Arc<RwLock<Value>>,
+        requests: Arc<AtomicUsize>,
+    }
+
+    async fn spawn_jwks_stub() -> (String, JwksStub) {
+        let stub = JwksStub {
+            jwks: Arc::new(RwLock::new(serde_json::from_str(JWKS).unwrap())),
+            requests: Arc::new(AtomicUsize::new(0)),
+        };
+        let app = Router::new()
+            .route("/certs", get(|State(stub): State<JwksStub>| async move {
+                stub.requests.fetch_add(1, Ordering::SeqCst);
+                Json(stub.jwks.read().await.clone())
+            }))
+            .with_state(stub.clone());
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (format!("http://{address}/certs"), stub)
+    }
+
+    fn sign(kid: &str, claims: Value) -> String {
+        let mut header = Header::new(Algorithm::RS256);
+        header.kid = Some(kid.to_string());
+        encode(&header, &claims, &EncodingKey::from_rsa_pem(SIGNING_KEY.as_bytes()).unwrap()).unwrap()
+    }
+
+    fn claims() -> Value {
+        json!({
+            "iss": ISSUER,
+            "aud": "test-client-id",
+            "sub": "110235950686105464135",
+            "email": "TestEmail@lift.com",
+            "given_name": "Tom",
+            "family_name": "Gill",
+            "nonce": "test-nonce",
+            "iat": Utc::now().timestamp(),
+            "exp": Utc::now().timestamp() + 3600,
+        })
+    }
+
+    fn verifier() -> IdTokenVerifier {
+        IdTokenVerifier::new(Client::new(), "test-client-id".to_string())
+    }
+
+    #[tokio::test]
+    async fn test_verify_valid_id_token() {
+        let (jwks_uri, _) = spawn_jwks_stub().await;
+        let issuer = IdTokenIssuer { jwks_uri: &jwks_uri, issuer: ISSUER };
+
+        let claims = verifier().verify(&sign("test-key-1", claims()), "test-nonce", &issuer).await.unwrap();
+
+        let user: User = claims.into();
+        assert_eq!(user.sub, "110235950686105464135");
+        assert_eq!(user.email, "TestEmail@lift.com");
+        assert_eq!(user.given_name, "Tom");
+        assert_eq!(user.family_name, "Gill");
+    }
+
+    #[tokio::test]
+    async fn test_verify_rejects_nonce_mismatch() {
+        let (jwks_uri, _) = spawn_jwks_stub().await;
+        let issuer = IdTokenIssuer { jwks_uri: &jwks_uri, issuer: ISSUER };
+
+        let result = verifier().verify(&sign("test-key-1", claims()), "other-nonce", &issuer).await;
+
+        assert_error!(result, &AppError::TokenError(TokenError::GenericTokenError(String::new())));
+    }
+
+    #[tokio::test]
+    async fn test_verify_rejects_expired_token() {
+        let (jwks_uri, _) = spawn_jwks_stub().await;
+        let issuer = IdTokenIssuer { jwks_uri: &jwks_uri, issuer: ISSUER };
+        let mut claims = claims();
+        claims["exp"] = json!(Utc::now().timestamp() - 3600);
+
+        let result = verifier().verify(&sign("test-key-1", claims), "test-nonce", &issuer).await;
+
+        assert_eq!(result.unwrap_err(), AppError::TokenError(TokenError::TokenExpired));
+    }
+
+    #[tokio::test]
+    async fn test_verify_rejects_wrong_audience_and_issuer() {
+        let (jwks_uri, _) = spawn_jwks_stub().await;
+        let issuer = IdTokenIssuer { jwks_uri: &jwks_uri, issuer: ISSUER };
+        let verifier = verifier();
+
+        let mut wrong_audience = claims();
+        wrong_audience["aud"] = json!("another-client-id");
+        let result = verifier.verify(&sign("test-key-1", wrong_audience), "test-nonce", &issuer).await;
+        assert_eq!(result.unwrap_err(), AppError::TokenError(TokenError::InvalidToken));
+
+        let mut wrong_issuer = claims();
+        wrong_issuer["iss"] = json!("https://evil.example.com");
+        let result = verifier.verify(&sign("test-key-1", wrong_issuer), "test-nonce", &issuer).await;
+        assert_eq!(result.unwrap_err(), AppError::TokenError(TokenError::InvalidToken));
+    }
+
+    #[tokio::test]
+    async fn test_verify_rejects_tampered_signature() {
+        let (jwks_uri, _) = spawn_jwks_stub().await;
+        let issuer = IdTokenIssuer { jwks_uri: &jwks_uri, issuer: ISSUER };
+        let id_token = sign("test-key-1", claims());
+        let (message, signature) = id_token.rsplit_once('.').unwrap();
+        let tampered = format!("{message}.{}", signature.chars().rev().collect::<String>());
+
+        let result = verifier().verify(&tampered, "test-nonce", &issuer).await;
+
+        assert_eq!(result.unwrap_err(), AppError::TokenError(TokenError::InvalidToken));
+    }
+
+    #[tokio::test]
+    async fn test_jwks_is_cached_and_refreshed_on_rotation() {
+        let (jwks_uri, stub) = spawn_jwks_stub().await;
+        let issuer = IdTokenIssuer { jwks_uri: &jwks_uri, issuer: ISSUER };
+        let mut verifier = verifier();
+        verifier.min_refresh_interval = Duration::ZERO;
+
+        verifier.verify(&sign("test-key-1", claims()), "test-nonce", &issuer).await.unwrap();
+        verifier.verify(&sign("test-key-1", claims()), "test-nonce", &issuer).await.unwrap();
+        assert_eq!(stub.requests.load(Ordering::SeqCst), 1);
+
+        stub.jwks.write().await["keys"][0]["kid"] = json!("test-key-2");
+
+        verifier.verify(&sign("test-key-2", claims()), "test-nonce", &issuer).await.unwrap();
+        assert_eq!(stub.requests.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_unknown_kid_does_not_refetch_within_min_interval() {
+        let (jwks_uri, stub) = spawn_jwks_stub().await;
+        let issuer = IdTokenIssuer { jwks_uri: &jwks_uri, issuer: ISSUER };
+        let verifier = verifier();
+
+        verifier.verify(&sign("test-key-1", claims()), "test-nonce", &issuer).await.unwrap();
+        let result = verifier.verify(&sign("unknown-key", claims()), "test-nonce", &issuer).await;
+
+        assert_eq!(result.unwrap_err(), AppError::TokenError(TokenError::InvalidToken));
+        assert_eq!(stub.requests.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,281 @@
+use std::str::FromStr;
+
+use async_session::base64;
+use chrono::Utc;
+use jsonwebtoken::{decode, decode_header, encode, errors::ErrorKind, jwk::{AlgorithmParameters, CommonParameters, EllipticCurve, Jwk, JwkSet, KeyAlgorithm, OctetKeyPairParameters, OctetKeyPairType, PublicKeyUse, RSAKeyParameters, RSAKeyType}, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use ring::signature::{Ed25519KeyPair, KeyPair};
+use rsa::{pkcs1::DecodeRsaPrivateKey, pkcs8::DecodePrivateKey, traits::PublicKeyParts, RsaPrivateKey};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{config::app_config::JwtConfig, error::{app_error::AppError, token_error::TokenError}, state::app_state::UserContext};
+
+/// Claims of the access tokens this service issues to API clients. Roles are deliberately
+/// not among them: they are loaded with the user on every request, so revoking one takes
+/// effect before the token expires.
+#[derive(Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub struct AccessTokenClaims {
+    pub sub: String,
+    pub email: String,
+    pub name: String,
+    pub iss: String,
+    pub aud: String,
+    pub iat: i64,
+    pub exp: i64,
+}
+
+impl AccessTokenClaims {
+    pub fn user_id(&self) -> Result<u64, AppError> {
+        Ok(self.sub.parse().map_err(|_| TokenError::InvalidToken)?)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct IssuedAccessToken {
+    pub access_token: String,
+    pub token_type: &'static str,
+    pub expires_in: i64,
+}
+
+/// Signs and verifies first-party access tokens with a single RS256 or EdDSA key, and
+/// publishes the public half as a JWKS.
+#[derive(Clone)]
+pub struct JwtService {
+    algorithm: Algorithm,
+    encoding_key: EncodingKey,
+    decoding_key: DecodingKey,
+    jwk: Jwk,
+    issuer: String,
+    audience: String,
+    access_token_ttl_seconds: i64,
+}
+
+impl JwtService {
+    /// Builds the service from a PEM encoded private key. RS256 accepts PKCS#1 or PKCS#8 keys,
+    /// EdDSA accepts a PKCS#8 Ed25519 key. Without a `kid` one is derived from the public key.
+    pub fn from_pem(algorithm: Algorithm, private_key_pem: &str, kid: Option<String>, issuer: String, audience: String, access_token_ttl_seconds: i64) -> Result<Self, AppError> {
+        let (encoding_key, algorithm_parameters) = match algorithm {
+            Algorithm::RS256 => {
+                let private_key = RsaPrivateKey::from_pkcs8_pem(private_key_pem)
+                    .or_else(|_| RsaPrivateKey::from_pkcs1_pem(private_key_pem))
+                    .map_err(|_| AppError::ConfigurationError("JWT signing key is not a valid RSA private key".to_string()))?;
+                let encoding_key = EncodingKey::from_rsa_pem(private_key_pem.as_bytes())
+                    .map_err(|_| AppError::ConfigurationError("JWT signing key is not a valid RSA private key".to_string()))?;
+                (encoding_key, AlgorithmParameters::RSA(RSAKeyParameters {
+                    key_type: RSAKeyType::RSA,
+                    n: base64_url(&private_key.n().to_bytes_be()),
+                    e: base64_url(&private_key.e().to_bytes_be()),
+                }))
+            }
+            Algorithm::EdDSA => {
+                let der = pem::parse(private_key_pem)
+                    .map_err(|_| AppError::ConfigurationError("JWT signing key is not valid PEM".to_string()))?;
+                let key_pair = Ed25519KeyPair::from_pkcs8_maybe_unchecked(der.contents())
+                    .map_err(|_| AppError::ConfigurationError("JWT signing key is not a valid Ed25519 private key".to_string()))?;
+                (EncodingKey::from_ed_der(der.contents()), AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+                    key_type: OctetKeyPairType::OctetKeyPair,
+                    curve: EllipticCurve::Ed25519,
+                    x: base64_url(key_pair.public_key().as_ref()),
+                }))
+            }
+            _ => return Err(AppError::ConfigurationError(format!("Unsupported JWT signing algorithm: {:?}", algorithm))),
+        };
+
+        let kid = kid.unwrap_or_else(|| derive_key_id(&algorithm_parameters));
+        let jwk = Jwk {
+            common: CommonParameters {
+                public_key_use: Some(PublicKeyUse::Signature),
+                key_algorithm: Some(if algorithm == Algorithm::RS256 { KeyAlgorithm::RS256 } else { KeyAlgorithm::EdDSA }),
+                key_id: Some(kid),
+                ..Default::default()
+            },
+            algorithm: algorithm_parameters,
+        };
+        let decoding_key = DecodingKey::from_jwk(&jwk)
+            .map_err(|error| AppError::ConfigurationError(format!("Invalid JWT signing key: {}", error)))?;
+
+        Ok(Self {
+            algorithm,
+            encoding_key,
+            decoding_key,
+            jwk,
+            issuer,
+            audience,
+            access_token_ttl_seconds,
+        })
+    }
+
+    pub fn from_config(config: &JwtConfig) -> Result<Self, AppError> {
+        let algorithm = Algorithm::from_str(&config.signing_algorithm)
+            .map_err(|_| AppError::ConfigurationError(format!("Unsupported JWT signing algorithm: {}", config.signing_algorithm)))?;
+        let signing_key = config.signing_key.as_deref()
+            .ok_or_else(|| AppError::ConfigurationError("jwt.signing_key is required".to_string()))?;
+
+        Self::from_pem(
+            algorithm,
+            signing_key,
+            config.key_id.clone(),
+            config.issuer.clone(),
+            config.audience.clone(),
+            config.access_token_ttl_seconds,
+        )
+    }
+
+    pub fn issue_access_token(&self, user: &UserContext) -> Result<IssuedAccessToken, AppError> {
+        let now = Utc::now().timestamp();
+        let claims = AccessTokenClaims {
+            sub: user.user_id.to_string(),
+            email: user.email.clone(),
+            name: user.name.clone(),
+            iss: self.issuer.clone(),
+            aud: self.audience.clone(),
+            iat: now,
+            exp: now + self.access_token_ttl_seconds,
+        };
+
+        let mut header = Header::new(self.algorithm);
+        header.kid = self.jwk.common.key_id.clone();
+        let access_token = encode(&header, &claims, &self.encoding_key)
+            .map_err(|error| AppError::InternalServerError(format!("Failed to sign access token: {}", error)))?;
+
+        Ok(IssuedAccessToken {
+            access_token,
+            token_type: "Bearer",
+            expires_in: self.access_token_ttl_seconds,
+        })
+    }
+
+    pub fn verify_access_token(&self, access_token: &str) -> Result<AccessTokenClaims, AppError> {
+        let header = decode_header(access_token).map_err(|_| TokenError::InvalidToken)?;
+        if header.kid != self.jwk.common.key_id {
+            return Err(TokenError::InvalidToken.into());
+        }
+
+        let mut validation = Validation::new(self.algorithm);
+        validation.set_issuer(&[&self.issuer]);
+        validation.set_audience(&[&self.audience]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+
+        let token_data = decode::<AccessTokenClaims>(access_token, &self.decoding_key, &validation)
+            .map_err(|error| match error.kind() {
+                ErrorKind::ExpiredSignature => TokenError::TokenExpired,
+                _ => TokenError::InvalidToken,
+            })?;
+
+        Ok(token_data.claims)
+    }
+
+    pub fn jwks(&self) -> JwkSet {
+        JwkSet { keys: vec![self.jwk.clone()] }
+    }
+}
+
+fn base64_url(bytes: &[u8]) -> String {
+    base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+}
+
+fn derive_key_id(algorithm_parameters: &AlgorithmParameters) -> String {
+    let public_key = match algorithm_parameters {
+        AlgorithmParameters::RSA(parameters) => format!("{}.{}", parameters.n, parameters.e),
+        AlgorithmParameters::OctetKeyPair(parameters) => parameters.x.clone(),
+        _ => String::new(),
+    };
+    hex::encode(&Sha256::digest(public_key.as_bytes())[..8])
+}
+
+#[cfg(test)]
+mod tests {
+    use async_session::base64;
+    use chrono::Utc;
+    use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+
+    use crate::{assert_error, error::{app_error::AppError, token_error::TokenError}, state::app_state::UserContext};
+
+    use super::{AccessTokenClaims, JwtService};
+
+    const RSA_KEY: &str = include_str!("../../tests/fixtures/id_token_signing_key.pem");
+    const ED25519_KEY: &str = include_str!("../../tests/fixtures/jwt_ed25519_key.pem");
+
+    fn tom() -> UserContext {
+        UserContext { user_id: 1, email: "TestEmail@lift.com".to_string(), name: "Tom".to_string(), roles: vec!["admin".to_string()] }
+    }
+
+    fn jwt_service(algorithm: Algorithm, key: &str) -> JwtService {
+        JwtService::from_pem(algorithm, key, Some("test-key".to_string()), "oauth-app".to_string(), "oauth-app-api".to_string(), 900).unwrap()
+    }
+
+    #[test]
+    fn test_round_trip_with_each_algorithm() {
+        for (algorithm, key) in [(Algorithm::RS256, RSA_KEY), (Algorithm::EdDSA, ED25519_KEY)] {
+            let jwt_service = jwt_service(algorithm, key);
+
+            let issued = jwt_service.issue_access_token(&tom()).unwrap();
+            let claims = jwt_service.verify_access_token(&issued.access_token).unwrap();
+
+            assert_eq!(claims.user_id().unwrap(), tom().user_id);
+            assert_eq!(claims.aud, "oauth-app-api");
+            let payload: serde_json::Value = serde_json::from_slice(&base64::decode_config(issued.access_token.split('.').nth(1).unwrap(), base64::URL_SAFE_NO_PAD).unwrap()).unwrap();
+            assert!(payload.get("roles").is_none());
+            let header = jsonwebtoken::decode_header(&issued.access_token).unwrap();
+            assert_eq!(header.alg, algorithm);
+            assert_eq!(header.kid.as_deref(), Some("test-key"));
+        }
+    }
+
+    fn claims(aud: &str, exp: i64) -> AccessTokenClaims {
+        AccessTokenClaims {
+            sub: "1".to_string(),
+            email: "TestEmail@lift.com".to_string(),
+            name: "Tom".to_string(),
+            iss: "oauth-app".to_string(),
+            aud: aud.to_string(),
+            iat: exp - 900,
+            exp,
+        }
+    }
+
+    fn sign(claims: &AccessTokenClaims) -> String {
+        let mut header = Header::new(Algorithm::RS256);
+        header.kid = Some("test-key".to_string());
+        encode(&header, claims, &EncodingKey::from_rsa_pem(RSA_KEY.as_bytes()).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_expired_token() {
+        let jwt_service = jwt_service(Algorithm::RS256, RSA_KEY);
+        let token = sign(&claims("oauth-app-api", Utc::now().timestamp() - 1800));
+
+        let result = jwt_service.verify_access_token(&token);
+
+        assert_eq!(result.unwrap_err(), AppError::TokenError(TokenError::TokenExpired));
+    }
+
+    #[test]
+    fn test_token_for_another_audience() {
+        let jwt_service = jwt_service(Algorithm::RS256, RSA_KEY);
+        let token = sign(&claims("another-api", Utc::now().timestamp() + 900));
+
+        let result = jwt_service.verify_access_token(&token);
+
+        assert_error!(result, &AppError::TokenError(TokenError::InvalidToken));
+    }
+
+    #[test]
+    fn test_token_signed_by_another_key() {
+        let issued = jwt_service(Algorithm::EdDSA, ED25519_KEY).issue_access_token(&tom()).unwrap();
+
+        let result = jwt_service(Algorithm::RS256, RSA_KEY).verify_access_token(&issued.access_token);
+
+        assert_error!(result, &AppError::TokenError(TokenError::InvalidToken));
+    }
+
+    #[test]
+    fn test_jwks_publishes_public_key() {
+        let jwks = serde_json::to_value(jwt_service(Algorithm::EdDSA, ED25519_KEY).jwks()).unwrap();
+
+        assert_eq!(jwks["keys"][0]["kid"], "test-key");
+        assert_eq!(jwks["keys"][0]["kty"], "OKP");
+        assert_eq!(jwks["keys"][0]["crv"], "Ed25519");
+        assert!(jwks["keys"][0].get("d").is_none());
+    }
+}
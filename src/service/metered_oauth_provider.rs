@@ -0,0 +1,53 @@
+use std::{future::Future, sync::Arc, time::Instant};
+
+use async_trait::async_trait;
+use oauth2::PkceCodeVerifier;
+
+use crate::{error::app_error::AppError, service::{metrics::{Metrics, Outcome}, oauth_provider::{AccessTokenInfo, AuthorisationRequest, OAuthProvider, ProviderLogin}}};
+
+/// Wraps a provider to record the latency and errors of every call that reaches it.
+pub struct MeteredOAuthProvider {
+    inner: Arc<dyn OAuthProvider>,
+    metrics: Metrics,
+}
+
+impl MeteredOAuthProvider {
+    pub fn new(inner: Arc<dyn OAuthProvider>, metrics: Metrics) -> Self {
+        Self { inner, metrics }
+    }
+
+    async fn observe<T>(&self, operation: &str, call: impl Future<Output = Result<T, AppError>>) -> Result<T, AppError> {
+        let started = Instant::now();
+        let result = call.await;
+        self.metrics.observe_upstream_call(self.inner.name(), operation, started.elapsed(), Outcome::of(&result));
+        result
+    }
+}
+
+#[async_trait]
+impl OAuthProvider for MeteredOAuthProvider {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    /// Built locally, so there is no upstream call to time.
+    async fn authorisation_request(&self) -> Result<AuthorisationRequest, AppError> {
+        self.inner.authorisation_request().await
+    }
+
+    async fn complete_login(&self, code: String, pkce_verifier: PkceCodeVerifier, nonce: Option<&str>) -> Result<ProviderLogin, AppError> {
+        self.observe("complete_login", self.inner.complete_login(code, pkce_verifier, nonce)).await
+    }
+
+    async fn validate_access_token(&self, access_token: &str) -> Result<AccessTokenInfo, AppError> {
+        self.observe("validate_access_token", self.inner.validate_access_token(access_token)).await
+    }
+
+    async fn refresh(&self, refresh_token: &str) -> Result<String, AppError> {
+        self.observe("refresh", self.inner.refresh(refresh_token)).await
+    }
+
+    async fn revoke(&self, token: &str) -> Result<(), AppError> {
+        self.observe("revoke", self.inner.revoke(token)).await
+    }
+}
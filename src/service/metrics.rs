@@ -0,0 +1,182 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use prometheus::{core::Collector, Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGaugeVec, Opts, Registry, TextEncoder};
+use sqlx::MySqlPool;
+
+use crate::error::app_error::AppError;
+
+/// Label value for refreshes of our own API refresh tokens, as opposed to a provider's.
+pub const FIRST_PARTY: &str = "first_party";
+
+const LATENCY_BUCKETS: [f64; 11] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// Whether an operation succeeded, used as the `outcome` label.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Outcome {
+    Success,
+    Failure,
+}
+
+impl Outcome {
+    pub fn of<T, E>(result: &Result<T, E>) -> Self {
+        if result.is_ok() { Self::Success } else { Self::Failure }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Success => "success",
+            Self::Failure => "failure",
+        }
+    }
+}
+
+/// The Prometheus metrics served at `/metrics`. Every clone records into the same registry.
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Registry,
+    http_requests: IntCounterVec,
+    http_request_duration: HistogramVec,
+    logins_started: IntCounterVec,
+    logins_completed: IntCounterVec,
+    logins_failed: IntCounterVec,
+    csrf_mismatches: IntCounter,
+    csrf_sessions_purged: IntCounter,
+    token_refreshes: IntCounterVec,
+    token_revocations: IntCounterVec,
+    upstream_request_duration: HistogramVec,
+    upstream_errors: IntCounterVec,
+    token_info_cache_lookups: IntCounterVec,
+    db_pool_connections: IntGaugeVec,
+}
+
+impl Metrics {
+    pub fn new() -> Result<Self, AppError> {
+        let registry = Registry::new();
+        let metrics = Self {
+            http_requests: register(&registry, IntCounterVec::new(
+                Opts::new("http_requests_total", "HTTP requests by route template, method and status"),
+                &["method", "route", "status"],
+            ))?,
+            http_request_duration: register(&registry, HistogramVec::new(
+                HistogramOpts::new("http_request_duration_seconds", "HTTP request latency by route template, method and status")
+                    .buckets(LATENCY_BUCKETS.to_vec()),
+                &["method", "route", "status"],
+            ))?,
+            logins_started: register(&registry, IntCounterVec::new(
+                Opts::new("auth_logins_started_total", "Logins sent to a provider's consent screen"),
+                &["provider"],
+            ))?,
+            logins_completed: register(&registry, IntCounterVec::new(
+                Opts::new("auth_logins_completed_total", "Provider callbacks that signed a user in or linked an identity"),
+                &["provider"],
+            ))?,
+            logins_failed: register(&registry, IntCounterVec::new(
+                Opts::new("auth_logins_failed_total", "Provider callbacks that failed"),
+                &["provider"],
+            ))?,
+            csrf_mismatches: register(&registry, IntCounter::new(
+                "auth_csrf_mismatches_total",
+                "Callbacks whose state did not match the stored CSRF token",
+            ))?,
+            csrf_sessions_purged: register(&registry, IntCounter::new(
+                "auth_csrf_sessions_purged_total",
+                "Expired or used login sessions deleted by the session sweeper",
+            ))?,
+            token_refreshes: register(&registry, IntCounterVec::new(
+                Opts::new("auth_token_refreshes_total", "Access token refreshes, `first_party` for our own refresh tokens"),
+                &["provider", "outcome"],
+            ))?,
+            token_revocations: register(&registry, IntCounterVec::new(
+                Opts::new("auth_token_revocations_total", "Provider token revocations at logout"),
+                &["provider", "outcome"],
+            ))?,
+            upstream_request_duration: register(&registry, HistogramVec::new(
+                HistogramOpts::new("oauth_upstream_request_duration_seconds", "Latency of calls to OAuth providers")
+                    .buckets(LATENCY_BUCKETS.to_vec()),
+                &["provider", "operation"],
+            ))?,
+            upstream_errors: register(&registry, IntCounterVec::new(
+                Opts::new("oauth_upstream_errors_total", "Failed calls to OAuth providers"),
+                &["provider", "operation"],
+            ))?,
+            token_info_cache_lookups: register(&registry, IntCounterVec::new(
+                Opts::new("token_info_cache_lookups_total", "Access token validation cache lookups, `hit` or `miss`"),
+                &["provider", "result"],
+            ))?,
+            db_pool_connections: register(&registry, IntGaugeVec::new(
+                Opts::new("db_pool_connections", "Database pool connections by state: idle, in_use or max"),
+                &["state"],
+            ))?,
+            registry,
+        };
+        Ok(metrics)
+    }
+
+    pub fn observe_http_request(&self, method: &str, route: &str, status: u16, elapsed: Duration) {
+        let status = status.to_string();
+        let labels = [method, route, status.as_str()];
+        self.http_requests.with_label_values(&labels).inc();
+        self.http_request_duration.with_label_values(&labels).observe(elapsed.as_secs_f64());
+    }
+
+    pub fn login_started(&self, provider: &str) {
+        self.logins_started.with_label_values(&[provider]).inc();
+    }
+
+    pub fn login_finished(&self, provider: &str, outcome: Outcome) {
+        match outcome {
+            Outcome::Success => self.logins_completed.with_label_values(&[provider]).inc(),
+            Outcome::Failure => self.logins_failed.with_label_values(&[provider]).inc(),
+        }
+    }
+
+    pub fn csrf_mismatch(&self) {
+        self.csrf_mismatches.inc();
+    }
+
+    pub fn csrf_sessions_purged(&self, count: u64) {
+        self.csrf_sessions_purged.inc_by(count);
+    }
+
+    pub fn token_refreshed(&self, provider: &str, outcome: Outcome) {
+        self.token_refreshes.with_label_values(&[provider, outcome.as_str()]).inc();
+    }
+
+    pub fn token_revoked(&self, provider: &str, outcome: Outcome) {
+        self.token_revocations.with_label_values(&[provider, outcome.as_str()]).inc();
+    }
+
+    pub fn observe_upstream_call(&self, provider: &str, operation: &str, elapsed: Duration, outcome: Outcome) {
+        self.upstream_request_duration.with_label_values(&[provider, operation]).observe(elapsed.as_secs_f64());
+        if outcome == Outcome::Failure {
+            self.upstream_errors.with_label_values(&[provider, operation]).inc();
+        }
+    }
+
+    pub fn token_info_cache_lookup(&self, provider: &str, hit: bool) {
+        let result = if hit { "hit" } else { "miss" };
+        self.token_info_cache_lookups.with_label_values(&[provider, result]).inc();
+    }
+
+    /// Renders every metric in the Prometheus text format, sampling the pool first.
+    pub fn render(&self, pool: &MySqlPool) -> Result<String, AppError> {
+        let size = i64::from(pool.size());
+        let idle = i64::try_from(pool.num_idle()).unwrap_or(i64::MAX);
+        self.db_pool_connections.with_label_values(&["idle"]).set(idle);
+        self.db_pool_connections.with_label_values(&["in_use"]).set(size - idle);
+        self.db_pool_connections.with_label_values(&["max"]).set(i64::from(pool.options().get_max_connections()));
+
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .context("Failed to encode metrics")?;
+        Ok(String::from_utf8(buffer).context("Metrics are not valid UTF-8")?)
+    }
+}
+
+fn register<T: Collector + Clone + 'static>(registry: &Registry, metric: prometheus::Result<T>) -> Result<T, AppError> {
+    let metric = metric.context("Failed to create metric")?;
+    registry.register(Box::new(metric.clone())).context("Failed to register metric")?;
+    Ok(metric)
+}
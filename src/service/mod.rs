@@ -1,2 +1,18 @@
+pub mod cookie_policy;
+pub mod generic_oauth_provider;
 pub mod google_token_service;
+pub mod health_service;
+pub mod id_token_verifier;
+pub mod jwt_service;
+pub mod metered_oauth_provider;
+pub mod metrics;
+pub mod oauth_provider;
+pub mod refresh_token_service;
+pub mod return_to_policy;
+pub mod session_service;
+pub mod session_sweeper;
+pub mod shutdown_signal;
+pub mod token_cipher;
+pub mod token_info_cache;
+pub mod trace_context;
 pub mod user_service;
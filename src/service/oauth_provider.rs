@@ -0,0 +1,123 @@
+use std::{collections::HashMap, sync::Arc};
+
+use async_trait::async_trait;
+use oauth2::{CsrfToken, PkceCodeVerifier};
+use reqwest::Url;
+
+use crate::{error::app_error::AppError, service::{metered_oauth_provider::MeteredOAuthProvider, metrics::Metrics}};
+
+/// Everything needed to send the browser to a provider's consent screen.
+pub struct AuthorisationRequest {
+    pub url: Url,
+    pub csrf_token: CsrfToken,
+    pub pkce_verifier: PkceCodeVerifier,
+    /// Only set by providers that verify the id token's `nonce` claim against it.
+    pub nonce: Option<String>,
+}
+
+/// A user's profile mapped from whatever shape the provider returns it in.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct NormalizedProfile {
+    pub provider: String,
+    pub subject: String,
+    pub email: String,
+    pub email_verified: bool,
+    pub given_name: String,
+    pub family_name: String,
+}
+
+#[derive(Debug)]
+pub struct ProviderTokens {
+    pub access_token: String,
+    pub refresh_token: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct ProviderLogin {
+    pub tokens: ProviderTokens,
+    pub profile: NormalizedProfile,
+}
+
+/// The result of validating an access token with the provider that issued it.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AccessTokenInfo {
+    pub subject: String,
+    /// Remaining lifetime in seconds, when the provider reports it.
+    pub expires_in: Option<i64>,
+}
+
+#[async_trait]
+pub trait OAuthProvider: Send + Sync {
+    fn name(&self) -> &str;
+    async fn authorisation_request(&self) -> Result<AuthorisationRequest, AppError>;
+    async fn complete_login(&self, code: String, pkce_verifier: PkceCodeVerifier, nonce: Option<&str>) -> Result<ProviderLogin, AppError>;
+    async fn validate_access_token(&self, access_token: &str) -> Result<AccessTokenInfo, AppError>;
+    async fn refresh(&self, refresh_token: &str) -> Result<String, AppError>;
+    async fn revoke(&self, token: &str) -> Result<(), AppError>;
+}
+
+/// The OAuth providers configured for this deployment, keyed by the name used in
+/// `/auth/{provider}` routes.
+#[derive(Clone, Default)]
+pub struct ProviderRegistry {
+    providers: HashMap<String, Arc<dyn OAuthProvider>>,
+}
+
+impl ProviderRegistry {
+    pub fn register(&mut self, provider: Arc<dyn OAuthProvider>) {
+        tracing::info!("Registered OAuth provider {}", provider.name());
+        self.providers.insert(provider.name().to_string(), provider);
+    }
+
+    pub fn get(&self, name: &str) -> Result<Arc<dyn OAuthProvider>, AppError> {
+        self.providers
+            .get(name)
+            .cloned()
+            .ok_or_else(|| AppError::NotFound(format!("Unknown OAuth provider: {}", name)))
+    }
+
+    /// The same providers, each recording its upstream calls into `metrics`.
+    pub fn metered(self, metrics: &Metrics) -> Self {
+        let providers = self.providers
+            .into_iter()
+            .map(|(name, provider)| {
+                let provider: Arc<dyn OAuthProvider> = Arc::new(MeteredOAuthProvider::new(provider, metrics.clone()));
+                (name, provider)
+            })
+            .collect();
+        Self { providers }
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.providers.keys().cloned().collect();
+        names.sort();
+        names
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::{assert_error, error::app_error::AppError, service::google_token_service::{GoogleTokenService, TokenServiceTrait}, test_utils::placeholder_oauth_config};
+
+    use super::ProviderRegistry;
+
+    #[test]
+    fn test_registry_lookup() {
+        let mut registry = ProviderRegistry::default();
+        registry.register(Arc::new(GoogleTokenService::new(placeholder_oauth_config()).unwrap()));
+
+        assert!(registry.get("google").is_ok());
+        assert_eq!(registry.names(), vec!["google".to_string()]);
+    }
+
+    #[test]
+    fn test_registry_unknown_provider() {
+        let registry = ProviderRegistry::default();
+
+        let result = registry.get("myspace").map(|_| ());
+
+        assert_error!(result, &AppError::NotFound(String::new()));
+    }
+}
@@ -0,0 +1,183 @@
+use std::sync::Arc;
+
+use async_session::base64;
+use chrono::{DateTime, Duration, Utc};
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+
+use crate::{config::database::Database, error::{app_error::AppError, token_error::TokenError}, repository::refresh_token_repository::{RefreshTokenRecord, RefreshTokenRepository, RefreshTokenRepositoryTrait}};
+
+const REFRESH_TOKEN_LIFETIME_DAYS: i64 = 30;
+
+/// A refresh token handed to an API client, together with the user it was issued to.
+#[derive(Debug, Eq, PartialEq)]
+pub struct RotatedRefreshToken {
+    pub user_id: u64,
+    pub refresh_token: String,
+}
+
+/// First-party refresh tokens. Each login starts a token family; every refresh rotates the
+/// presented token for a new one in the same family. Only hashes are stored.
+#[derive(Clone)]
+pub struct RefreshTokenService {
+    refresh_token_repository: RefreshTokenRepository,
+}
+
+impl RefreshTokenService {
+    pub fn new(db_conn: &Arc<Database>) -> Self {
+        Self {
+            refresh_token_repository: RefreshTokenRepository::new(db_conn),
+        }
+    }
+
+    pub async fn issue(&self, user_id: u64) -> Result<String, AppError> {
+        let family_id = hex::encode(random_bytes::<16>());
+        self.add_to_family(&family_id, user_id).await
+    }
+
+    /// Exchanges a refresh token for a new one. Presenting a token that was already rotated
+    /// means it has leaked, so the whole family is revoked.
+    pub async fn rotate(&self, refresh_token: &str) -> Result<RotatedRefreshToken, AppError> {
+        let record = self.refresh_token_repository
+            .find_refresh_token(&hash_token(refresh_token))
+            .await?
+            .ok_or(TokenError::InvalidToken)?;
+
+        if record.revoked_at.is_some() {
+            return Err(TokenError::InvalidToken.into());
+        }
+        if record.rotated_at.is_some() {
+            return self.revoke_reused_family(&record).await;
+        }
+        if record.expires_at <= Utc::now() {
+            return Err(TokenError::TokenExpired.into());
+        }
+
+        let refresh_token = new_refresh_token();
+        let rotated = self.refresh_token_repository
+            .rotate_refresh_token(record.id, &record.family_id, record.user_id, &hash_token(&refresh_token), new_expiry())
+            .await?;
+        if !rotated {
+            return self.revoke_reused_family(&record).await;
+        }
+        Ok(RotatedRefreshToken { user_id: record.user_id, refresh_token })
+    }
+
+    async fn revoke_reused_family(&self, record: &RefreshTokenRecord) -> Result<RotatedRefreshToken, AppError> {
+        tracing::warn!(
+            target: "security",
+            family_id = %record.family_id,
+            user_id = record.user_id,
+            "Refresh token reuse detected, revoking the token family"
+        );
+        self.refresh_token_repository.revoke_family(&record.family_id).await?;
+        Err(TokenError::InvalidToken.into())
+    }
+
+    async fn add_to_family(&self, family_id: &str, user_id: u64) -> Result<String, AppError> {
+        let refresh_token = new_refresh_token();
+        self.refresh_token_repository
+            .add_refresh_token(family_id, user_id, &hash_token(&refresh_token), new_expiry())
+            .await?;
+        Ok(refresh_token)
+    }
+}
+
+fn new_refresh_token() -> String {
+    base64::encode_config(random_bytes::<32>(), base64::URL_SAFE_NO_PAD)
+}
+
+fn new_expiry() -> DateTime<Utc> {
+    Utc::now() + Duration::days(REFRESH_TOKEN_LIFETIME_DAYS)
+}
+
+fn random_bytes<const N: usize>() -> [u8; N] {
+    let mut bytes = [0u8; N];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    bytes
+}
+
+fn hash_token(refresh_token: &str) -> String {
+    hex::encode(Sha256::digest(refresh_token.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use chrono::{Duration, Utc};
+    use sqlx::MySqlPool;
+
+    use crate::{config::database::Database, error::{app_error::AppError, token_error::TokenError}, repository::refresh_token_repository::{RefreshTokenRepository, RefreshTokenRepositoryTrait}};
+
+    use super::{hash_token, RefreshTokenService};
+
+    async fn get_refresh_token_service(db: MySqlPool) -> (RefreshTokenService, RefreshTokenRepository) {
+        let db_conn = Arc::new(Database { pool: db });
+        (RefreshTokenService::new(&db_conn), RefreshTokenRepository::new(&db_conn))
+    }
+
+    #[sqlx::test(fixtures("./../../tests/fixtures/users.sql"))]
+    async fn test_rotation(db: MySqlPool) {
+        let (refresh_token_service, refresh_token_repository) = get_refresh_token_service(db).await;
+
+        let first = refresh_token_service.issue(1).await.unwrap();
+        let second = refresh_token_service.rotate(&first).await.unwrap();
+        let third = refresh_token_service.rotate(&second.refresh_token).await.unwrap();
+
+        assert_eq!(third.user_id, 1);
+        assert_ne!(second.refresh_token, first);
+        assert_ne!(third.refresh_token, second.refresh_token);
+
+        let stored = refresh_token_repository.find_refresh_token(&hash_token(&third.refresh_token)).await.unwrap().unwrap();
+        let original = refresh_token_repository.find_refresh_token(&hash_token(&first)).await.unwrap().unwrap();
+        assert_eq!(stored.family_id, original.family_id);
+        assert!(refresh_token_repository.find_refresh_token(&first).await.unwrap().is_none());
+    }
+
+    #[sqlx::test(fixtures("./../../tests/fixtures/users.sql"))]
+    async fn test_reuse_revokes_family(db: MySqlPool) {
+        let (refresh_token_service, _) = get_refresh_token_service(db).await;
+
+        let first = refresh_token_service.issue(1).await.unwrap();
+        let second = refresh_token_service.rotate(&first).await.unwrap();
+
+        let reuse = refresh_token_service.rotate(&first).await;
+        assert_eq!(reuse.unwrap_err(), AppError::TokenError(TokenError::InvalidToken));
+
+        let latest = refresh_token_service.rotate(&second.refresh_token).await;
+        assert_eq!(latest.unwrap_err(), AppError::TokenError(TokenError::InvalidToken));
+    }
+
+    #[sqlx::test(fixtures("./../../tests/fixtures/users.sql"))]
+    async fn test_reuse_does_not_affect_other_families(db: MySqlPool) {
+        let (refresh_token_service, _) = get_refresh_token_service(db).await;
+
+        let leaked = refresh_token_service.issue(1).await.unwrap();
+        let other_device = refresh_token_service.issue(1).await.unwrap();
+        refresh_token_service.rotate(&leaked).await.unwrap();
+        let _ = refresh_token_service.rotate(&leaked).await;
+
+        assert!(refresh_token_service.rotate(&other_device).await.is_ok());
+    }
+
+    #[sqlx::test(fixtures("./../../tests/fixtures/users.sql"))]
+    async fn test_expired_refresh_token(db: MySqlPool) {
+        let (refresh_token_service, refresh_token_repository) = get_refresh_token_service(db).await;
+
+        refresh_token_repository.add_refresh_token("family", 1, &hash_token("expired-token"), Utc::now() - Duration::minutes(1)).await.unwrap();
+
+        let result = refresh_token_service.rotate("expired-token").await;
+
+        assert_eq!(result.unwrap_err(), AppError::TokenError(TokenError::TokenExpired));
+    }
+
+    #[sqlx::test(fixtures("./../../tests/fixtures/users.sql"))]
+    async fn test_unknown_refresh_token(db: MySqlPool) {
+        let (refresh_token_service, _) = get_refresh_token_service(db).await;
+
+        let result = refresh_token_service.rotate("unknown-token").await;
+
+        assert_eq!(result.unwrap_err(), AppError::TokenError(TokenError::InvalidToken));
+    }
+}
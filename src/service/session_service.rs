@@ -0,0 +1,151 @@
+use std::sync::Arc;
+
+use async_session::base64;
+use chrono::{Duration, Utc};
+use rand::RngCore;
+
+use crate::{config::database::Database, error::app_error::AppError, repository::session_repository::{SessionRepository, SessionRepositoryTrait}, service::token_cipher::TokenCipher};
+
+pub const USER_SESSION_LIFETIME_DAYS: i64 = 7;
+
+/// A signed-in browser session with the decrypted tokens of the provider it was created with.
+#[derive(Debug, Eq, PartialEq)]
+pub struct UserSession {
+    pub user_id: u64,
+    pub provider: String,
+    pub access_token: String,
+    pub refresh_token: Option<String>,
+}
+
+#[derive(Clone)]
+pub struct SessionService {
+    session_repository: SessionRepository,
+    token_cipher: TokenCipher,
+}
+
+impl SessionService {
+    pub fn new(db_conn: &Arc<Database>, token_cipher: TokenCipher) -> Self {
+        Self {
+            session_repository: SessionRepository::new(db_conn),
+            token_cipher,
+        }
+    }
+
+    /// Stores the tokens server-side and returns the opaque session id handed to the browser.
+    pub async fn create_session(&self, user_id: u64, provider: &str, access_token: &str, refresh_token: Option<&str>) -> Result<String, AppError> {
+        let session_id = generate_session_id();
+        let expires_at = Utc::now() + Duration::days(USER_SESSION_LIFETIME_DAYS);
+        let encrypted_refresh_token = refresh_token
+            .map(|refresh_token| self.token_cipher.encrypt(refresh_token))
+            .transpose()?;
+
+        self.session_repository.add_user_session(
+            &session_id,
+            user_id,
+            provider,
+            &self.token_cipher.encrypt(access_token)?,
+            encrypted_refresh_token.as_deref(),
+            expires_at,
+        ).await?;
+
+        Ok(session_id)
+    }
+
+    pub async fn get_session(&self, session_id: &str) -> Result<Option<UserSession>, AppError> {
+        let Some(record) = self.session_repository.get_user_session(session_id).await? else {
+            return Ok(None);
+        };
+
+        Ok(Some(UserSession {
+            user_id: record.user_id,
+            provider: record.provider,
+            access_token: self.token_cipher.decrypt(&record.access_token)?,
+            refresh_token: record.refresh_token
+                .map(|refresh_token| self.token_cipher.decrypt(&refresh_token))
+                .transpose()?,
+        }))
+    }
+
+    pub async fn update_access_token(&self, session_id: &str, access_token: &str) -> Result<(), AppError> {
+        let encrypted_access_token = self.token_cipher.encrypt(access_token)?;
+        self.session_repository.update_user_session_access_token(session_id, &encrypted_access_token).await
+    }
+
+    pub async fn destroy_session(&self, session_id: &str) -> Result<(), AppError> {
+        self.session_repository.delete_user_session(session_id).await
+    }
+}
+
+pub fn generate_session_id() -> String {
+    let mut key = vec![0u8; 64];
+    rand::thread_rng().fill_bytes(&mut key);
+    base64::encode(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use sqlx::MySqlPool;
+
+    use crate::{config::database::Database, repository::session_repository::{SessionRepository, SessionRepositoryTrait}, service::token_cipher::TokenCipher};
+
+    use super::{SessionService, UserSession};
+
+    async fn get_session_service(db: MySqlPool) -> (SessionService, SessionRepository) {
+        let db_conn = Arc::new(Database { pool: db });
+        let token_cipher = TokenCipher::new(&[7u8; 32]).unwrap();
+        (SessionService::new(&db_conn, token_cipher), SessionRepository::new(&db_conn))
+    }
+
+    #[sqlx::test(fixtures("./../../tests/fixtures/users.sql"))]
+    async fn test_create_session_encrypts_tokens(db: MySqlPool) {
+        let (session_service, session_repository) = get_session_service(db).await;
+
+        let session_id = session_service.create_session(1, "google", "access-token", Some("refresh-token")).await.unwrap();
+
+        let record = session_repository.get_user_session(&session_id).await.unwrap().unwrap();
+        assert_ne!(record.access_token, "access-token");
+        assert_ne!(record.refresh_token.as_deref(), Some("refresh-token"));
+
+        let user_session = session_service.get_session(&session_id).await.unwrap();
+        assert_eq!(user_session, Some(UserSession {
+            user_id: 1,
+            provider: "google".to_string(),
+            access_token: "access-token".to_string(),
+            refresh_token: Some("refresh-token".to_string()),
+        }));
+    }
+
+    #[sqlx::test(fixtures("./../../tests/fixtures/users.sql"))]
+    async fn test_create_session_without_refresh_token(db: MySqlPool) {
+        let (session_service, _) = get_session_service(db).await;
+
+        let session_id = session_service.create_session(2, "github", "access-token", None).await.unwrap();
+
+        let user_session = session_service.get_session(&session_id).await.unwrap().unwrap();
+        assert_eq!(user_session.provider, "github");
+        assert!(user_session.refresh_token.is_none());
+    }
+
+    #[sqlx::test(fixtures("./../../tests/fixtures/users.sql"))]
+    async fn test_update_access_token(db: MySqlPool) {
+        let (session_service, _) = get_session_service(db).await;
+
+        let session_id = session_service.create_session(1, "google", "access-token", Some("refresh-token")).await.unwrap();
+        session_service.update_access_token(&session_id, "new-access-token").await.unwrap();
+
+        let user_session = session_service.get_session(&session_id).await.unwrap().unwrap();
+        assert_eq!(user_session.access_token, "new-access-token");
+    }
+
+    #[sqlx::test(fixtures("./../../tests/fixtures/users.sql"))]
+    async fn test_destroy_session(db: MySqlPool) {
+        let (session_service, _) = get_session_service(db).await;
+
+        let session_id = session_service.create_session(1, "google", "access-token", Some("refresh-token")).await.unwrap();
+        session_service.destroy_session(&session_id).await.unwrap();
+
+        assert!(session_service.get_session(&session_id).await.unwrap().is_none());
+    }
+}
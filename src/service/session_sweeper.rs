@@ -0,0 +1,98 @@
+use std::time::Duration;
+
+use tokio::{task::JoinHandle, time::MissedTickBehavior};
+
+use crate::{error::app_error::AppError, repository::session_repository::{SessionRepository, SessionRepositoryTrait}, service::{metrics::Metrics, shutdown_signal::ShutdownSignal}};
+
+/// Deletes login (CSRF) sessions that expired or were used by a callback, which would
+/// otherwise accumulate in the `sessions` table forever.
+#[derive(Clone)]
+pub struct SessionSweeper {
+    session_repository: SessionRepository,
+    metrics: Metrics,
+    batch_size: u32,
+}
+
+impl SessionSweeper {
+    pub fn new(session_repository: SessionRepository, metrics: Metrics, batch_size: u32) -> Self {
+        Self { session_repository, metrics, batch_size: batch_size.max(1) }
+    }
+
+    /// Deletes batches until a short one shows nothing is left, so a large backlog never
+    /// holds locks on the table in one long statement. Returns the number of rows deleted.
+    pub async fn sweep(&self) -> Result<u64, AppError> {
+        let mut total = 0;
+        loop {
+            let deleted = self.session_repository.delete_expired_csrf_sessions(self.batch_size).await?;
+            self.metrics.csrf_sessions_purged(deleted);
+            total += deleted;
+            if deleted < u64::from(self.batch_size) {
+                return Ok(total);
+            }
+        }
+    }
+
+    /// Sweeps every `interval` until `shutdown` is triggered. A failed sweep is logged and
+    /// retried on the next tick.
+    pub fn spawn(self, interval: Duration, shutdown: ShutdownSignal) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+            loop {
+                tokio::select! {
+                    _ = ticker.tick() => {}
+                    _ = shutdown.triggered() => return,
+                }
+                match self.sweep().await {
+                    Ok(0) => tracing::debug!("Session sweep found no expired login sessions"),
+                    Ok(deleted) => tracing::info!(deleted, "Deleted expired login sessions"),
+                    Err(error) => tracing::warn!("Session sweep failed: {}", error),
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use sqlx::MySqlPool;
+
+    use crate::{config::database::Database, repository::session_repository::{SessionRepository, SessionRepositoryTrait}, service::{metrics::Metrics, shutdown_signal::ShutdownSignal}};
+
+    use super::SessionSweeper;
+
+    async fn get_sweeper(db: MySqlPool, batch_size: u32) -> (SessionSweeper, SessionRepository, Metrics) {
+        let session_repository = SessionRepository::new(&Arc::new(Database { pool: db.clone() }));
+        let metrics = Metrics::new().unwrap();
+        let sweeper = SessionSweeper::new(session_repository.clone(), metrics.clone(), batch_size);
+        (sweeper, session_repository, metrics)
+    }
+
+    #[sqlx::test(fixtures("./../../tests/fixtures/session.sql"))]
+    async fn test_sweep_deletes_in_batches(db: MySqlPool) {
+        let (sweeper, session_repository, metrics) = get_sweeper(db.clone(), 1).await;
+        session_repository.expire_session("no_verifier_session_id").await.unwrap();
+
+        assert_eq!(sweeper.sweep().await.unwrap(), 2);
+        assert_eq!(sweeper.sweep().await.unwrap(), 0);
+
+        assert!(session_repository.get_csrf_session_by_session_id("test_session_id").await.is_ok());
+        assert!(metrics.render(&db).unwrap().contains("auth_csrf_sessions_purged_total 2"));
+    }
+
+    #[sqlx::test(fixtures("./../../tests/fixtures/session.sql"))]
+    async fn test_spawned_sweeper_stops_on_shutdown(db: MySqlPool) {
+        let (sweeper, session_repository, _) = get_sweeper(db, 100).await;
+        let shutdown = ShutdownSignal::default();
+
+        let task = sweeper.spawn(Duration::from_millis(10), shutdown.clone());
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        shutdown.trigger();
+
+        tokio::time::timeout(Duration::from_secs(1), task).await.unwrap().unwrap();
+        assert_eq!(session_repository.delete_expired_csrf_sessions(100).await.unwrap(), 0);
+    }
+}
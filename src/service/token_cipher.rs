@@ -0,0 +1,105 @@
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    Aes256Gcm, Key, Nonce,
+};
+use anyhow::Context;
+use async_session::base64;
+
+use crate::error::app_error::AppError;
+
+const NONCE_LENGTH: usize = 12;
+
+/// Encrypts OAuth tokens before they are written to the database.
+///
+/// Ciphertexts are stored as base64 of `nonce || ciphertext` using AES-256-GCM.
+#[derive(Clone)]
+pub struct TokenCipher {
+    cipher: Aes256Gcm,
+}
+
+impl std::fmt::Debug for TokenCipher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TokenCipher").finish_non_exhaustive()
+    }
+}
+
+impl TokenCipher {
+    pub fn new(key: &[u8]) -> Result<Self, AppError> {
+        if key.len() != 32 {
+            return Err(AppError::ConfigurationError(
+                "Session encryption key must be 32 bytes".to_string(),
+            ));
+        }
+        Ok(Self {
+            cipher: Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key)),
+        })
+    }
+
+    pub fn from_base64(key: &str) -> Result<Self, AppError> {
+        let key = base64::decode(key).map_err(|_| {
+            AppError::ConfigurationError("Session encryption key is not valid base64".to_string())
+        })?;
+        Self::new(&key)
+    }
+
+    pub fn encrypt(&self, plaintext: &str) -> Result<String, AppError> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, plaintext.as_bytes())
+            .map_err(|_| anyhow::anyhow!("Failed to encrypt token"))?;
+
+        let mut payload = nonce.to_vec();
+        payload.extend_from_slice(&ciphertext);
+        Ok(base64::encode(payload))
+    }
+
+    pub fn decrypt(&self, encrypted: &str) -> Result<String, AppError> {
+        let payload = base64::decode(encrypted).context("Encrypted token is not valid base64")?;
+        if payload.len() < NONCE_LENGTH {
+            return Err(anyhow::anyhow!("Encrypted token is too short").into());
+        }
+
+        let (nonce, ciphertext) = payload.split_at(NONCE_LENGTH);
+        let plaintext = self
+            .cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| anyhow::anyhow!("Failed to decrypt token"))?;
+
+        Ok(String::from_utf8(plaintext).context("Decrypted token is not valid UTF-8")?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{assert_error, error::app_error::AppError};
+
+    use super::TokenCipher;
+
+    #[test]
+    fn test_encrypt_decrypt_round_trip() {
+        let cipher = TokenCipher::new(&[7u8; 32]).unwrap();
+
+        let encrypted = cipher.encrypt("ya29.access-token").unwrap();
+
+        assert_ne!(encrypted, "ya29.access-token");
+        assert_eq!(cipher.decrypt(&encrypted).unwrap(), "ya29.access-token");
+    }
+
+    #[test]
+    fn test_decrypt_with_wrong_key_fails() {
+        let cipher = TokenCipher::new(&[7u8; 32]).unwrap();
+        let other_cipher = TokenCipher::new(&[8u8; 32]).unwrap();
+
+        let encrypted = cipher.encrypt("ya29.access-token").unwrap();
+
+        assert!(other_cipher.decrypt(&encrypted).is_err());
+    }
+
+    #[test]
+    fn test_invalid_key_length() {
+        let result = TokenCipher::new(&[7u8; 16]);
+
+        assert_error!(result, &AppError::ConfigurationError(String::new()));
+    }
+}
@@ -0,0 +1,274 @@
+use std::{collections::HashMap, sync::{atomic::{AtomicU64, Ordering}, Arc}, time::Duration};
+
+use async_trait::async_trait;
+use sha2::{Digest, Sha256};
+use tokio::{sync::RwLock, time::Instant};
+
+use crate::service::{metrics::Metrics, oauth_provider::AccessTokenInfo};
+
+pub const DEFAULT_MAX_ENTRIES: usize = 10_000;
+/// Upper bound on how long a validation result is trusted, even if the provider reports a
+/// longer remaining lifetime, so tokens revoked outside this service are noticed. Also used
+/// for providers that do not report a lifetime at all.
+pub const DEFAULT_MAX_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// Storage for access token validation results. Keys are already hashed, so a backend never sees
+/// raw access tokens.
+#[async_trait]
+pub trait TokenInfoCacheBackend: Send + Sync {
+    async fn get(&self, key: &str) -> Option<AccessTokenInfo>;
+    async fn insert(&self, key: String, token_info: AccessTokenInfo, ttl: Duration);
+    async fn remove(&self, key: &str);
+}
+
+struct CacheEntry {
+    token_info: AccessTokenInfo,
+    expires_at: Instant,
+}
+
+/// Process-local backend bounded to `max_entries`. When full, expired entries are purged
+/// first and then the entry closest to expiry is evicted.
+pub struct InMemoryTokenInfoCache {
+    entries: RwLock<HashMap<String, CacheEntry>>,
+    max_entries: usize,
+}
+
+impl InMemoryTokenInfoCache {
+    pub fn new(max_entries: usize) -> Self {
+        Self {
+            entries: RwLock::new(HashMap::new()),
+            max_entries,
+        }
+    }
+}
+
+#[async_trait]
+impl TokenInfoCacheBackend for InMemoryTokenInfoCache {
+    async fn get(&self, key: &str) -> Option<AccessTokenInfo> {
+        let entries = self.entries.read().await;
+        entries
+            .get(key)
+            .filter(|entry| entry.expires_at > Instant::now())
+            .map(|entry| entry.token_info.clone())
+    }
+
+    async fn insert(&self, key: String, token_info: AccessTokenInfo, ttl: Duration) {
+        if self.max_entries == 0 {
+            return;
+        }
+
+        let mut entries = self.entries.write().await;
+        if entries.len() >= self.max_entries && !entries.contains_key(&key) {
+            let now = Instant::now();
+            entries.retain(|_, entry| entry.expires_at > now);
+        }
+        if entries.len() >= self.max_entries && !entries.contains_key(&key) {
+            let soonest_expiring = entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.expires_at)
+                .map(|(key, _)| key.clone());
+            if let Some(soonest_expiring) = soonest_expiring {
+                entries.remove(&soonest_expiring);
+            }
+        }
+
+        entries.insert(key, CacheEntry { token_info, expires_at: Instant::now() + ttl });
+    }
+
+    async fn remove(&self, key: &str) {
+        self.entries.write().await.remove(key);
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct TokenInfoCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+/// Caches access token validation results so the auth middleware does not call the
+/// provider on every request. Entries are scoped to the provider that issued the token.
+#[derive(Clone)]
+pub struct TokenInfoCache {
+    backend: Arc<dyn TokenInfoCacheBackend>,
+    max_ttl: Duration,
+    hits: Arc<AtomicU64>,
+    misses: Arc<AtomicU64>,
+    metrics: Option<Metrics>,
+}
+
+impl TokenInfoCache {
+    pub fn new(backend: Arc<dyn TokenInfoCacheBackend>, max_ttl: Duration) -> Self {
+        Self {
+            backend,
+            max_ttl,
+            hits: Arc::new(AtomicU64::new(0)),
+            misses: Arc::new(AtomicU64::new(0)),
+            metrics: None,
+        }
+    }
+
+    /// The same cache, also counting its hits and misses into `metrics`.
+    pub fn metered(mut self, metrics: &Metrics) -> Self {
+        self.metrics = Some(metrics.clone());
+        self
+    }
+
+    pub fn in_memory() -> Self {
+        Self::new(Arc::new(InMemoryTokenInfoCache::new(DEFAULT_MAX_ENTRIES)), DEFAULT_MAX_TTL)
+    }
+
+    pub async fn get(&self, provider: &str, access_token: &str) -> Option<AccessTokenInfo> {
+        let token_info = self.backend.get(&cache_key(provider, access_token)).await;
+        match token_info {
+            Some(_) => self.hits.fetch_add(1, Ordering::Relaxed),
+            None => self.misses.fetch_add(1, Ordering::Relaxed),
+        };
+        if let Some(metrics) = &self.metrics {
+            metrics.token_info_cache_lookup(provider, token_info.is_some());
+        }
+        token_info
+    }
+
+    pub async fn insert(&self, provider: &str, access_token: &str, token_info: AccessTokenInfo) {
+        let ttl = match token_info.expires_in {
+            Some(expires_in) => {
+                let Ok(expires_in) = u64::try_from(expires_in) else {
+                    return;
+                };
+                Duration::from_secs(expires_in).min(self.max_ttl)
+            }
+            None => self.max_ttl,
+        };
+        if ttl.is_zero() {
+            return;
+        }
+        self.backend.insert(cache_key(provider, access_token), token_info, ttl).await;
+    }
+
+    pub async fn invalidate(&self, provider: &str, access_token: &str) {
+        self.backend.remove(&cache_key(provider, access_token)).await;
+    }
+
+    pub fn stats(&self) -> TokenInfoCacheStats {
+        TokenInfoCacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+}
+
+fn cache_key(provider: &str, access_token: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(provider.as_bytes());
+    hasher.update(b":");
+    hasher.update(access_token.as_bytes());
+    hex::encode(hasher.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use crate::service::oauth_provider::AccessTokenInfo;
+
+    use super::{InMemoryTokenInfoCache, TokenInfoCache, TokenInfoCacheBackend, TokenInfoCacheStats};
+
+    fn token_info(subject: &str, expires_in: i64) -> AccessTokenInfo {
+        AccessTokenInfo {
+            subject: subject.to_string(),
+            expires_in: Some(expires_in),
+        }
+    }
+
+    fn cache(max_entries: usize) -> TokenInfoCache {
+        TokenInfoCache::new(Arc::new(InMemoryTokenInfoCache::new(max_entries)), Duration::from_secs(300))
+    }
+
+    #[tokio::test]
+    async fn test_counts_hits_and_misses() {
+        let cache = cache(10);
+
+        assert!(cache.get("google", "access-token").await.is_none());
+        cache.insert("google", "access-token", token_info("1", 3600)).await;
+        assert_eq!(cache.get("google", "access-token").await, Some(token_info("1", 3600)));
+
+        assert_eq!(cache.stats(), TokenInfoCacheStats { hits: 1, misses: 1 });
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_honours_expires_in() {
+        let cache = cache(10);
+
+        cache.insert("google", "access-token", token_info("1", 30)).await;
+        tokio::time::advance(Duration::from_secs(29)).await;
+        assert!(cache.get("google", "access-token").await.is_some());
+
+        tokio::time::advance(Duration::from_secs(2)).await;
+        assert!(cache.get("google", "access-token").await.is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_ttl_is_capped() {
+        let cache = cache(10);
+
+        cache.insert("google", "access-token", token_info("1", 3600)).await;
+        tokio::time::advance(Duration::from_secs(301)).await;
+
+        assert!(cache.get("google", "access-token").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_expired_token_is_not_cached() {
+        let cache = cache(10);
+
+        cache.insert("google", "access-token", token_info("1", 0)).await;
+
+        assert!(cache.get("google", "access-token").await.is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_unknown_lifetime_uses_max_ttl() {
+        let cache = cache(10);
+
+        cache.insert("github", "access-token", AccessTokenInfo { subject: "1".to_string(), expires_in: None }).await;
+        tokio::time::advance(Duration::from_secs(299)).await;
+        assert!(cache.get("github", "access-token").await.is_some());
+
+        tokio::time::advance(Duration::from_secs(2)).await;
+        assert!(cache.get("github", "access-token").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_entries_are_scoped_to_provider() {
+        let cache = cache(10);
+
+        cache.insert("google", "access-token", token_info("1", 3600)).await;
+
+        assert!(cache.get("github", "access-token").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_invalidate() {
+        let cache = cache(10);
+
+        cache.insert("google", "access-token", token_info("1", 3600)).await;
+        cache.invalidate("google", "access-token").await;
+
+        assert!(cache.get("google", "access-token").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_in_memory_backend_is_bounded() {
+        let backend = InMemoryTokenInfoCache::new(2);
+
+        backend.insert("first".to_string(), token_info("1", 60), Duration::from_secs(60)).await;
+        backend.insert("second".to_string(), token_info("2", 120), Duration::from_secs(120)).await;
+        backend.insert("third".to_string(), token_info("3", 180), Duration::from_secs(180)).await;
+
+        assert!(backend.get("first").await.is_none());
+        assert!(backend.get("second").await.is_some());
+        assert!(backend.get("third").await.is_some());
+        assert_eq!(backend.entries.read().await.len(), 2);
+    }
+}
@@ -0,0 +1,110 @@
+use std::fmt;
+
+use rand::RngCore;
+
+/// A W3C Trace Context (`traceparent`) position: the trace a request belongs to and the
+/// span that handled it. See <https://www.w3.org/TR/trace-context/#traceparent-header>.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct TraceContext {
+    pub trace_id: [u8; 16],
+    pub span_id: [u8; 8],
+    pub sampled: bool,
+}
+
+impl TraceContext {
+    /// Starts a new, sampled trace.
+    pub fn root() -> Self {
+        Self { trace_id: random_id(), span_id: random_id(), sampled: true }
+    }
+
+    /// A new span in the same trace, e.g. this service's span under an incoming parent.
+    pub fn child(&self) -> Self {
+        Self { span_id: random_id(), ..*self }
+    }
+
+    /// Parses a version `00` header. Malformed values and all-zero ids are rejected, in
+    /// which case the caller starts a new trace.
+    pub fn parse(traceparent: &str) -> Option<Self> {
+        let mut parts = traceparent.trim().split('-');
+        let (version, trace_id, span_id, flags) = (parts.next()?, parts.next()?, parts.next()?, parts.next()?);
+        if version != "00" || parts.next().is_some() {
+            return None;
+        }
+
+        let trace_id: [u8; 16] = decode_id(trace_id)?;
+        let span_id: [u8; 8] = decode_id(span_id)?;
+        let [flags]: [u8; 1] = decode_id(flags)?;
+        Some(Self { trace_id, span_id, sampled: flags & 1 == 1 })
+    }
+
+    pub fn trace_id_hex(&self) -> String {
+        hex::encode(self.trace_id)
+    }
+}
+
+impl fmt::Display for TraceContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "00-{}-{}-{:02x}", hex::encode(self.trace_id), hex::encode(self.span_id), u8::from(self.sampled))
+    }
+}
+
+fn decode_id<const N: usize>(value: &str) -> Option<[u8; N]> {
+    if value.len() != N * 2 || value.bytes().any(|byte| byte.is_ascii_uppercase()) {
+        return None;
+    }
+    let mut id = [0u8; N];
+    hex::decode_to_slice(value, &mut id).ok()?;
+    // Ids must not be all zeros; the one-byte flags field may be.
+    if N > 1 && id.iter().all(|byte| *byte == 0) {
+        return None;
+    }
+    Some(id)
+}
+
+fn random_id<const N: usize>() -> [u8; N] {
+    let mut id = [0u8; N];
+    rand::thread_rng().fill_bytes(&mut id);
+    id
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TraceContext;
+
+    const TRACEPARENT: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+    #[test]
+    fn test_parse_and_format_round_trip() {
+        let context = TraceContext::parse(TRACEPARENT).unwrap();
+
+        assert_eq!(context.trace_id_hex(), "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert!(context.sampled);
+        assert_eq!(context.to_string(), TRACEPARENT);
+    }
+
+    #[test]
+    fn test_child_keeps_the_trace() {
+        let parent = TraceContext::parse(TRACEPARENT).unwrap();
+
+        let child = parent.child();
+
+        assert_eq!(child.trace_id, parent.trace_id);
+        assert_ne!(child.span_id, parent.span_id);
+    }
+
+    #[test]
+    fn test_parse_rejects_invalid_headers() {
+        for traceparent in [
+            "",
+            "01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+            "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra",
+            "00-not-hex-01",
+        ] {
+            assert_eq!(TraceContext::parse(traceparent), None, "{} was accepted", traceparent);
+        }
+    }
+}
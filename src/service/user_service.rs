@@ -1,66 +1,183 @@
 use std::sync::Arc;
 
-use crate::{config::database::Database, error::app_error::AppError, repository::user_repository::{UserRepository, UserRepositoryTrait}, state::app_state::UserContext, User};
+use serde::Deserialize;
 
+use crate::{config::database::Database, error::app_error::AppError, repository::{identity_repository::{IdentityRepository, IdentityRepositoryTrait, UserIdentity}, role_repository::{RoleRepository, RoleRepositoryTrait, ADMIN_ROLE}, user_repository::{UserProfile, UserRepository, UserRepositoryTrait}}, service::oauth_provider::NormalizedProfile, state::app_state::UserContext};
+
+
+/// Length limit of the `users` name columns.
+const MAX_NAME_LENGTH: usize = 64;
+
+/// The fields a user may change about themselves. Anything else, e.g. `email`, is rejected.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProfileUpdate {
+    pub first_name: Option<String>,
+    pub last_name: Option<String>,
+}
 
 #[derive(Clone)]
 pub struct UserService {
     user_repository: UserRepository,
+    identity_repository: IdentityRepository,
+    role_repository: RoleRepository,
+    bootstrap_admin_email: Option<String>,
 }
 
 impl UserService {
     pub fn new(db_conn: &Arc<Database>) -> Self {
         Self {
             user_repository: UserRepository::new(db_conn),
+            identity_repository: IdentityRepository::new(db_conn),
+            role_repository: RoleRepository::new(db_conn),
+            bootstrap_admin_email: None,
         }
     }
 
-    pub async fn find_or_insert_user(&self, user_data: &User) -> Result<UserContext, AppError> {
-        let existing_user = self.user_repository.find_user_by_google_id(&user_data.sub).await?;
+    /// The user with this email address is made an admin when they log in.
+    pub fn with_bootstrap_admin_email(mut self, email: Option<String>) -> Self {
+        self.bootstrap_admin_email = email;
+        self
+    }
+
+    /// Returns the user linked to the provider identity, or a new user for an unknown identity
+    /// with a verified email. An unknown identity is never linked to an existing user by email,
+    /// since any provider can claim an address; that takes the signed-in `link_identity` flow.
+    pub async fn find_or_insert_user(&self, profile: &NormalizedProfile) -> Result<UserContext, AppError> {
+        let existing_user = self.identity_repository.find_user_by_identity(&profile.provider, &profile.subject).await?;
         if let Some(user_context) = existing_user {
-            return Ok(user_context);
+            return self.bootstrap_admin(user_context).await;
         }
-    
-        let user_id = self.user_repository.add_user(
-                &user_data.sub,
-                &user_data.email, 
-                &user_data.given_name, 
-                &user_data.family_name).await?;
-    
-        Ok(UserContext {
+
+        if !profile.email_verified {
+            return Err(AppError::BadRequest(format!("{} has not verified this email address", profile.provider)));
+        }
+
+        if self.user_repository.find_user_by_email(&profile.email).await?.is_some() {
+            return Err(email_taken(profile));
+        }
+
+        let added = self.identity_repository.add_user_with_identity(
+                &profile.email, 
+                &profile.given_name, 
+                &profile.family_name,
+                &profile.provider,
+                &profile.subject).await;
+        let user_id = match added {
+            Ok(user_id) => user_id,
+            // A concurrent first login got there first: the same identity, which is then
+            // this user, or another one with the same email.
+            Err(AppError::Conflict(_)) => {
+                let existing_user = self.identity_repository.find_user_by_identity(&profile.provider, &profile.subject).await?;
+                return match existing_user {
+                    Some(user_context) => self.bootstrap_admin(user_context).await,
+                    None => Err(email_taken(profile)),
+                };
+            }
+            Err(error) => return Err(error),
+        };
+        let user_context = UserContext {
             user_id,
-            email: user_data.email.clone(),
-            name: user_data.given_name.clone(),
-        })
+            email: profile.email.clone(),
+            name: profile.given_name.clone(),
+            roles: Vec::new(),
+        };
+
+        self.bootstrap_admin(user_context).await
+    }
+
+    async fn bootstrap_admin(&self, mut user_context: UserContext) -> Result<UserContext, AppError> {
+        let is_bootstrap_admin = self.bootstrap_admin_email.as_deref()
+            .is_some_and(|email| email.eq_ignore_ascii_case(&user_context.email));
+        if is_bootstrap_admin && !user_context.has_role(ADMIN_ROLE) {
+            tracing::info!(user_id = user_context.user_id, "Granting the admin role to the bootstrap admin");
+            self.role_repository.assign_role(user_context.user_id, ADMIN_ROLE).await?;
+            user_context.roles.push(ADMIN_ROLE.to_string());
+        }
+        Ok(user_context)
+    }
+
+    pub async fn link_identity(&self, user_id: u64, profile: &NormalizedProfile) -> Result<(), AppError> {
+        self.identity_repository.link_identity(user_id, &profile.provider, &profile.subject, &profile.email).await
+    }
+
+    pub async fn unlink_identity(&self, user_id: u64, provider: &str) -> Result<(), AppError> {
+        self.identity_repository.unlink_identity(user_id, provider).await
+    }
+
+    pub async fn list_identities(&self, user_id: u64) -> Result<Vec<UserIdentity>, AppError> {
+        self.identity_repository.list_identities(user_id).await
+    }
+
+    pub async fn get_profile(&self, user_id: u64) -> Result<UserProfile, AppError> {
+        self.user_repository.find_user_profile(user_id).await?
+            .ok_or_else(|| AppError::NotFound(format!("User {} does not exist", user_id)))
+    }
+
+    /// Applies the update and returns the resulting profile.
+    pub async fn update_profile(&self, user_id: u64, update: ProfileUpdate) -> Result<UserProfile, AppError> {
+        let first_name = update.first_name.as_deref().map(str::trim);
+        let last_name = update.last_name.as_deref().map(str::trim);
+        if first_name.is_some_and(str::is_empty) {
+            return Err(AppError::BadRequest("first_name must not be empty".to_string()));
+        }
+        for (field, value) in [("first_name", first_name), ("last_name", last_name)] {
+            if value.is_some_and(|value| value.chars().count() > MAX_NAME_LENGTH) {
+                return Err(AppError::BadRequest(format!("{} must be at most {} characters", field, MAX_NAME_LENGTH)));
+            }
+        }
+
+        self.user_repository.update_user_names(user_id, first_name, last_name).await?;
+        self.get_profile(user_id).await
     }
 }
 
+fn email_taken(profile: &NormalizedProfile) -> AppError {
+    AppError::Conflict(format!(
+        "An account with this email address already exists. Sign in with its provider and link {} from the account page",
+        profile.provider,
+    ))
+}
+
 #[cfg(test)]
 mod tests {
     use std::sync::Arc;
 
     use sqlx::MySqlPool;
 
+    use crate::{assert_error, error::app_error::AppError, service::oauth_provider::NormalizedProfile};
     use crate::state::app_state::UserContext;
-    use crate::User;
     use crate::config::database::Database;
 
-    use super::UserService;
+    use super::{ProfileUpdate, UserService};
 
     async fn get_user_service(db: MySqlPool) -> UserService {
         let db_conn = Database { pool: db };
         UserService::new(&Arc::new(db_conn))
     }
 
+    fn profile(provider: &str, subject: &str, email: &str, email_verified: bool) -> NormalizedProfile {
+        NormalizedProfile {
+            provider: provider.to_string(),
+            subject: subject.to_string(),
+            email: email.to_string(),
+            email_verified,
+            given_name: "George".to_string(),
+            family_name: "Thomas".to_string(),
+        }
+    }
+
     #[sqlx::test(fixtures("./../../tests/fixtures/users.sql"))]
     async fn test_find_or_insert_user_for_existing_user(db: MySqlPool) {
         let user_service = get_user_service(db).await;
 
-        let test_user = User {
-            sub: "110235950686105464135".to_string(),
+        let test_user = NormalizedProfile {
+            provider: "google".to_string(),
+            subject: "110235950686105464135".to_string(),
+            email: "TestEmail@lift.com".to_string(),
+            email_verified: true,
             given_name: "Tom".to_string(),
             family_name: "Gill".to_string(),
-            email: "TestEmail@lift.com".to_string(),
         };
 
         let result = user_service.find_or_insert_user(&test_user).await;
@@ -69,6 +186,7 @@ mod tests {
             user_id: 1,
             email: "TestEmail@lift.com".to_string(),
             name: "Tom".to_string(),
+            roles: Vec::new(),
         };
 
         assert!(result.is_ok());
@@ -79,12 +197,7 @@ mod tests {
     async fn test_find_or_insert_user_for_new_user(db: MySqlPool) {
         let user_service = get_user_service(db).await;
 
-        let test_user = User {
-            sub: "897239842378324289342".to_string(),
-            given_name: "George".to_string(),
-            family_name: "Thomas".to_string(),
-            email: "gt@lift.com".to_string(),
-        };
+        let test_user = profile("google", "897239842378324289342", "gt@lift.com", true);
 
         let result = user_service.find_or_insert_user(&test_user).await;
 
@@ -92,9 +205,104 @@ mod tests {
             user_id: 3,
             email: "gt@lift.com".to_string(),
             name: "George".to_string(),
+            roles: Vec::new(),
         };
 
         assert!(result.is_ok());
         assert_eq!(result.unwrap(), expected_user_context);
     }
+
+    #[sqlx::test(fixtures("./../../tests/fixtures/users.sql"))]
+    async fn test_find_or_insert_user_does_not_link_by_email(db: MySqlPool) {
+        let user_service = get_user_service(db).await;
+
+        let result = user_service.find_or_insert_user(&profile("github", "583231", "TestEmail-2@lift.com", true)).await;
+
+        assert_error!(result, &AppError::Conflict(String::new()));
+        assert_eq!(user_service.list_identities(2).await.unwrap().len(), 1);
+    }
+
+    #[sqlx::test(fixtures("./../../tests/fixtures/users.sql"))]
+    async fn test_find_or_insert_user_in_parallel_for_the_same_email(db: MySqlPool) {
+        let user_service = get_user_service(db).await;
+
+        let attempts = ["1001", "1002", "1003", "1004"].map(|subject| {
+            let user_service = user_service.clone();
+            tokio::spawn(async move { user_service.find_or_insert_user(&profile("github", subject, "gt@lift.com", true)).await })
+        });
+        let mut created = Vec::new();
+        for attempt in attempts {
+            match attempt.await.unwrap() {
+                Ok(user_context) => created.push(user_context.user_id),
+                result => {
+                    assert_error!(result, &AppError::Conflict(String::new()));
+                }
+            }
+        }
+
+        assert_eq!(created.len(), 1);
+        assert_eq!(user_service.list_identities(created[0]).await.unwrap().len(), 1);
+    }
+
+    #[sqlx::test(fixtures("./../../tests/fixtures/users.sql"))]
+    async fn test_find_or_insert_user_in_parallel_for_the_same_identity(db: MySqlPool) {
+        let user_service = get_user_service(db).await;
+
+        let attempts = [0; 4].map(|_| {
+            let user_service = user_service.clone();
+            tokio::spawn(async move { user_service.find_or_insert_user(&profile("github", "1001", "gt@lift.com", true)).await })
+        });
+        let mut user_ids = Vec::new();
+        for attempt in attempts {
+            user_ids.push(attempt.await.unwrap().unwrap().user_id);
+        }
+
+        user_ids.dedup();
+        assert_eq!(user_ids.len(), 1);
+    }
+
+    #[sqlx::test(fixtures("./../../tests/fixtures/users.sql"))]
+    async fn test_find_or_insert_user_finds_explicitly_linked_identity(db: MySqlPool) {
+        let user_service = get_user_service(db).await;
+        user_service.link_identity(2, &profile("github", "583231", "octocat@lift.com", false)).await.unwrap();
+
+        let result = user_service.find_or_insert_user(&profile("github", "583231", "octocat@lift.com", false)).await;
+
+        assert_eq!(result.unwrap().user_id, 2);
+    }
+
+    #[sqlx::test(fixtures("./../../tests/fixtures/users.sql"))]
+    async fn test_find_or_insert_user_rejects_unverified_email(db: MySqlPool) {
+        let user_service = get_user_service(db).await;
+
+        let result = user_service.find_or_insert_user(&profile("github", "583231", "TestEmail-2@lift.com", false)).await;
+
+        assert_error!(result, &AppError::BadRequest(String::new()));
+    }
+
+    #[sqlx::test(fixtures("./../../tests/fixtures/users.sql"))]
+    async fn test_bootstrap_admin_is_granted_admin_role(db: MySqlPool) {
+        let user_service = get_user_service(db).await.with_bootstrap_admin_email(Some("testemail-2@lift.com".to_string()));
+
+        let patrick = user_service.find_or_insert_user(&profile("google", "107329637626229533241", "TestEmail-2@lift.com", true)).await.unwrap();
+        let new_user = user_service.find_or_insert_user(&profile("google", "897239842378324289342", "gt@lift.com", true)).await.unwrap();
+        let patrick_again = user_service.find_or_insert_user(&profile("google", "107329637626229533241", "TestEmail-2@lift.com", true)).await.unwrap();
+
+        assert_eq!(patrick.roles, vec!["admin"]);
+        assert!(new_user.roles.is_empty());
+        assert_eq!(patrick_again.roles, vec!["admin"]);
+    }
+
+    #[sqlx::test(fixtures("./../../tests/fixtures/users.sql"))]
+    async fn test_update_profile_validates_names(db: MySqlPool) {
+        let user_service = get_user_service(db).await;
+
+        let empty = user_service.update_profile(1, ProfileUpdate { first_name: Some("  ".to_string()), ..Default::default() }).await;
+        let too_long = user_service.update_profile(1, ProfileUpdate { last_name: Some("x".repeat(65)), ..Default::default() }).await;
+        let updated = user_service.update_profile(1, ProfileUpdate { first_name: Some(" Thomas ".to_string()), ..Default::default() }).await;
+
+        assert_error!(empty, &AppError::BadRequest(String::new()));
+        assert_error!(too_long, &AppError::BadRequest(String::new()));
+        assert_eq!(updated.unwrap().first_name, "Thomas");
+    }
 }
@@ -1,116 +1,79 @@
 use std::sync::Arc;
 
-use axum::extract::FromRef;
-use oauth2::basic::BasicClient;
 use reqwest::Client;
-use tokio::sync::RwLock;
 
-use crate::{config::database::Database, error::app_error::AppError, repository::{session_repository::{SessionRepository, SessionRepositoryTrait}, user_repository::{UserRepository, UserRepositoryTrait}}, service::{google_token_service::{GoogleTokenService, TokenServiceTrait}, user_service::UserService}};
+use crate::{config::database::Database, error::app_error::AppError, repository::{session_repository::{SessionRepository, SessionRepositoryTrait}, user_repository::{UserRepository, UserRepositoryTrait}}, service::{cookie_policy::CookiePolicy, health_service::HealthService, jwt_service::JwtService, metrics::Metrics, oauth_provider::ProviderRegistry, refresh_token_service::RefreshTokenService, return_to_policy::ReturnToPolicy, session_service::SessionService, shutdown_signal::ShutdownSignal, token_cipher::TokenCipher, token_info_cache::TokenInfoCache, user_service::UserService}};
 
 #[derive(Clone, Eq, PartialEq, Debug)]
 pub struct UserContext {
     pub user_id: u64,
     pub email: String,
     pub name: String,
+    pub roles: Vec<String>,
+}
+
+impl UserContext {
+    pub fn has_role(&self, role: &str) -> bool {
+        self.roles.iter().any(|user_role| user_role == role)
+    }
 }
 
 #[derive(Clone)]
 pub struct AppState {
     pub database: Arc<Database>,
     pub http_client: Client,
-    pub user_context: Arc<RwLock<Option<UserContext>>>,
-    pub google_token_service: GoogleTokenService,
+    pub providers: ProviderRegistry,
+    pub health_service: HealthService,
+    pub shutdown: ShutdownSignal,
+    pub metrics: Metrics,
     pub user_service: UserService,
+    pub session_service: SessionService,
+    pub token_info_cache: TokenInfoCache,
+    pub jwt_service: JwtService,
+    pub refresh_token_service: RefreshTokenService,
+    pub return_to_policy: ReturnToPolicy,
+    pub cookie_policy: CookiePolicy,
     pub user_repository: UserRepository,
     pub session_repository: SessionRepository,
 }
 
-impl FromRef<AppState> for GoogleTokenService {
-    fn from_ref(state: &AppState) -> Self {
-        state.google_token_service.clone()
-    }
-}
-
 impl AppState {
-    pub async fn new(db: Database, oauth_client: BasicClient) -> Result<Self, AppError> {
+    pub async fn new(db: Database, providers: ProviderRegistry, token_cipher: TokenCipher, jwt_service: JwtService, shutdown: ShutdownSignal) -> Result<Self, AppError> {
         let db_conn = Arc::new(db);
+        let metrics = Metrics::new()?;
+        let providers = providers.metered(&metrics);
+        let token_info_cache = TokenInfoCache::in_memory().metered(&metrics);
         Ok(Self {
             database: db_conn.clone(),
             http_client: Client::new(),
-            user_context: Arc::new(RwLock::new(None)),
-            google_token_service: GoogleTokenService::new(oauth_client),
+            health_service: HealthService::new(&db_conn, &providers, &shutdown),
+            shutdown,
+            metrics,
+            providers,
             user_service: UserService::new(&db_conn),
+            session_service: SessionService::new(&db_conn, token_cipher),
+            token_info_cache,
+            jwt_service,
+            refresh_token_service: RefreshTokenService::new(&db_conn),
+            return_to_policy: ReturnToPolicy::default(),
+            cookie_policy: CookiePolicy::default(),
             user_repository: UserRepository::new(&db_conn),
             session_repository: SessionRepository::new(&db_conn),
         })
     }
 
-    pub async fn get_user_id(&self) -> Option<u64> {
-        self.user_context.read().await.as_ref().map(|user| user.user_id)
+    pub fn with_bootstrap_admin_email(mut self, email: Option<String>) -> Self {
+        self.user_service = self.user_service.with_bootstrap_admin_email(email);
+        self
     }
 
-    pub async fn set_user_context(&self, user_context: UserContext) {
-        let mut user_context_lock = self.user_context.write().await;
-        *user_context_lock = Some(user_context);
-    }
-
-    pub async fn clear_user_context(&self) {
-        let mut user_context_lock = self.user_context.write().await;
-        *user_context_lock = None;
-    }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::config::database::Database;
-    use oauth2::{AuthUrl, ClientId, ClientSecret, TokenUrl};
-    use sqlx::MySqlPool;
-    
-    async fn setup(db: MySqlPool) -> AppState {
-        let db_conn = Database { pool: db };
-        let placeholder_client = BasicClient::new(
-            ClientId::new("test-client-id".to_string()),
-            Some(ClientSecret::new("test-client-secret".to_string())),
-            AuthUrl::new("https://test.auth.url".to_string()).unwrap(),
-            Some(TokenUrl::new("https://test.token.url".to_string()).unwrap())
-        );
-        AppState::new(db_conn, placeholder_client).await.unwrap()
+    pub fn with_return_to_policy(mut self, return_to_policy: ReturnToPolicy) -> Self {
+        self.return_to_policy = return_to_policy;
+        self
     }
 
-    #[sqlx::test]
-    async fn test_user_context_operations(db: MySqlPool) {
-        let app_state = setup(db).await;
-        let test_user = UserContext { user_id: 1, email: "test@lift.com".to_string(), name: "John".to_string() };
-
-        assert!(app_state.get_user_id().await.is_none());
-
-        app_state.set_user_context(test_user).await;
-        assert_eq!(app_state.get_user_id().await, Some(1));
-
-        app_state.clear_user_context().await;
-        assert!(app_state.get_user_id().await.is_none());
-    }
-
-    #[sqlx::test]
-    async fn test_concurrent_access(db: MySqlPool) {
-        let app_state = setup(db).await;
-        let app_state_clone = app_state.clone();
-        let app_state_clone2 = app_state.clone();
-        let test_user = UserContext { user_id: 1, email: "test@lift.com".to_string(), name: "John".to_string() };
-
-        let handle1 = tokio::spawn(async move {
-            app_state.set_user_context(test_user).await;
-        });
-
-        let handle2 = tokio::spawn(async move {
-            app_state_clone.clear_user_context().await;
-        });
-
-        tokio::try_join!(handle1, handle2).unwrap();
-
-        let final_state = app_state_clone2.get_user_id().await;
-
-        assert!(final_state.is_none() || final_state == Some(1));
+    pub fn with_cookie_policy(mut self, cookie_policy: CookiePolicy) -> Self {
+        self.cookie_policy = cookie_policy;
+        self
     }
 }
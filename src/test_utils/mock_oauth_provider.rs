@@ -0,0 +1,388 @@
+use std::{collections::{HashMap, VecDeque}, sync::{Arc, Mutex}};
+
+use async_session::base64;
+use axum::{extract::{Form, Query, State}, response::{IntoResponse, Redirect, Response}, routing::{get, post}, Json, Router};
+use axum_extra::{headers::{authorization::{Basic, Bearer}, Authorization}, TypedHeader};
+use chrono::Utc;
+use http::StatusCode;
+use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+use oauth2::{url::Url, CsrfToken};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+use crate::config::oauth_provider_config::OAuthProviderConfig;
+
+use super::placeholder_oauth_config;
+
+const SIGNING_KEY: &str = include_str!("../../tests/fixtures/id_token_signing_key.pem");
+const JWKS: &str = include_str!("../../tests/fixtures/jwks.json");
+const SIGNING_KEY_ID: &str = "test-key-1";
+const ACCESS_TOKEN_LIFETIME_SECONDS: i64 = 3600;
+
+/// The account that signs in at the mock consent screen.
+#[derive(Clone, Debug)]
+pub struct MockUser {
+    pub sub: String,
+    pub email: String,
+    pub email_verified: bool,
+    pub given_name: String,
+    pub family_name: String,
+}
+
+impl MockUser {
+    /// Tom from `tests/fixtures/users.sql`.
+    pub fn tom() -> Self {
+        Self {
+            sub: "110235950686105464135".to_string(),
+            email: "TestEmail@lift.com".to_string(),
+            email_verified: true,
+            given_name: "Tom".to_string(),
+            family_name: "Gill".to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum MockEndpoint {
+    Authorize,
+    Token,
+    TokenInfo,
+    UserInfo,
+    Revoke,
+    Jwks,
+}
+
+struct PendingAuthorisation {
+    client_id: String,
+    redirect_uri: String,
+    nonce: Option<String>,
+    code_challenge: Option<String>,
+    user: MockUser,
+}
+
+struct Grant {
+    user: MockUser,
+    client_id: String,
+    revoked: bool,
+}
+
+#[derive(Default)]
+struct MockStore {
+    user: Option<MockUser>,
+    pending_authorisations: HashMap<String, PendingAuthorisation>,
+    grants: Vec<Grant>,
+    access_tokens: HashMap<String, usize>,
+    refresh_tokens: HashMap<String, usize>,
+    revoked_tokens: Vec<String>,
+    client_secrets: Vec<String>,
+    scripted_responses: HashMap<MockEndpoint, VecDeque<(StatusCode, Value)>>,
+    requests: HashMap<MockEndpoint, usize>,
+}
+
+impl MockStore {
+    /// Counts the request and pops the next scripted response for the endpoint, if any.
+    fn scripted_response(&mut self, endpoint: MockEndpoint) -> Option<Response> {
+        *self.requests.entry(endpoint).or_default() += 1;
+        self.scripted_responses
+            .get_mut(&endpoint)
+            .and_then(VecDeque::pop_front)
+            .map(|(status, body)| (status, Json(body)).into_response())
+    }
+
+    fn active_grant(&self, access_token: &str) -> Option<&Grant> {
+        self.access_tokens
+            .get(access_token)
+            .map(|grant_id| &self.grants[*grant_id])
+            .filter(|grant| !grant.revoked)
+    }
+
+    fn issue_access_token(&mut self, grant_id: usize) -> String {
+        let access_token = format!("mock-access-{}", CsrfToken::new_random().secret());
+        self.access_tokens.insert(access_token.clone(), grant_id);
+        access_token
+    }
+}
+
+#[derive(Clone)]
+struct MockState {
+    issuer: String,
+    store: Arc<Mutex<MockStore>>,
+}
+
+/// A local stand-in for Google's OAuth 2.0 and OpenID Connect endpoints. It runs on a random
+/// port for the lifetime of the test, signs id tokens with the key from `tests/fixtures`
+/// and lets tests script failures and inspect what the app sent it.
+pub struct MockOAuthProvider {
+    base_url: String,
+    state: MockState,
+}
+
+impl MockOAuthProvider {
+    pub async fn spawn() -> Self {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let state = MockState {
+            issuer: base_url.clone(),
+            store: Arc::new(Mutex::new(MockStore::default())),
+        };
+
+        let app = Router::new()
+            .route("/authorize", get(authorize))
+            .route("/token", post(token))
+            .route("/tokeninfo", get(token_info))
+            .route("/userinfo", get(user_info))
+            .route("/revoke", post(revoke))
+            .route("/certs", get(jwks))
+            .with_state(state.clone());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        Self { base_url, state }
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base_url, path)
+    }
+
+    /// Google settings pointing every endpoint at the mock, redirecting back to
+    /// `/auth/google/authorized` on the app under test.
+    pub fn oauth_config(&self) -> OAuthProviderConfig {
+        OAuthProviderConfig {
+            auth_url: self.url("/authorize"),
+            token_url: self.url("/token"),
+            revocation_url: self.url("/revoke"),
+            token_info_url: self.url("/tokeninfo"),
+            userinfo_url: self.url("/userinfo"),
+            jwks_url: self.url("/certs"),
+            issuer: self.base_url.clone(),
+            ..placeholder_oauth_config()
+        }
+    }
+
+    /// The account used by subsequent authorisations. Defaults to [`MockUser::tom`].
+    pub fn set_user(&self, user: MockUser) {
+        self.store().user = Some(user);
+    }
+
+    /// Answers the next request to `endpoint` with `status` and `body` instead of the
+    /// normal behaviour.
+    pub fn respond_once(&self, endpoint: MockEndpoint, status: StatusCode, body: Value) {
+        self.store().scripted_responses.entry(endpoint).or_default().push_back((status, body));
+    }
+
+    /// Makes every access token issued so far unknown, as if it had expired.
+    pub fn expire_access_tokens(&self) {
+        self.store().access_tokens.clear();
+    }
+
+    pub fn revoked_tokens(&self) -> Vec<String> {
+        self.store().revoked_tokens.clone()
+    }
+
+    /// The client secrets the app authenticated with at the token endpoint, oldest first.
+    pub fn client_secrets(&self) -> Vec<String> {
+        self.store().client_secrets.clone()
+    }
+
+    pub fn request_count(&self, endpoint: MockEndpoint) -> usize {
+        self.store().requests.get(&endpoint).copied().unwrap_or_default()
+    }
+
+    /// Plays the browser at the consent screen: follows the app's redirect to the mock and
+    /// returns the path and query the mock redirects back to.
+    pub async fn authorize(&self, authorisation_url: &str) -> String {
+        let response = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .unwrap()
+            .get(authorisation_url)
+            .send()
+            .await
+            .unwrap();
+        let location = response.headers()[http::header::LOCATION].to_str().unwrap();
+        let callback = Url::parse(location).unwrap();
+        match callback.query() {
+            Some(query) => format!("{}?{}", callback.path(), query),
+            None => callback.path().to_string(),
+        }
+    }
+
+    fn store(&self) -> std::sync::MutexGuard<'_, MockStore> {
+        self.state.store.lock().unwrap()
+    }
+}
+
+#[derive(Deserialize)]
+struct AuthorizeQuery {
+    client_id: String,
+    redirect_uri: String,
+    state: String,
+    nonce: Option<String>,
+    code_challenge: Option<String>,
+}
+
+async fn authorize(State(state): State<MockState>, Query(query): Query<AuthorizeQuery>) -> Response {
+    let mut store = state.store.lock().unwrap();
+    if let Some(response) = store.scripted_response(MockEndpoint::Authorize) {
+        return response;
+    }
+
+    let code = CsrfToken::new_random().secret().to_string();
+    let mut redirect = Url::parse(&query.redirect_uri).unwrap();
+    redirect.query_pairs_mut().append_pair("code", &code).append_pair("state", &query.state);
+    let user = store.user.clone().unwrap_or_else(MockUser::tom);
+    store.pending_authorisations.insert(code, PendingAuthorisation {
+        client_id: query.client_id,
+        redirect_uri: query.redirect_uri,
+        nonce: query.nonce,
+        code_challenge: query.code_challenge,
+        user,
+    });
+
+    Redirect::to(redirect.as_str()).into_response()
+}
+
+async fn token(State(state): State<MockState>, client: Option<TypedHeader<Authorization<Basic>>>, Form(form): Form<HashMap<String, String>>) -> Response {
+    let mut store = state.store.lock().unwrap();
+    if let Some(TypedHeader(Authorization(client))) = client {
+        store.client_secrets.push(client.password().to_string());
+    }
+    if let Some(response) = store.scripted_response(MockEndpoint::Token) {
+        return response;
+    }
+
+    let param = |name: &str| form.get(name).map(String::as_str).unwrap_or_default();
+    match param("grant_type") {
+        "authorization_code" => {
+            let Some(authorisation) = store.pending_authorisations.remove(param("code")) else {
+                return oauth_error(StatusCode::BAD_REQUEST, "invalid_grant");
+            };
+            let challenge = base64::encode_config(Sha256::digest(param("code_verifier").as_bytes()), base64::URL_SAFE_NO_PAD);
+            if authorisation.code_challenge.as_deref() != Some(challenge.as_str()) || authorisation.redirect_uri != param("redirect_uri") {
+                return oauth_error(StatusCode::BAD_REQUEST, "invalid_grant");
+            }
+
+            let id_token = sign_id_token(&state.issuer, &authorisation);
+            let grant_id = store.grants.len();
+            store.grants.push(Grant {
+                user: authorisation.user,
+                client_id: authorisation.client_id,
+                revoked: false,
+            });
+            let access_token = store.issue_access_token(grant_id);
+            let refresh_token = format!("mock-refresh-{}", CsrfToken::new_random().secret());
+            store.refresh_tokens.insert(refresh_token.clone(), grant_id);
+
+            Json(json!({
+                "access_token": access_token,
+                "token_type": "Bearer",
+                "expires_in": ACCESS_TOKEN_LIFETIME_SECONDS,
+                "refresh_token": refresh_token,
+                "id_token": id_token,
+            })).into_response()
+        }
+        "refresh_token" => {
+            let grant_id = store.refresh_tokens.get(param("refresh_token")).copied()
+                .filter(|grant_id| !store.grants[*grant_id].revoked);
+            let Some(grant_id) = grant_id else {
+                return oauth_error(StatusCode::BAD_REQUEST, "invalid_grant");
+            };
+            let access_token = store.issue_access_token(grant_id);
+
+            Json(json!({
+                "access_token": access_token,
+                "token_type": "Bearer",
+                "expires_in": ACCESS_TOKEN_LIFETIME_SECONDS,
+            })).into_response()
+        }
+        _ => oauth_error(StatusCode::BAD_REQUEST, "unsupported_grant_type"),
+    }
+}
+
+async fn token_info(State(state): State<MockState>, bearer: Option<TypedHeader<Authorization<Bearer>>>) -> Response {
+    let mut store = state.store.lock().unwrap();
+    if let Some(response) = store.scripted_response(MockEndpoint::TokenInfo) {
+        return response;
+    }
+
+    let grant = bearer.and_then(|TypedHeader(Authorization(bearer))| store.active_grant(bearer.token()));
+    let Some(grant) = grant else {
+        return oauth_error(StatusCode::BAD_REQUEST, "invalid_token");
+    };
+    Json(json!({
+        "audience": grant.client_id,
+        "email": grant.user.email,
+        "expires_in": ACCESS_TOKEN_LIFETIME_SECONDS,
+        "issued_to": grant.client_id,
+        "user_id": grant.user.sub,
+    })).into_response()
+}
+
+async fn user_info(State(state): State<MockState>, bearer: Option<TypedHeader<Authorization<Bearer>>>) -> Response {
+    let mut store = state.store.lock().unwrap();
+    if let Some(response) = store.scripted_response(MockEndpoint::UserInfo) {
+        return response;
+    }
+
+    let grant = bearer.and_then(|TypedHeader(Authorization(bearer))| store.active_grant(bearer.token()));
+    let Some(grant) = grant else {
+        return oauth_error(StatusCode::UNAUTHORIZED, "invalid_token");
+    };
+    Json(json!({
+        "sub": grant.user.sub,
+        "email": grant.user.email,
+        "email_verified": grant.user.email_verified,
+        "given_name": grant.user.given_name,
+        "family_name": grant.user.family_name,
+    })).into_response()
+}
+
+/// Revoking either token of a grant revokes the whole grant, as Google does.
+async fn revoke(State(state): State<MockState>, Form(form): Form<HashMap<String, String>>) -> Response {
+    let mut store = state.store.lock().unwrap();
+    if let Some(response) = store.scripted_response(MockEndpoint::Revoke) {
+        return response;
+    }
+
+    let token = form.get("token").cloned().unwrap_or_default();
+    let grant_id = store.refresh_tokens.get(&token).or_else(|| store.access_tokens.get(&token)).copied();
+    let Some(grant_id) = grant_id else {
+        return oauth_error(StatusCode::BAD_REQUEST, "invalid_token");
+    };
+    store.grants[grant_id].revoked = true;
+    store.revoked_tokens.push(token);
+
+    StatusCode::OK.into_response()
+}
+
+async fn jwks(State(state): State<MockState>) -> Response {
+    let mut store = state.store.lock().unwrap();
+    if let Some(response) = store.scripted_response(MockEndpoint::Jwks) {
+        return response;
+    }
+
+    Json(serde_json::from_str::<Value>(JWKS).unwrap()).into_response()
+}
+
+fn oauth_error(status: StatusCode, error: &str) -> Response {
+    (status, Json(json!({ "error": error }))).into_response()
+}
+
+fn sign_id_token(issuer: &str, authorisation: &PendingAuthorisation) -> String {
+    let now = Utc::now().timestamp();
+    let claims = json!({
+        "iss": issuer,
+        "aud": authorisation.client_id,
+        "sub": authorisation.user.sub,
+        "email": authorisation.user.email,
+        "email_verified": authorisation.user.email_verified,
+        "given_name": authorisation.user.given_name,
+        "family_name": authorisation.user.family_name,
+        "nonce": authorisation.nonce,
+        "iat": now,
+        "exp": now + ACCESS_TOKEN_LIFETIME_SECONDS,
+    });
+    let mut header = Header::new(Algorithm::RS256);
+    header.kid = Some(SIGNING_KEY_ID.to_string());
+    encode(&header, &claims, &EncodingKey::from_rsa_pem(SIGNING_KEY.as_bytes()).unwrap()).unwrap()
+}
@@ -1,3 +1,13 @@
+pub mod mock_oauth_provider;
+pub mod span_collector;
+
+use std::sync::Arc;
+
+use jsonwebtoken::Algorithm;
+use sqlx::MySqlPool;
+
+use crate::{config::{database::Database, oauth_provider_config::OAuthProviderConfig}, service::{google_token_service::{GoogleTokenService, TokenServiceTrait}, jwt_service::JwtService, oauth_provider::ProviderRegistry, shutdown_signal::ShutdownSignal, token_cipher::TokenCipher}, state::app_state::AppState};
+
 #[macro_export]
 macro_rules! assert_error {
     ($result_or_error:expr, $expected_type:expr) => {
@@ -15,3 +25,43 @@ macro_rules! assert_error {
         }
     }
 }
+
+pub fn placeholder_oauth_config() -> OAuthProviderConfig {
+    OAuthProviderConfig {
+        client_id: "test-client-id".to_string(),
+        client_secret: "test-client-secret".to_string(),
+        redirect_url: "http://localhost/auth/google/authorized".to_string(),
+        auth_url: "https://test.auth.url".to_string(),
+        token_url: "https://test.token.url".to_string(),
+        revocation_url: "https://test.revocation.url".to_string(),
+        token_info_url: "https://test.tokeninfo.url".to_string(),
+        userinfo_url: "https://test.userinfo.url".to_string(),
+        jwks_url: "https://test.jwks.url".to_string(),
+        issuer: "https://test.issuer.url".to_string(),
+        scopes: vec!["openid".to_string(), "email".to_string(), "profile".to_string()],
+        extra_auth_params: vec![
+            ("access_type".to_string(), "offline".to_string()),
+            ("prompt".to_string(), "consent".to_string()),
+        ],
+    }
+}
+
+pub async fn setup_app_state(db: MySqlPool) -> AppState {
+    setup_app_state_with_oauth_config(db, placeholder_oauth_config()).await
+}
+
+pub async fn setup_app_state_with_oauth_config(db: MySqlPool, oauth_config: OAuthProviderConfig) -> AppState {
+    let db_conn = Database { pool: db };
+    let mut providers = ProviderRegistry::default();
+    providers.register(Arc::new(GoogleTokenService::new(oauth_config).unwrap()));
+    let token_cipher = TokenCipher::new(&[7u8; 32]).unwrap();
+    let jwt_service = JwtService::from_pem(
+        Algorithm::RS256,
+        include_str!("../../tests/fixtures/id_token_signing_key.pem"),
+        Some("test-key".to_string()),
+        "oauth-app".to_string(),
+        "oauth-app-api".to_string(),
+        900,
+    ).unwrap();
+    AppState::new(db_conn, providers, token_cipher, jwt_service, ShutdownSignal::default()).await.unwrap()
+}
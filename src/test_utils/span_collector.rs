@@ -0,0 +1,92 @@
+use std::{collections::HashMap, fmt, sync::{Arc, Mutex}};
+
+use tracing::{field::{Field, Visit}, span::{Attributes, Id, Record}, subscriber::DefaultGuard, Subscriber};
+use tracing_subscriber::{layer::{Context, SubscriberExt}, registry::LookupSpan, Layer};
+
+/// A span as seen by [`SpanCollector`], with its fields formatted as strings.
+#[derive(Clone, Debug)]
+pub struct CollectedSpan {
+    pub name: String,
+    pub parent: Option<String>,
+    pub fields: HashMap<String, String>,
+}
+
+impl CollectedSpan {
+    pub fn field(&self, name: &str) -> Option<&str> {
+        self.fields.get(name).map(String::as_str)
+    }
+}
+
+/// Records every span created on the current thread while it is alive. Tests run on a
+/// current-thread runtime, so this captures everything a request handled in-process does.
+pub struct SpanCollector {
+    spans: Arc<Mutex<Vec<CollectedSpan>>>,
+    _guard: DefaultGuard,
+}
+
+impl SpanCollector {
+    pub fn install() -> Self {
+        let spans = Arc::new(Mutex::new(Vec::new()));
+        let subscriber = tracing_subscriber::registry().with(CollectorLayer { spans: spans.clone() });
+        Self { spans, _guard: tracing::subscriber::set_default(subscriber) }
+    }
+
+    pub fn spans(&self) -> Vec<CollectedSpan> {
+        self.spans.lock().unwrap().clone()
+    }
+
+    /// The first span with this name.
+    pub fn span(&self, name: &str) -> Option<CollectedSpan> {
+        self.spans().into_iter().find(|span| span.name == name)
+    }
+}
+
+/// Where a span's entry lives in the collector, stored in the span's extensions.
+struct SpanIndex(usize);
+
+struct CollectorLayer {
+    spans: Arc<Mutex<Vec<CollectedSpan>>>,
+}
+
+impl<S: Subscriber + for<'span> LookupSpan<'span>> Layer<S> for CollectorLayer {
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let mut visitor = FieldVisitor::default();
+        attrs.record(&mut visitor);
+
+        let mut spans = self.spans.lock().unwrap();
+        span.extensions_mut().insert(SpanIndex(spans.len()));
+        spans.push(CollectedSpan {
+            name: span.name().to_string(),
+            parent: span.parent().map(|parent| parent.name().to_string()),
+            fields: visitor.0,
+        });
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let Some(SpanIndex(index)) = span.extensions().get::<SpanIndex>().map(|index| SpanIndex(index.0)) else {
+            return;
+        };
+        let mut visitor = FieldVisitor::default();
+        values.record(&mut visitor);
+        self.spans.lock().unwrap()[index].fields.extend(visitor.0);
+    }
+}
+
+#[derive(Default)]
+struct FieldVisitor(HashMap<String, String>);
+
+impl Visit for FieldVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), value.to_string());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0.insert(field.name().to_string(), format!("{:?}", value));
+    }
+}
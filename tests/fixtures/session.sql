@@ -1,4 +1,4 @@
-INSERT INTO sessions (session_id, csrf_token, expires_at) VALUES 
-    ("test_session_id", "test_csrf_token", NOW() + INTERVAL 1 HOUR),
-    ("expired_session_id", "test_csrf_token", DATE_SUB(NOW(), INTERVAL 10 MINUTE));
-    
\ No newline at end of file
+INSERT INTO sessions (session_id, csrf_token, pkce_verifier, nonce, expires_at) VALUES 
+    ("test_session_id", "test_csrf_token", "test_pkce_verifier", "test_nonce", NOW() + INTERVAL 1 HOUR),
+    ("expired_session_id", "test_csrf_token", "test_pkce_verifier", "test_nonce", DATE_SUB(NOW(), INTERVAL 10 MINUTE)),
+    ("no_verifier_session_id", "test_csrf_token", NULL, NULL, NOW() + INTERVAL 1 HOUR);
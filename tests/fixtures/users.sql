@@ -1,3 +1,6 @@
-INSERT INTO users (email,first_name,last_name,created_at,last_updated,google_id) VALUES
-	 ('TestEmail@lift.com','Tom','Gill','2025-01-16 20:50:43','2025-01-21 20:13:48','110235950686105464135'),
-	 ('TestEmail-2@lift.com','Patrick','Tilly','2025-01-18 21:35:07','2025-01-18 21:35:07','107329637626229533241');
+INSERT INTO users (email,first_name,last_name,created_at,last_updated) VALUES
+	 ('TestEmail@lift.com','Tom','Gill','2025-01-16 20:50:43','2025-01-21 20:13:48'),
+	 ('TestEmail-2@lift.com','Patrick','Tilly','2025-01-18 21:35:07','2025-01-18 21:35:07');
+INSERT INTO user_identities (user_id,provider,subject,email) VALUES
+	 (1,'google','110235950686105464135','TestEmail@lift.com'),
+	 (2,'google','107329637626229533241','TestEmail-2@lift.com');